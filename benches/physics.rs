@@ -0,0 +1,119 @@
+//! Benchmarks for the physics query pipeline, in a level with many enemies
+//!
+//! Run with `cargo bench --bench physics`.
+
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rapier2d::prelude::{Group, InteractionGroups, SharedShape};
+use theseeker_engine::physics::{
+    update_query_pipeline, Collider, PhysicsWorld, StaticCollider, ENEMY,
+    GROUND, PLAYER,
+};
+
+/// Number of (already merged) wall rectangles in the level
+const WALLS: usize = 2000;
+/// Number of enemies walking around
+const ENEMIES: usize = 500;
+
+#[derive(Component)]
+struct Enemy;
+
+fn setup_level(world: &mut World) {
+    for i in 0..WALLS {
+        let x = (i % 100) as f32 * 64.0;
+        let y = (i / 100) as f32 * 64.0;
+        world.spawn((
+            Collider::cuboid(
+                48.0,
+                16.0,
+                InteractionGroups {
+                    memberships: GROUND,
+                    filter: Group::all(),
+                },
+            ),
+            GlobalTransform::from_xyz(x, y, 0.0),
+            StaticCollider,
+        ));
+    }
+    for i in 0..ENEMIES {
+        let x = (i % 100) as f32 * 64.0 + 8.0;
+        let y = (i / 100) as f32 * 64.0 + 14.0;
+        world.spawn((
+            Collider::cuboid(
+                16.0,
+                10.0,
+                InteractionGroups {
+                    memberships: ENEMY,
+                    filter: Group::all(),
+                },
+            ),
+            GlobalTransform::from_xyz(x, y, 0.0),
+            Enemy,
+        ));
+    }
+}
+
+/// Moves every enemy by a pixel, like walking would
+fn walk_enemies(mut query: Query<&mut GlobalTransform, With<Enemy>>) {
+    for mut transform in query.iter_mut() {
+        *transform =
+            transform.mul_transform(Transform::from_xyz(1.0, 0.0, 0.0));
+    }
+}
+
+fn bench_update_query_pipeline(c: &mut Criterion) {
+    let mut world = World::new();
+    world.init_resource::<PhysicsWorld>();
+    setup_level(&mut world);
+    let mut schedule = Schedule::default();
+    schedule.add_systems((walk_enemies, update_query_pipeline).chain());
+    // the first run adds all the colliders
+    schedule.run(&mut world);
+
+    c.bench_function("update_query_pipeline", |b| {
+        b.iter(|| schedule.run(&mut world))
+    });
+}
+
+fn bench_enemy_shape_casts(c: &mut Criterion) {
+    let mut world = World::new();
+    world.init_resource::<PhysicsWorld>();
+    setup_level(&mut world);
+    let mut schedule = Schedule::default();
+    schedule.add_systems(update_query_pipeline);
+    schedule.run(&mut world);
+
+    let mut q_enemies = world.query_filtered::<&GlobalTransform, With<Enemy>>();
+    let origins: Vec<Vec2> = q_enemies
+        .iter(&world)
+        .map(|transform| transform.translation().xy())
+        .collect();
+    let shape = SharedShape::cuboid(8.0, 5.0);
+    let physics = world.resource::<PhysicsWorld>();
+
+    // roughly what `move_collide` does for every enemy, every tick
+    c.bench_function("enemy_shape_casts", |b| {
+        b.iter(|| {
+            for origin in &origins {
+                black_box(physics.shape_cast(
+                    *origin,
+                    Direction2d::X,
+                    &*shape,
+                    1.0,
+                    InteractionGroups {
+                        memberships: ENEMY,
+                        filter: PLAYER | GROUND,
+                    },
+                    None,
+                ));
+            }
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_update_query_pipeline,
+    bench_enemy_shape_casts
+);
+criterion_main!(benches);
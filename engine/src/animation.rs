@@ -32,6 +32,8 @@ pub struct SpriteAnimationTracker {
     frame_max: FrameId,
     ticks_per_frame: u32,
     ticks_remain: u32,
+    /// Tick rate changes since the script started, for any new frame timings
+    tick_scale: f64,
     bookmarks: HashMap<String, FrameId>,
     q_extra: Vec<QueuedAction>,
 }
@@ -56,6 +58,11 @@ impl SpriteAnimationTracker {
         }
     }
 
+    /// Frame timings from the asset, adjusted to the current tick rate
+    fn scaled_ticks(&self, ticks: u32) -> u32 {
+        ((ticks as f64 * self.tick_scale).round() as u32).max(1)
+    }
+
     fn resolve_frame(
         &self,
         bm: Option<&String>,
@@ -310,12 +317,13 @@ impl ScriptAction for SpriteAnimationScriptAction {
                 ticks_per_frame,
                 reset_progress,
             } => {
-                tracker.ticks_per_frame = *ticks_per_frame;
+                let ticks_per_frame = tracker.scaled_ticks(*ticks_per_frame);
+                tracker.ticks_per_frame = ticks_per_frame;
                 if let Some(true) = reset_progress {
-                    tracker.ticks_remain = *ticks_per_frame;
+                    tracker.ticks_remain = ticks_per_frame;
                 } else {
                     tracker.ticks_remain =
-                        tracker.ticks_remain.min(*ticks_per_frame);
+                        tracker.ticks_remain.min(ticks_per_frame);
                 }
                 ScriptUpdateResult::NormalRun
             },
@@ -427,6 +435,7 @@ impl ScriptTracker for SpriteAnimationTracker {
         (q,): &mut <Self::InitParam as SystemParam>::Item<'_, '_>,
     ) {
         self.carryover = carryover;
+        self.tick_scale = 1.0;
         self.ticks_per_frame = settings.ticks_per_frame;
         self.ticks_remain = 0;
         self.next_frame = Some(settings.frame_start);
@@ -457,6 +466,13 @@ impl ScriptTracker for SpriteAnimationTracker {
         self.ticks_remain = other.ticks_remain.min(self.ticks_per_frame);
     }
 
+    fn rescale_ticks(&mut self, _tick: u64, ratio: f64) {
+        self.tick_scale *= ratio;
+        self.ticks_per_frame =
+            ((self.ticks_per_frame as f64 * ratio).round() as u32).max(1);
+        self.ticks_remain = (self.ticks_remain as f64 * ratio).round() as u32;
+    }
+
     fn finalize(&mut self) {
         // nothing really needs to be done here
     }
@@ -612,3 +628,27 @@ impl ScriptAsset for SpriteAnimation {
         builder
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rescale_ticks_persists() {
+        let mut tracker = SpriteAnimationTracker {
+            tick_scale: 1.0,
+            ticks_per_frame: 4,
+            ticks_remain: 3,
+            ..Default::default()
+        };
+        tracker.rescale_ticks(0, 2.0);
+        assert_eq!(tracker.ticks_per_frame, 8);
+        assert_eq!(tracker.ticks_remain, 6);
+        // frame timings set by the script later are in the old tick rate
+        assert_eq!(tracker.scaled_ticks(4), 8);
+        tracker.rescale_ticks(0, 0.25);
+        assert_eq!(tracker.ticks_per_frame, 2);
+        assert_eq!(tracker.scaled_ticks(4), 2);
+        assert_eq!(tracker.scaled_ticks(1), 1);
+    }
+}
@@ -1,4 +1,12 @@
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+
 use bevy::reflect::TypePath;
+use serde::de::value::{Error as DeError, MapDeserializer};
+use serde::de::{IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserializer};
 
 use crate::prelude::*;
 
@@ -11,6 +19,129 @@ use crate::prelude::*;
 #[serde(transparent)]
 pub struct DynamicConfig(pub HashMap<String, DynamicConfigValue>);
 
+/// Stand-ins for values that failed, tried in order,
+/// so that the rest of the config can still be checked
+const PLACEHOLDERS: [DynamicConfigValue; 3] = [
+    DynamicConfigValue::Int(0),
+    DynamicConfigValue::Float(0.0),
+    DynamicConfigValue::String(String::new()),
+];
+
+impl DynamicConfig {
+    /// Deserialize the values into a config struct, and validate it
+    ///
+    /// Every error is reported, not just the first: values of the wrong
+    /// type, unknown and missing keys, and values that are out of range.
+    pub fn parse<T>(&self) -> Result<T, Vec<ConfigError>>
+    where
+        T: DeserializeOwned + ValidateConfig,
+    {
+        let mut values = self.0.clone();
+        let mut errors = Vec::new();
+        // keys that have a placeholder, and which one
+        let mut replaced: HashMap<String, usize> = default();
+        let config = loop {
+            let failed = Cell::new(None);
+            let e = match deserialize_config::<T>(&values, &failed) {
+                Ok(config) => break config,
+                Err(e) => e,
+            };
+            let failed = match failed.get() {
+                Some(Failed::Key(key)) => Failed::Key(key.to_owned()),
+                Some(Failed::Value(key)) => Failed::Value(key.to_owned()),
+                None => {
+                    match missing_field(&e) {
+                        Some(key) => Failed::Missing(key),
+                        None => {
+                            errors.push(ConfigError {
+                                key: None,
+                                message: e.to_string(),
+                            });
+                            return Err(errors);
+                        },
+                    }
+                },
+            };
+            let (key, placeholder) = match failed {
+                Failed::Key(key) => {
+                    errors.push(ConfigError {
+                        key: Some(key.clone()),
+                        message: e.to_string(),
+                    });
+                    values.remove(&key);
+                    continue;
+                },
+                Failed::Value(key) => {
+                    let next = replaced.get(&key).map_or(0, |i| i + 1);
+                    (key, next)
+                },
+                Failed::Missing(key) => (key, 0),
+            };
+            if !replaced.contains_key(&key) {
+                errors.push(ConfigError {
+                    key: Some(key.clone()),
+                    message: e.to_string(),
+                });
+            }
+            // nothing fits, so the rest can't be checked
+            let Some(value) = PLACEHOLDERS.get(placeholder) else {
+                return Err(errors);
+            };
+            values.insert(key.clone(), value.clone());
+            replaced.insert(key, placeholder);
+        };
+        // the map has no order, but the errors should
+        errors.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut validator = ConfigValidator::default();
+        config.validate(&mut validator);
+        // placeholders are not what the config says, so don't check them
+        errors.extend(
+            validator.errors.into_iter().filter(|e| {
+                !e.key.as_ref().is_some_and(|key| replaced.contains_key(key))
+            }),
+        );
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn deserialize_config<'a, T: DeserializeOwned>(
+    values: &'a HashMap<String, DynamicConfigValue>,
+    failed: &'a Cell<Option<Failed<&'a str>>>,
+) -> Result<T, DeError> {
+    let fields = values.iter().map(|(key, value)| {
+        (
+            KeyDeserializer { key, failed },
+            FieldDeserializer { key, value, failed },
+        )
+    });
+    T::deserialize(MapDeserializer::<_, DeError>::new(
+        fields,
+    ))
+}
+
+/// The key from a "missing field" error
+fn missing_field(e: &DeError) -> Option<String> {
+    let message = e.to_string();
+    let rest = message.strip_prefix("missing field `")?;
+    let (key, _) = rest.split_once('`')?;
+    Some(key.to_owned())
+}
+
+/// Where deserializing a [`DynamicConfig`] failed
+#[derive(Debug, Clone, Copy)]
+enum Failed<K> {
+    /// The key is not one of the fields
+    Key(K),
+    /// The value is the wrong type
+    Value(K),
+    /// A field that has no value
+    Missing(K),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
@@ -57,34 +188,346 @@ impl DynamicConfigValue {
     }
 }
 
-/// A utility type for loading config values. Use like:
+/// Deserializes one key of a [`DynamicConfig`], remembering it
+/// if it turns out not to be a field
+struct KeyDeserializer<'a> {
+    key: &'a str,
+    failed: &'a Cell<Option<Failed<&'a str>>>,
+}
+
+impl<'de, 'a> Deserializer<'de> for KeyDeserializer<'a> {
+    type Error = DeError;
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        let result = visitor.visit_str(self.key);
+        if result.is_err() {
+            self.failed.set(Some(Failed::Key(self.key)));
+        }
+        result
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, DeError> for KeyDeserializer<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Deserializes one value of a [`DynamicConfig`], remembering its key
+/// if it turns out to be the wrong type
+struct FieldDeserializer<'a> {
+    key: &'a str,
+    value: &'a DynamicConfigValue,
+    failed: &'a Cell<Option<Failed<&'a str>>>,
+}
+
+impl<'de, 'a> Deserializer<'de> for FieldDeserializer<'a> {
+    type Error = DeError;
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        let result = match self.value {
+            DynamicConfigValue::Int(value) => visitor.visit_i64(*value),
+            DynamicConfigValue::Float(value) => visitor.visit_f64(*value),
+            DynamicConfigValue::String(value) => visitor.visit_str(value),
+        };
+        if result.is_err() {
+            self.failed.set(Some(Failed::Value(self.key)));
+        }
+        result
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        // a value that is there is always `Some`
+        visitor.visit_some(self)
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, DeError> for FieldDeserializer<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Something wrong with a config value
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    /// The key of the value, if it is known
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "'{}': {}", key, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Checks on config values, beyond what deserialization can do
 ///
+/// ```ignore
+/// impl ValidateConfig for PlayerConfig {
+///     fn validate(&self, v: &mut ConfigValidator) {
+///         v.range("max_move_vel", self.max_move_vel, 0.0..);
+///         v.range("sliding_friction", self.sliding_friction, 0.0..=1.0);
+///     }
+/// }
 /// ```
-/// let mut errors = Vec::new();
-/// update_field(&mut errors, &dynamic_cfg.0, "jump_vel_init", |val| struct_config.jump_vel_init = val);
-/// ```
-pub fn update_field<F>(
-    errors: &mut Vec<String>,
-    config: &HashMap<String, DynamicConfigValue>,
-    field: &str,
-    setter: F,
+pub trait ValidateConfig {
+    fn validate(&self, v: &mut ConfigValidator);
+}
+
+/// Collects the errors found by [`ValidateConfig::validate`]
+#[derive(Debug, Default)]
+pub struct ConfigValidator {
+    errors: Vec<ConfigError>,
+}
+
+impl ConfigValidator {
+    /// The value must be within the range
+    pub fn range<V, R>(&mut self, key: &str, value: V, range: R)
+    where
+        V: PartialOrd + fmt::Debug,
+        R: RangeBounds<V> + fmt::Debug,
+    {
+        if !range.contains(&value) {
+            self.error(
+                key,
+                format!(
+                    "{:?} is not in the range {:?}",
+                    value, range
+                ),
+            );
+        }
+    }
+
+    /// Report any other problem with a value
+    pub fn error(&mut self, key: &str, message: impl Into<String>) {
+        self.errors.push(ConfigError {
+            key: Some(key.to_owned()),
+            message: message.into(),
+        });
+    }
+}
+
+/// Keeps a config resource up to date with a [`DynamicConfig`] asset
+///
+/// The asset (by its preloaded asset key) is parsed into the resource as
+/// soon as it is loaded, and again whenever the file changes. If it fails
+/// to parse or validate, every error is logged with the file and key, and
+/// the resource keeps its last good value.
+pub struct TypedConfigPlugin<T> {
+    key: &'static str,
+    _pd: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedConfigPlugin<T> {
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Plugin for TypedConfigPlugin<T>
+where
+    T: Resource + Default + DeserializeOwned + ValidateConfig,
+{
+    fn build(&self, app: &mut App) {
+        app.init_resource::<T>();
+        app.insert_resource(TypedConfigSource::<T> {
+            key: self.key,
+            loaded: false,
+            _pd: PhantomData,
+        });
+        app.add_systems(Update, load_typed_config::<T>);
+    }
+}
+
+#[derive(Resource)]
+struct TypedConfigSource<T> {
+    key: &'static str,
+    loaded: bool,
+    _pd: PhantomData<fn() -> T>,
+}
+
+fn load_typed_config<T>(
+    mut evr_asset: EventReader<AssetEvent<DynamicConfig>>,
+    cfgs: Res<Assets<DynamicConfig>>,
+    preloaded: Res<PreloadedAssets>,
+    asset_server: Res<AssetServer>,
+    mut source: ResMut<TypedConfigSource<T>>,
+    mut config: ResMut<T>,
 ) where
-    F: FnOnce(f32),
+    T: Resource + DeserializeOwned + ValidateConfig,
 {
-    if let Some(value) = config.get(field) {
-        match value.as_float() {
-            Ok(val) => setter(val as f32),
-            Err(err) => {
-                errors.push(format!(
-                    "Error parsing '{}': {}",
-                    field, err
-                ))
-            },
+    // read them all, even if the config isn't there yet
+    let modified: Vec<_> = evr_asset
+        .read()
+        .filter_map(|ev| {
+            match ev {
+                AssetEvent::Modified { id } => Some(*id),
+                _ => None,
+            }
+        })
+        .collect();
+    // the key is only known once the asset is in `PreloadedAssets`,
+    // which happens after it was added, so we can't wait for that event
+    let Some(id) = preloaded.get_single_assetid::<DynamicConfig>(source.key)
+    else {
+        return;
+    };
+    if source.loaded && !modified.contains(&id) {
+        return;
+    }
+    let Some(cfg) = cfgs.get(id) else {
+        return;
+    };
+    source.loaded = true;
+    match cfg.parse::<T>() {
+        Ok(parsed) => *config = parsed,
+        Err(errors) => {
+            let file = asset_server
+                .get_path(id)
+                .map(|path| path.to_string())
+                .unwrap_or_else(|| source.key.to_owned());
+            for e in errors {
+                error!("{}: {}", file, e);
+            }
+            warn!("{}: keeping the last good config", file);
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct TestConfig {
+        speed: f32,
+        ticks: u32,
+        #[serde(default)]
+        name: Option<String>,
+    }
+
+    impl ValidateConfig for TestConfig {
+        fn validate(&self, v: &mut ConfigValidator) {
+            v.range("speed", self.speed, 0.0..=10.0);
         }
-    } else {
-        errors.push(format!(
-            "Missing '{}' field in the config",
-            field
-        ));
+    }
+
+    fn config(values: &[(&str, DynamicConfigValue)]) -> DynamicConfig {
+        DynamicConfig(
+            values
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_typed_config() {
+        let cfg = config(&[
+            ("speed", DynamicConfigValue::Int(3)),
+            ("ticks", DynamicConfigValue::Int(12)),
+            (
+                "name",
+                DynamicConfigValue::String("x".into()),
+            ),
+        ]);
+        let parsed = cfg.parse::<TestConfig>().unwrap();
+        assert_eq!(parsed.speed, 3.0);
+        assert_eq!(parsed.ticks, 12);
+        assert_eq!(parsed.name.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn typed_config_errors_have_keys() {
+        let cfg = config(&[
+            ("speed", DynamicConfigValue::Float(1.0)),
+            ("ticks", DynamicConfigValue::Int(-4)),
+        ]);
+        let errors = cfg.parse::<TestConfig>().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key.as_deref(), Some("ticks"));
+
+        let cfg = config(&[
+            ("speed", DynamicConfigValue::Float(-1.0)),
+            ("ticks", DynamicConfigValue::Int(4)),
+        ]);
+        let errors = cfg.parse::<TestConfig>().unwrap_err();
+        assert_eq!(errors[0].key.as_deref(), Some("speed"));
+    }
+
+    #[test]
+    fn typed_config_reports_every_error() {
+        let cfg = config(&[
+            (
+                "speed",
+                DynamicConfigValue::String("fast".into()),
+            ),
+            ("ticks", DynamicConfigValue::Int(-4)),
+            ("sped", DynamicConfigValue::Float(1.0)),
+        ]);
+        let errors = cfg.parse::<TestConfig>().unwrap_err();
+        let keys: Vec<_> = errors.iter().map(|e| e.key.as_deref()).collect();
+        assert_eq!(
+            keys,
+            [Some("sped"), Some("speed"), Some("ticks")]
+        );
+    }
+
+    #[test]
+    fn typed_config_reports_missing_keys() {
+        let errors = config(&[]).parse::<TestConfig>().unwrap_err();
+        let keys: Vec<_> = errors.iter().map(|e| e.key.as_deref()).collect();
+        assert_eq!(keys, [Some("speed"), Some("ticks")]);
+        assert!(errors[0].message.contains("missing"));
+    }
+
+    #[test]
+    fn typed_config_validates_around_bad_values() {
+        let cfg = config(&[
+            ("speed", DynamicConfigValue::Float(20.0)),
+            (
+                "ticks",
+                DynamicConfigValue::String("x".into()),
+            ),
+        ]);
+        let errors = cfg.parse::<TestConfig>().unwrap_err();
+        let keys: Vec<_> = errors.iter().map(|e| e.key.as_deref()).collect();
+        assert_eq!(keys, [Some("ticks"), Some("speed")]);
     }
 }
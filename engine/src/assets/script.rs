@@ -1,6 +1,9 @@
 use bevy::reflect::TypePath;
 
 use super::config::DynamicConfigValue;
+use crate::audio::bus::AudioBus;
+use crate::audio::dsp::AudioParam;
+use crate::audio::music::MusicSync;
 use crate::data::*;
 use crate::prelude::*;
 
@@ -96,6 +99,9 @@ pub enum CommonScriptRunIf {
     Tick(OneOrMany<u64>),
     #[serde(rename = "run_every_n_ticks")]
     TickQuant(Quant),
+    /// On the beats of the music (`"4"` is every bar in 4/4)
+    #[serde(rename = "run_every_n_beats")]
+    BeatQuant(Quant),
     #[serde(rename = "run_at_time")]
     Time(OneOrMany<TimeSpec>),
     #[serde(rename = "run_at_millis")]
@@ -156,6 +162,47 @@ pub enum CommonScriptAction {
         asset_key: String,
         volume: Option<f32>,
         pan: Option<f32>,
+        /// Set the volume and pan from where the entity is,
+        /// relative to the audio listener (`pan` is ignored)
+        #[serde(default)]
+        positional: bool,
+        /// Which mixer bus to play on, if not the one from the asset key
+        bus: Option<AudioBus>,
+        /// Playback rate (`1.0` is normal, `2.0` is an octave up)
+        pitch: Option<f32>,
+        /// Random change in pitch, up to this much either way
+        /// (`0.05` is up to 5% higher or lower)
+        pitch_variation: Option<f32>,
+        /// Make buses with ducking turn down while this plays
+        #[serde(default)]
+        sidechain: bool,
+    },
+    /// Switch to another music track
+    PlayMusic {
+        /// The dynamic asset key of the `MusicTrack`
+        asset_key: String,
+        #[serde(default)]
+        sync: MusicSync,
+    },
+    /// Stop the music
+    StopMusic {
+        #[serde(default)]
+        sync: MusicSync,
+    },
+    /// Play a sound over the music, in time with it
+    PlayStinger {
+        asset_key: String,
+        volume: Option<f32>,
+        #[serde(default)]
+        sync: MusicSync,
+    },
+    /// Change an effect parameter of a mixer bus
+    SetAudioParam {
+        bus: AudioBus,
+        param: AudioParam,
+        value: f32,
+        /// Fade to the new value over this many seconds
+        fade: Option<f32>,
     },
 }
 
@@ -2,22 +2,46 @@ use bevy::audio::AddAudioSource;
 
 use crate::prelude::*;
 
+pub mod bus;
+pub mod dsp;
 mod mixer;
+pub mod music;
+pub mod render;
+mod resample;
+pub mod spatial;
+
+pub use mixer::MixerVoice;
 
 pub struct AudioPlugin;
 
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins((
+            bus::AudioBusPlugin,
+            music::MusicPlugin,
+            spatial::SpatialAudioPlugin,
+        ));
         app.add_audio_source::<PrecisionMixerInstance>();
         app.add_systems(Startup, setup_precisionmixer);
+        app.add_systems(
+            Update,
+            sync_precisionmixer_tick_rate.in_set(GameTickSet::Pre),
+        );
     }
 }
 
 fn setup_precisionmixer(
     mut commands: Commands,
     mut ass: ResMut<Assets<PrecisionMixerInstance>>,
+    offline: Option<Res<render::OfflineAudio>>,
+    gt: Res<GameTime>,
 ) {
-    let controller = mixer::PrecisionMixerController::new(2, 48_000, 96.0);
+    // rendering offline: the offline plugin sets up its own mixer
+    if offline.is_some() {
+        return;
+    }
+    let controller =
+        mixer::PrecisionMixerController::new(2, 48_000, gt.hz as f32);
     let handle = ass.add(PrecisionMixerInstance {
         controller: controller.clone(),
     });
@@ -32,6 +56,32 @@ fn setup_precisionmixer(
     ));
 }
 
+/// Keep the mixer's tick-to-sample mapping in sync with the rate of `GameTime`
+///
+/// Without this, scripted audio would drift whenever ticks are stretched
+/// by slow motion or hit-stop, the tick rate is changed, or ticks are
+/// dropped to catch up after a hitch.
+fn sync_precisionmixer_tick_rate(
+    gt: Res<GameTime>,
+    q_mixer: Query<&PrecisionMixerControl>,
+) {
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let rate = gt.hz * gt.effective_time_scale();
+    // ticks dropped by the catch-up limit move the game clock back
+    let dropped = gt.last_dropped_ticks();
+    if ctl.controller.tick_rate() == rate && dropped == 0 {
+        return;
+    }
+    ctl.controller.retime(
+        gt.total_ticks() as f64 + gt.overstep(),
+        dropped as f64,
+        gt.last_update(),
+        rate,
+    );
+}
+
 #[derive(Component)]
 pub struct PrecisionMixerControl {
     pub controller: Arc<mixer::PrecisionMixerController>,
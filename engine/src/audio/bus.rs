@@ -0,0 +1,166 @@
+//! Mixer buses
+//!
+//! Every sound is routed through one bus, each with its own volume and mute,
+//! and then through the master bus. Sounds pick their bus by the prefix of
+//! their asset key, unless the script says otherwise.
+
+use std::path::Path;
+
+use strum::IntoEnumIterator;
+use strum_macros::{EnumIter, EnumString, IntoStaticStr};
+
+use super::PrecisionMixerControl;
+use crate::prelude::*;
+
+pub struct AudioBusPlugin;
+
+impl Plugin for AudioBusPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AudioSettings>();
+        app.add_systems(
+            Update,
+            apply_audio_settings.run_if(resource_changed::<AudioSettings>),
+        );
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+#[derive(EnumIter, EnumString, IntoStaticStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AudioBus {
+    /// Everything goes through this one, so it's the overall volume
+    Master,
+    Music,
+    #[default]
+    Sfx,
+    Ui,
+    Ambience,
+}
+
+impl AudioBus {
+    pub const COUNT: usize = 5;
+
+    /// The bus for a sound that doesn't specify one
+    ///
+    /// `audio.music.*`, `audio.ui.*` and `audio.ambience.*` go to their
+    /// buses, anything else is a sound effect.
+    pub fn from_asset_key(key: &str) -> AudioBus {
+        if key.starts_with("audio.music.") {
+            AudioBus::Music
+        } else if key.starts_with("audio.ui.") {
+            AudioBus::Ui
+        } else if key.starts_with("audio.ambience.") {
+            AudioBus::Ambience
+        } else {
+            AudioBus::Sfx
+        }
+    }
+
+    pub(super) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The user's audio settings: volume per bus, and latency
+#[derive(Resource, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// How late the audio output is heard, in milliseconds (from calibration)
+    ///
+    /// Sounds timed to game ticks are played this much earlier.
+    pub latency_ms: f32,
+    pub master: BusSettings,
+    pub music: BusSettings,
+    pub sfx: BusSettings,
+    pub ui: BusSettings,
+    pub ambience: BusSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct BusSettings {
+    /// `0.0` to `1.0`
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for BusSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl BusSettings {
+    /// The gain to apply, taking mute into account
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.volume.clamp(0.0, 1.0)
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn bus(&self, bus: AudioBus) -> &BusSettings {
+        match bus {
+            AudioBus::Master => &self.master,
+            AudioBus::Music => &self.music,
+            AudioBus::Sfx => &self.sfx,
+            AudioBus::Ui => &self.ui,
+            AudioBus::Ambience => &self.ambience,
+        }
+    }
+
+    pub fn bus_mut(&mut self, bus: AudioBus) -> &mut BusSettings {
+        match bus {
+            AudioBus::Master => &mut self.master,
+            AudioBus::Music => &mut self.music,
+            AudioBus::Sfx => &mut self.sfx,
+            AudioBus::Ui => &mut self.ui,
+            AudioBus::Ambience => &mut self.ambience,
+        }
+    }
+
+    /// Read settings from a file
+    pub fn load(path: impl AsRef<Path>) -> AnyResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| {
+            format!("Cannot read audio settings {:?}", path)
+        })?;
+        toml::from_str(&text)
+            .with_context(|| format!("Invalid audio settings {:?}", path))
+    }
+
+    /// Write settings to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> AnyResult<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self)?;
+        std::fs::write(path, text)
+            .with_context(|| format!("Cannot write audio settings {:?}", path))
+    }
+}
+
+fn apply_audio_settings(
+    settings: Res<AudioSettings>,
+    q_mixer: Query<&PrecisionMixerControl>,
+) {
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    for bus in AudioBus::iter() {
+        ctl.controller.set_bus_gain(bus, settings.bus(bus).gain());
+    }
+    ctl.controller
+        .set_latency_offset(settings.latency_ms / 1000.0);
+}
@@ -0,0 +1,856 @@
+//! Effects on the mixer buses
+//!
+//! Every bus has the same chain of effects, in this order: high-pass filter,
+//! low-pass filter, reverb and ducking. They all start out doing nothing,
+//! and are turned on by changing their [`AudioParam`]s, from scripts
+//! (`SetAudioParam`) or from code, through the mixer controller.
+//!
+//! Ducking turns a bus down while "sidechain" sounds are playing (like the
+//! music under a boss roar). Any sound can be made a sidechain sound with
+//! [`MixerVoice::set_sidechain`](super::MixerVoice::set_sidechain).
+//!
+//! All the buffers are allocated up front, so that nothing allocates
+//! on the audio thread.
+
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+use std::sync::atomic::{fence, AtomicI64, AtomicU32, Ordering as MemOrdering};
+
+use strum::IntoEnumIterator;
+use strum_macros::{EnumIter, EnumString, IntoStaticStr};
+
+use super::mixer::MySample;
+use crate::prelude::*;
+
+/// Lowest and highest filter cutoffs (and also what counts as "off")
+const MIN_CUTOFF: f32 = 20.0;
+const MAX_CUTOFF: f32 = 20_000.0;
+/// Encoded cutoffs this close (in octaves) to a bound decode to the bound
+const CUTOFF_SNAP: f32 = 1e-4;
+/// A sidechain signal this loud (peak) ducks by the full amount
+const DUCK_FULL_LEVEL: f32 = 0.25;
+/// How quickly ducking kicks in, in seconds
+const DUCK_ATTACK: f32 = 0.01;
+/// Scales the input of the reverb, so the tail is about as loud as the input
+const REVERB_GAIN: f32 = 0.09;
+/// Delay lengths of the reverb's comb and allpass filters,
+/// in samples at 44.1 kHz (from Freeverb)
+const COMB_TUNING: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_TUNING: [usize; 2] = [556, 441];
+/// Extra delay on the right channel, so the reverb sounds wide
+const STEREO_SPREAD: usize = 23;
+
+/// Something about the effects of a bus that can be changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+#[derive(EnumIter, EnumString, IntoStaticStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AudioParam {
+    /// Cutoff of the low-pass filter, in Hz (off at 20000)
+    Lowpass,
+    /// Cutoff of the high-pass filter, in Hz (off at 20)
+    Highpass,
+    /// How much of the output is reverb (`0.0` is off, `1.0` is all reverb)
+    ReverbMix,
+    /// Size of the room (`0.0` to `1.0`), longer tail when bigger
+    ReverbSize,
+    /// How quickly the high end of the reverb dies out (`0.0` to `1.0`)
+    ReverbDamping,
+    /// How much to turn the bus down under sidechain sounds
+    /// (`0.0` is off, `1.0` is all the way down)
+    Duck,
+    /// Seconds for ducking to recover after the sidechain sound is over
+    DuckRelease,
+}
+
+impl AudioParam {
+    pub const COUNT: usize = 7;
+
+    /// The value of the parameter when nothing has changed it
+    pub fn default_value(self) -> f32 {
+        match self {
+            AudioParam::Lowpass => MAX_CUTOFF,
+            AudioParam::Highpass => MIN_CUTOFF,
+            AudioParam::ReverbMix => 0.0,
+            AudioParam::ReverbSize => 0.5,
+            AudioParam::ReverbDamping => 0.5,
+            AudioParam::Duck => 0.0,
+            AudioParam::DuckRelease => 0.5,
+        }
+    }
+
+    /// The default values of all parameters, by index
+    pub fn defaults() -> [f32; AudioParam::COUNT] {
+        let mut values = [0.0; AudioParam::COUNT];
+        for param in AudioParam::iter() {
+            values[param.index()] = param.default_value();
+        }
+        values
+    }
+
+    /// The default values of all parameters, by index, as automated
+    pub(super) fn encoded_defaults() -> [f32; AudioParam::COUNT] {
+        let mut values = [0.0; AudioParam::COUNT];
+        for param in AudioParam::iter() {
+            values[param.index()] = param.encode(param.default_value());
+        }
+        values
+    }
+
+    pub(super) fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Filter cutoffs are automated in log space, so fades sound even
+    pub(super) fn encode(self, value: f32) -> f32 {
+        match self {
+            AudioParam::Lowpass | AudioParam::Highpass => {
+                value.clamp(MIN_CUTOFF, MAX_CUTOFF).log2()
+            },
+            _ => value,
+        }
+    }
+
+    /// Undo [`Self::encode`]
+    ///
+    /// `exp2` of a `log2` is not always exact, so cutoffs at (or very near)
+    /// the bounds come back as the bounds, which turn the filters off.
+    pub(super) fn decode(self, value: f32) -> f32 {
+        match self {
+            AudioParam::Lowpass | AudioParam::Highpass => {
+                if value <= MIN_CUTOFF.log2() + CUTOFF_SNAP {
+                    MIN_CUTOFF
+                } else if value >= MAX_CUTOFF.log2() - CUTOFF_SNAP {
+                    MAX_CUTOFF
+                } else {
+                    value.exp2()
+                }
+            },
+            _ => value,
+        }
+    }
+}
+
+/// A value that can be changed from any thread, and faded over time
+///
+/// Fades are linear, between sample numbers. A fade is several values, so
+/// they are guarded by a sequence number (a seqlock): readers retry if a
+/// change happened while they were reading, instead of mixing the old fade
+/// with the new one. Readers never block the writer.
+pub(super) struct Automation {
+    /// Odd while a change is being written
+    seq: AtomicU32,
+    /// `f32` bits, the value at the end of the fade, if there is one
+    target: AtomicU32,
+    /// `f32` bits, the value at the start of the fade
+    from: AtomicU32,
+    /// Sample number the fade starts at
+    start: AtomicI64,
+    /// Length of the fade in samples (`0` for no fade)
+    len: AtomicI64,
+}
+
+/// What an [`Automation`] is doing, read all at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Fade {
+    pub target: f32,
+    pub from: f32,
+    pub start: i64,
+    pub len: i64,
+}
+
+impl Fade {
+    /// The value at a given sample number
+    pub fn value_at(&self, sample: i64) -> f32 {
+        if self.len <= 0 || sample >= self.start + self.len {
+            self.target
+        } else if sample <= self.start {
+            self.from
+        } else {
+            let t = (sample - self.start) as f32 / self.len as f32;
+            self.from + (self.target - self.from) * t
+        }
+    }
+}
+
+impl Automation {
+    pub(super) fn new(value: f32) -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            target: AtomicU32::new(value.to_bits()),
+            from: AtomicU32::new(value.to_bits()),
+            start: AtomicI64::new(0),
+            len: AtomicI64::new(0),
+        }
+    }
+
+    /// The value, or the one it is fading towards
+    pub(super) fn target(&self) -> f32 {
+        self.fade().target
+    }
+
+    /// Change the value right away, cancelling any fade
+    pub(super) fn set(&self, value: f32) {
+        self.write(|current| {
+            Fade {
+                target: value,
+                len: 0,
+                ..current
+            }
+        });
+    }
+
+    /// Fade to `value` over `len` samples, starting at sample `start`
+    ///
+    /// The fade starts from whatever the value would have been at `start`,
+    /// so a new fade can take over from one that hasn't finished.
+    pub(super) fn ramp(&self, value: f32, start: i64, len: i64) {
+        self.write(|current| {
+            Fade {
+                target: value,
+                from: current.value_at(start),
+                start,
+                len: len.max(0),
+            }
+        });
+    }
+
+    /// The value at a given sample number, following the fade
+    pub(super) fn value_at(&self, sample: i64) -> f32 {
+        self.fade().value_at(sample)
+    }
+
+    /// A consistent snapshot of the fade
+    pub(super) fn fade(&self) -> Fade {
+        loop {
+            let seq = self.seq.load(MemOrdering::Acquire);
+            if seq & 1 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let fade = self.load();
+            fence(MemOrdering::Acquire);
+            if self.seq.load(MemOrdering::Relaxed) == seq {
+                return fade;
+            }
+        }
+    }
+
+    fn load(&self) -> Fade {
+        Fade {
+            target: f32::from_bits(self.target.load(MemOrdering::Relaxed)),
+            from: f32::from_bits(self.from.load(MemOrdering::Relaxed)),
+            start: self.start.load(MemOrdering::Relaxed),
+            len: self.len.load(MemOrdering::Relaxed),
+        }
+    }
+
+    /// Replace the fade, based on the current one
+    fn write(&self, f: impl FnOnce(Fade) -> Fade) {
+        // only one writer at a time: take the sequence number from even to odd
+        let mut seq = self.seq.load(MemOrdering::Relaxed);
+        loop {
+            if seq & 1 == 1 {
+                std::hint::spin_loop();
+                seq = self.seq.load(MemOrdering::Relaxed);
+                continue;
+            }
+            match self.seq.compare_exchange_weak(
+                seq,
+                seq.wrapping_add(1),
+                MemOrdering::Acquire,
+                MemOrdering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        fence(MemOrdering::Release);
+        let fade = f(self.load());
+        self.target.store(
+            fade.target.to_bits(),
+            MemOrdering::Relaxed,
+        );
+        self.from.store(
+            fade.from.to_bits(),
+            MemOrdering::Relaxed,
+        );
+        self.start.store(fade.start, MemOrdering::Relaxed);
+        self.len.store(fade.len, MemOrdering::Relaxed);
+        self.seq.store(
+            seq.wrapping_add(2),
+            MemOrdering::Release,
+        );
+    }
+}
+
+/// The effect chain of one bus
+pub(super) struct BusEffects {
+    sample_rate: f32,
+    channels: usize,
+    /// The parameters the effects are currently set up for
+    params: [f32; AudioParam::COUNT],
+    highpass: Option<Biquad>,
+    lowpass: Option<Biquad>,
+    reverb: Reverb,
+    ducker: Ducker,
+}
+
+impl BusEffects {
+    pub(super) fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            channels: channels.max(1) as usize,
+            params: AudioParam::defaults(),
+            highpass: None,
+            lowpass: None,
+            reverb: Reverb::new(sample_rate),
+            ducker: Ducker::default(),
+        }
+    }
+
+    /// Pick up new parameter values
+    ///
+    /// Call at the start of a frame, with values from [`AudioParam::decode`].
+    pub(super) fn update(&mut self, params: [f32; AudioParam::COUNT]) {
+        if params == self.params {
+            return;
+        }
+        let get = |p: AudioParam| params[p.index()];
+        let changed =
+            |p: AudioParam| params[p.index()] != self.params[p.index()];
+
+        if changed(AudioParam::Highpass) {
+            let cutoff = get(AudioParam::Highpass);
+            self.highpass = (cutoff > MIN_CUTOFF).then(|| {
+                let mut filter = self.highpass.take().unwrap_or_default();
+                filter.set_highpass(cutoff, self.sample_rate);
+                filter
+            });
+        }
+        if changed(AudioParam::Lowpass) {
+            let cutoff = get(AudioParam::Lowpass);
+            self.lowpass = (cutoff < MAX_CUTOFF).then(|| {
+                let mut filter = self.lowpass.take().unwrap_or_default();
+                filter.set_lowpass(cutoff, self.sample_rate);
+                filter
+            });
+        }
+        self.reverb.set(
+            get(AudioParam::ReverbMix),
+            get(AudioParam::ReverbSize),
+            get(AudioParam::ReverbDamping),
+        );
+        let rate = self.sample_rate * self.channels as f32;
+        self.ducker.set(
+            get(AudioParam::Duck),
+            get(AudioParam::DuckRelease),
+            rate,
+        );
+        self.params = params;
+    }
+
+    /// Run one sample through the chain
+    ///
+    /// `key` is the level of the sidechain signal.
+    pub(super) fn process(
+        &mut self,
+        channel: usize,
+        value: MySample,
+        key: MySample,
+    ) -> MySample {
+        let mut value = value;
+        if let Some(filter) = &mut self.highpass {
+            value = filter.process(channel, value);
+        }
+        if let Some(filter) = &mut self.lowpass {
+            value = filter.process(channel, value);
+        }
+        value = self.reverb.process(channel, value);
+        value * self.ducker.gain(key)
+    }
+}
+
+/// Second order filter, with Butterworth response
+///
+/// Coefficients from the Audio EQ Cookbook.
+#[derive(Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    /// `x[n-1]`, `x[n-2]`, `y[n-1]`, `y[n-2]`, per channel
+    state: [[f32; 4]; 2],
+}
+
+impl Biquad {
+    fn set_lowpass(&mut self, cutoff: f32, sample_rate: f32) {
+        let (cos, alpha) = Self::prepare(cutoff, sample_rate);
+        let a0 = 1.0 + alpha;
+        self.b0 = (1.0 - cos) / 2.0 / a0;
+        self.b1 = (1.0 - cos) / a0;
+        self.b2 = self.b0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    fn set_highpass(&mut self, cutoff: f32, sample_rate: f32) {
+        let (cos, alpha) = Self::prepare(cutoff, sample_rate);
+        let a0 = 1.0 + alpha;
+        self.b0 = (1.0 + cos) / 2.0 / a0;
+        self.b1 = -(1.0 + cos) / a0;
+        self.b2 = self.b0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    fn prepare(cutoff: f32, sample_rate: f32) -> (f32, f32) {
+        let cutoff = cutoff.clamp(MIN_CUTOFF, sample_rate * 0.45);
+        let w0 = TAU * cutoff / sample_rate;
+        (
+            w0.cos(),
+            w0.sin() / (2.0 * FRAC_1_SQRT_2),
+        )
+    }
+
+    fn process(&mut self, channel: usize, x: f32) -> f32 {
+        let [x1, x2, y1, y2] = self.state[channel];
+        let y = self.b0 * x + self.b1 * x1 + self.b2 * x2
+            - self.a1 * y1
+            - self.a2 * y2;
+        self.state[channel] = [x, x1, y, y1];
+        y
+    }
+}
+
+/// A small Freeverb-style reverb: parallel combs into series allpasses
+struct Reverb {
+    combs: [[Comb; 4]; 2],
+    allpasses: [[Allpass; 2]; 2],
+    mix: f32,
+    feedback: f32,
+    damping: f32,
+}
+
+impl Reverb {
+    fn new(sample_rate: u32) -> Self {
+        let scale = |len: usize, channel: usize| {
+            ((len + channel * STEREO_SPREAD) as f32 * sample_rate as f32
+                / 44_100.0) as usize
+        };
+        Self {
+            combs: std::array::from_fn(|ch| {
+                COMB_TUNING.map(|len| Comb::new(scale(len, ch)))
+            }),
+            allpasses: std::array::from_fn(|ch| {
+                ALLPASS_TUNING.map(|len| Allpass::new(scale(len, ch)))
+            }),
+            mix: 0.0,
+            feedback: 0.0,
+            damping: 0.0,
+        }
+    }
+
+    fn set(&mut self, mix: f32, size: f32, damping: f32) {
+        let mix = mix.clamp(0.0, 1.0);
+        if self.mix <= 0.0 && mix > 0.0 {
+            // don't bring back the tail from the last time it was on
+            for comb in self.combs.iter_mut().flatten() {
+                comb.clear();
+            }
+            for allpass in self.allpasses.iter_mut().flatten() {
+                allpass.clear();
+            }
+        }
+        self.mix = mix;
+        self.feedback = 0.7 + 0.28 * size.clamp(0.0, 1.0);
+        self.damping = 0.4 * damping.clamp(0.0, 1.0);
+    }
+
+    fn process(&mut self, channel: usize, value: f32) -> f32 {
+        if self.mix <= 0.0 {
+            return value;
+        }
+        let input = value * REVERB_GAIN;
+        let mut wet = 0.0;
+        for comb in self.combs[channel].iter_mut() {
+            wet += comb.process(input, self.feedback, self.damping);
+        }
+        for allpass in self.allpasses[channel].iter_mut() {
+            wet = allpass.process(wet);
+        }
+        value * (1.0 - self.mix) + wet * self.mix
+    }
+}
+
+/// Feedback delay, with a low-pass in the loop
+struct Comb {
+    buffer: Vec<f32>,
+    pos: usize,
+    filtered: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            pos: 0,
+            filtered: 0.0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+        self.filtered = 0.0;
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.pos];
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer[self.pos] = input + self.filtered * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        output
+    }
+}
+
+/// Smears the echoes from the combs, without colouring the sound
+struct Allpass {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            pos: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.pos];
+        self.buffer[self.pos] = input + delayed * 0.5;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+/// Follows the level of the sidechain signal, and turns the bus down
+#[derive(Default)]
+struct Ducker {
+    amount: f32,
+    envelope: f32,
+    attack: f32,
+    release: f32,
+}
+
+impl Ducker {
+    /// `rate` is samples per second, counting every channel
+    fn set(&mut self, amount: f32, release: f32, rate: f32) {
+        let amount = amount.clamp(0.0, 1.0);
+        if self.amount <= 0.0 && amount > 0.0 {
+            self.envelope = 0.0;
+        }
+        self.amount = amount;
+        self.attack = 1.0 - (-1.0 / (DUCK_ATTACK * rate)).exp();
+        self.release = 1.0 - (-1.0 / (release.max(0.001) * rate)).exp();
+    }
+
+    fn gain(&mut self, key: f32) -> f32 {
+        if self.amount <= 0.0 {
+            return 1.0;
+        }
+        let key = key.abs();
+        let coeff = if key > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope += coeff * (key - self.envelope);
+        1.0 - self.amount * (self.envelope / DUCK_FULL_LEVEL).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RATE: f32 = 48_000.0;
+
+    /// RMS of a sine after `process`, skipping the first 0.1 s
+    fn sine_rms(freq: f32, mut process: impl FnMut(f32) -> f32) -> f32 {
+        let skip = (RATE * 0.1) as usize;
+        let len = RATE as usize;
+        let mut sum = 0.0;
+        for i in 0..len {
+            let x = (TAU * freq * i as f32 / RATE).sin();
+            let y = process(x);
+            if i >= skip {
+                sum += y * y;
+            }
+        }
+        (sum / (len - skip) as f32).sqrt()
+    }
+
+    fn close(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    #[test]
+    fn fade_value_at() {
+        let fade = Fade {
+            target: 0.0,
+            from: 1.0,
+            start: 100,
+            len: 100,
+        };
+        assert_eq!(fade.value_at(0), 1.0);
+        assert_eq!(fade.value_at(100), 1.0);
+        assert_eq!(fade.value_at(150), 0.5);
+        assert_eq!(fade.value_at(200), 0.0);
+        assert_eq!(fade.value_at(1000), 0.0);
+        let instant = Fade { len: 0, ..fade };
+        assert_eq!(instant.value_at(0), 0.0);
+    }
+
+    #[test]
+    fn automation_ramp_and_set() {
+        let automation = Automation::new(1.0);
+        assert_eq!(automation.value_at(0), 1.0);
+        assert_eq!(automation.target(), 1.0);
+
+        automation.ramp(0.0, 100, 100);
+        assert_eq!(automation.target(), 0.0);
+        assert_eq!(automation.value_at(50), 1.0);
+        assert_eq!(automation.value_at(150), 0.5);
+        assert_eq!(automation.value_at(250), 0.0);
+
+        // takes over halfway, from where the last ramp had got to
+        automation.ramp(1.0, 150, 50);
+        assert_eq!(automation.value_at(150), 0.5);
+        assert_eq!(automation.value_at(175), 0.75);
+        assert_eq!(automation.value_at(200), 1.0);
+
+        automation.set(0.25);
+        assert_eq!(automation.value_at(0), 0.25);
+        assert_eq!(automation.value_at(175), 0.25);
+    }
+
+    #[test]
+    fn automation_ramp_from_the_past() {
+        // a fade that should already have started is partway through
+        let automation = Automation::new(0.0);
+        automation.ramp(1.0, -100, 200);
+        assert_eq!(automation.value_at(0), 0.5);
+        // and one with no length is the same as setting it
+        automation.ramp(0.5, 0, -10);
+        assert_eq!(automation.value_at(0), 0.5);
+    }
+
+    #[test]
+    fn biquad_lowpass() {
+        let mut filter = Biquad::default();
+        filter.set_lowpass(1000.0, RATE);
+        let pass = sine_rms(100.0, |x| filter.process(0, x));
+        let corner = sine_rms(1000.0, |x| filter.process(0, x));
+        let stop = sine_rms(10_000.0, |x| filter.process(0, x));
+        assert!(
+            close(pass, FRAC_1_SQRT_2, 0.01),
+            "100 Hz: {pass}"
+        );
+        // -3 dB at the cutoff
+        assert!(
+            close(corner, 0.5, 0.01),
+            "1 kHz: {corner}"
+        );
+        assert!(stop < 0.01, "10 kHz: {stop}");
+    }
+
+    #[test]
+    fn biquad_highpass() {
+        let mut filter = Biquad::default();
+        filter.set_highpass(1000.0, RATE);
+        let stop = sine_rms(100.0, |x| filter.process(0, x));
+        let corner = sine_rms(1000.0, |x| filter.process(0, x));
+        let pass = sine_rms(10_000.0, |x| filter.process(0, x));
+        assert!(stop < 0.01, "100 Hz: {stop}");
+        assert!(
+            close(corner, 0.5, 0.01),
+            "1 kHz: {corner}"
+        );
+        assert!(
+            close(pass, FRAC_1_SQRT_2, 0.01),
+            "10 kHz: {pass}"
+        );
+    }
+
+    #[test]
+    fn biquad_channels_are_separate() {
+        let mut filter = Biquad::default();
+        filter.set_lowpass(1000.0, RATE);
+        for _ in 0..100 {
+            filter.process(0, 1.0);
+        }
+        assert_eq!(filter.process(1, 0.0), 0.0);
+    }
+
+    /// Energy of the reverb's response to an impulse, per 0.25 s
+    fn reverb_tail(size: f32, damping: f32) -> Vec<f32> {
+        let mut reverb = Reverb::new(RATE as u32);
+        reverb.set(1.0, size, damping);
+        let block = (RATE * 0.25) as usize;
+        (0..8)
+            .map(|b| {
+                (0..block)
+                    .map(|i| {
+                        let x = if b == 0 && i == 0 { 1.0 } else { 0.0 };
+                        reverb.process(0, x).powi(2)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reverb_off_passes_through() {
+        let mut reverb = Reverb::new(RATE as u32);
+        reverb.set(0.0, 1.0, 0.0);
+        for x in [1.0, -0.5, 0.25, 0.0] {
+            assert_eq!(reverb.process(0, x), x);
+        }
+    }
+
+    #[test]
+    fn reverb_tail_decays() {
+        let tail = reverb_tail(0.5, 0.5);
+        assert!(tail[0] > 0.0);
+        for pair in tail.windows(2) {
+            assert!(pair[1] < pair[0], "{tail:?}");
+        }
+        // bigger rooms ring for longer
+        let big = reverb_tail(1.0, 0.5);
+        assert!(big[7] > tail[7], "{big:?} {tail:?}");
+    }
+
+    #[test]
+    fn reverb_clears_when_turned_back_on() {
+        let mut reverb = Reverb::new(RATE as u32);
+        reverb.set(1.0, 1.0, 0.0);
+        for _ in 0..1000 {
+            reverb.process(0, 1.0);
+        }
+        reverb.set(0.0, 1.0, 0.0);
+        reverb.set(1.0, 1.0, 0.0);
+        for _ in 0..(RATE as usize) {
+            assert_eq!(reverb.process(0, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn ducker() {
+        let mut ducker = Ducker::default();
+        ducker.set(0.0, 0.5, RATE);
+        assert_eq!(ducker.gain(1.0), 1.0);
+
+        ducker.set(0.5, 0.5, RATE);
+        // 10 attack times at full level ducks all the way (by half)
+        let mut gain = 1.0;
+        for _ in 0..(RATE * 0.1) as usize {
+            gain = ducker.gain(DUCK_FULL_LEVEL);
+        }
+        assert!(close(gain, 0.5, 0.001), "{gain}");
+
+        // one release time after it stops, it has recovered to 1/e
+        for _ in 0..(RATE * 0.5) as usize {
+            gain = ducker.gain(0.0);
+        }
+        let expected = 1.0 - 0.5 * (-1.0f32).exp();
+        assert!(close(gain, expected, 0.001), "{gain}");
+    }
+
+    #[test]
+    fn default_chain_does_nothing() {
+        let mut effects = BusEffects::new(48_000, 2);
+        effects.update(AudioParam::defaults());
+        for x in [1.0, -0.5, 0.25, 0.0] {
+            assert_eq!(effects.process(0, x, 1.0), x);
+            assert_eq!(effects.process(1, x, 1.0), x);
+        }
+    }
+
+    #[test]
+    fn chain_picks_up_params() {
+        let mut effects = BusEffects::new(48_000, 2);
+        let mut params = AudioParam::defaults();
+        params[AudioParam::Lowpass.index()] = 1000.0;
+        effects.update(params);
+        assert!(effects.lowpass.is_some());
+        assert!(effects.highpass.is_none());
+        params[AudioParam::Lowpass.index()] = MAX_CUTOFF;
+        effects.update(params);
+        assert!(effects.lowpass.is_none());
+    }
+
+    #[test]
+    fn encoded_defaults_decode_to_defaults() {
+        let encoded = AudioParam::encoded_defaults();
+        for param in AudioParam::iter() {
+            assert_eq!(
+                param.decode(encoded[param.index()]),
+                param.default_value(),
+                "{param:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn filters_turn_off_after_a_ramp_back() {
+        let mut effects = BusEffects::new(48_000, 2);
+        let lowpass = Automation::new(AudioParam::Lowpass.encode(MAX_CUTOFF));
+        let highpass = Automation::new(AudioParam::Highpass.encode(MIN_CUTOFF));
+        let mut params = AudioParam::defaults();
+        let mut update_at = |sample: i64, effects: &mut BusEffects| {
+            params[AudioParam::Lowpass.index()] =
+                AudioParam::Lowpass.decode(lowpass.value_at(sample));
+            params[AudioParam::Highpass.index()] =
+                AudioParam::Highpass.decode(highpass.value_at(sample));
+            effects.update(params);
+        };
+
+        lowpass.ramp(
+            AudioParam::Lowpass.encode(800.0),
+            0,
+            1000,
+        );
+        highpass.ramp(
+            AudioParam::Highpass.encode(300.0),
+            0,
+            1000,
+        );
+        update_at(1000, &mut effects);
+        assert!(effects.lowpass.is_some());
+        assert!(effects.highpass.is_some());
+
+        lowpass.ramp(
+            AudioParam::Lowpass.encode(MAX_CUTOFF),
+            1000,
+            1000,
+        );
+        highpass.ramp(
+            AudioParam::Highpass.encode(MIN_CUTOFF),
+            1000,
+            1000,
+        );
+        for sample in (1000..=3000).step_by(100) {
+            update_at(sample, &mut effects);
+        }
+        assert!(effects.lowpass.is_none());
+        assert!(effects.highpass.is_none());
+    }
+}
@@ -1,9 +1,15 @@
-use std::sync::atomic::{AtomicBool, AtomicI64, Ordering as MemOrdering};
+use std::sync::atomic::{
+    AtomicBool, AtomicI64, AtomicU32, Ordering as MemOrdering,
+};
 use std::sync::Mutex;
 
 use cpal::FromSample;
 use rodio::{Sample, Source};
+use strum::IntoEnumIterator;
 
+use super::bus::AudioBus;
+use super::dsp::{AudioParam, Automation, BusEffects};
+use super::resample::Resampler;
 use crate::prelude::*;
 
 pub(super) type MySample = f32;
@@ -19,30 +25,164 @@ pub struct PrecisionMixer {
     sample_count: i64,
     current_channel: u16,
     playing: Vec<PrecisionMixerActiveTrack>,
+    /// The effect chain of each bus
+    effects: [BusEffects; AudioBus::COUNT],
 }
 
 pub struct PrecisionMixerController {
     has_pending: AtomicBool,
     has_playing: AtomicBool,
     sample_count: AtomicI64,
-    tick_rate: f32,
     sample_rate: u32,
     channels: u16,
+    /// `f32` bits of the gain of each bus
+    bus_gains: [AtomicU32; AudioBus::COUNT],
+    /// Effect parameters of each bus (encoded, see [`AudioParam::encode`])
+    bus_params: [[Automation; AudioParam::COUNT]; AudioBus::COUNT],
     pending: Mutex<Vec<PrecisionMixerQueuedTrack>>,
+    tick_clock: Mutex<TickClock>,
+    /// How many samples early to play sounds timed to game ticks
+    latency_offset: AtomicI64,
+}
+
+/// Maps game ticks to sample numbers
+///
+/// The mapping is piecewise linear: every time the rate of game ticks
+/// changes (slow motion, hit-stop), a new segment is started at the
+/// current tick, so that the mapping stays continuous.
+#[derive(Debug, Clone, Copy)]
+struct TickClock {
+    anchor_tick: f64,
+    anchor_sample: f64,
+    /// The (real) time when the current segment started
+    anchor_time: Duration,
+    /// Game ticks per second in the current segment (`0.0` if frozen)
+    tick_rate: f64,
+}
+
+/// Control over a sound that was sent to the mixer
+///
+/// Volume and pan can be changed while it is playing (or waiting to play).
+/// The mixer picks up the new values at the start of the next frame.
+/// Volume can also be ramped over time, for fades.
+/// Pitch changes are picked up every frame.
+pub struct MixerVoice {
+    volume: Automation,
+    /// `f32` bits
+    pan: AtomicU32,
+    /// `f32` bits, playback rate (`1.0` is normal)
+    pitch: AtomicU32,
+    /// Buses with ducking turn down while this plays
+    sidechain: AtomicBool,
+    stopped: AtomicBool,
+    /// Sample number to stop at
+    stop_at: AtomicI64,
+    done: AtomicBool,
+}
+
+impl MixerVoice {
+    pub(super) fn new(volume: f32, pan: f32) -> Arc<MixerVoice> {
+        Arc::new(MixerVoice {
+            volume: Automation::new(volume),
+            pan: AtomicU32::new(pan.to_bits()),
+            pitch: AtomicU32::new(1.0f32.to_bits()),
+            sidechain: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            stop_at: AtomicI64::new(i64::MAX),
+            done: AtomicBool::new(false),
+        })
+    }
+
+    /// The volume, or the one it is ramping towards
+    pub fn volume(&self) -> f32 {
+        self.volume.target()
+    }
+
+    /// Change the volume right away, cancelling any ramp
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+    }
+
+    /// Fade linearly to `volume` over `len` samples, starting at sample `start`
+    ///
+    /// The fade starts from whatever the volume would have been at `start`,
+    /// so a new ramp can take over from one that hasn't finished.
+    pub fn ramp_volume(&self, volume: f32, start: i64, len: i64) {
+        self.volume.ramp(volume, start, len);
+    }
+
+    /// The volume at a given sample number, following the ramp
+    pub fn volume_at(&self, sample: i64) -> f32 {
+        self.volume.value_at(sample)
+    }
+
+    pub fn pan(&self) -> f32 {
+        f32::from_bits(self.pan.load(MemOrdering::Relaxed))
+    }
+
+    pub fn set_pan(&self, pan: f32) {
+        self.pan.store(pan.to_bits(), MemOrdering::Relaxed);
+    }
+
+    pub fn pitch(&self) -> f32 {
+        f32::from_bits(self.pitch.load(MemOrdering::Relaxed))
+    }
+
+    /// Play faster and higher (`> 1.0`) or slower and lower (`< 1.0`)
+    ///
+    /// Changes the length of the sound, so anything timed
+    /// to the end of it will be off.
+    pub fn set_pitch(&self, pitch: f32) {
+        let pitch = pitch.clamp(1.0 / 16.0, 16.0);
+        self.pitch.store(pitch.to_bits(), MemOrdering::Relaxed);
+    }
+
+    /// Make buses with ducking turn down while this sound plays
+    pub fn set_sidechain(&self, sidechain: bool) {
+        self.sidechain.store(sidechain, MemOrdering::Relaxed);
+    }
+
+    pub fn is_sidechain(&self) -> bool {
+        self.sidechain.load(MemOrdering::Relaxed)
+    }
+
+    /// Cut the sound off (or cancel it, if it hasn't started yet)
+    pub fn stop(&self) {
+        self.stopped.store(true, MemOrdering::Relaxed);
+    }
+
+    /// Cut the sound off at an exact sample number
+    pub fn stop_at_sample(&self, sample: i64) {
+        self.stop_at.store(sample, MemOrdering::Relaxed);
+    }
+
+    fn should_stop(&self, sample: i64) -> bool {
+        self.stopped.load(MemOrdering::Relaxed)
+            || sample >= self.stop_at.load(MemOrdering::Relaxed)
+    }
+
+    /// Has the sound finished playing (or been stopped)?
+    pub fn is_done(&self) -> bool {
+        self.done.load(MemOrdering::Relaxed)
+    }
 }
 
 struct PrecisionMixerQueuedTrack {
     start_at_sample_number: Option<i64>,
     first_sample: MySample,
-    volume: f32,
-    pan: f32,
+    bus: AudioBus,
+    voice: Arc<MixerVoice>,
     source: Option<BoxedSource>,
 }
 
 struct PrecisionMixerActiveTrack {
     done: bool,
+    /// Including the gain of the bus
     volume: f32,
     pan: f32,
+    sidechain: bool,
+    bus: AudioBus,
+    voice: Arc<MixerVoice>,
     current_channel: u16,
     next_sample: MySample,
     source: BoxedSource,
@@ -64,7 +204,19 @@ impl PrecisionMixerController {
             pending: Mutex::new(Vec::with_capacity(16)),
             channels,
             sample_rate,
-            tick_rate,
+            bus_gains: std::array::from_fn(|_| {
+                AtomicU32::new(1.0f32.to_bits())
+            }),
+            bus_params: std::array::from_fn(|_| {
+                AudioParam::encoded_defaults().map(Automation::new)
+            }),
+            tick_clock: Mutex::new(TickClock {
+                anchor_tick: 0.0,
+                anchor_sample: 0.0,
+                anchor_time: Duration::ZERO,
+                tick_rate: tick_rate as f64,
+            }),
+            latency_offset: AtomicI64::new(0),
         })
     }
 
@@ -84,21 +236,175 @@ impl PrecisionMixerController {
         self.sample_rate
     }
 
+    /// Set how late the audio output is heard, in seconds
+    ///
+    /// Whatever keeps the sample counter in sync with the game should keep
+    /// it this much closer, so that sounds played with [`Self::play_at_tick`]
+    /// are heard in time with the game. Can be negative, if the audio is
+    /// heard early.
+    pub fn set_latency_offset(&self, seconds: f32) {
+        let samples = (seconds as f64 * self.sample_rate as f64).round();
+        self.latency_offset
+            .store(samples as i64, MemOrdering::Relaxed);
+    }
+
+    /// The latency offset, in seconds
+    pub fn latency_offset(&self) -> f32 {
+        (self.latency_offset.load(MemOrdering::Relaxed) as f64
+            / self.sample_rate as f64) as f32
+    }
+
+    /// Set the volume of a bus (`0.0` to mute it)
+    pub fn set_bus_gain(&self, bus: AudioBus, gain: f32) {
+        self.bus_gains[bus.index()].store(gain.to_bits(), MemOrdering::Relaxed);
+    }
+
+    pub fn bus_gain(&self, bus: AudioBus) -> f32 {
+        f32::from_bits(self.bus_gains[bus.index()].load(MemOrdering::Relaxed))
+    }
+
+    /// Change an effect parameter of a bus right away
+    pub fn set_bus_param(&self, bus: AudioBus, param: AudioParam, value: f32) {
+        self.bus_params[bus.index()][param.index()].set(param.encode(value));
+    }
+
+    /// Fade an effect parameter of a bus, over `len` samples from `start`
+    pub fn ramp_bus_param(
+        &self,
+        bus: AudioBus,
+        param: AudioParam,
+        value: f32,
+        start: i64,
+        len: i64,
+    ) {
+        self.bus_params[bus.index()][param.index()].ramp(
+            param.encode(value),
+            start,
+            len,
+        );
+    }
+
+    /// An effect parameter of a bus (or the value it is fading towards)
+    pub fn bus_param(&self, bus: AudioBus, param: AudioParam) -> f32 {
+        param.decode(self.bus_params[bus.index()][param.index()].target())
+    }
+
+    /// All effect parameters of a bus, at the given sample number
+    fn bus_params_at(
+        &self,
+        bus: usize,
+        sample: i64,
+    ) -> [f32; AudioParam::COUNT] {
+        let mut values = [0.0; AudioParam::COUNT];
+        for param in AudioParam::iter() {
+            let value = self.bus_params[bus][param.index()].value_at(sample);
+            values[param.index()] = param.decode(value);
+        }
+        values
+    }
+
+    /// The gain of a bus, after going through the master bus
+    fn effective_bus_gain(&self, bus: AudioBus) -> f32 {
+        if bus == AudioBus::Master {
+            self.bus_gain(AudioBus::Master)
+        } else {
+            self.bus_gain(bus) * self.bus_gain(AudioBus::Master)
+        }
+    }
+
+    /// Change the effective rate of game ticks
+    ///
+    /// `tick` is the (fractional) tick position at the (real) time `now`.
+    /// From then on, ticks are mapped to samples at the new `tick_rate`.
+    /// Use `0.0` when ticks are frozen (hit-stop).
+    ///
+    /// `skipped` is how many ticks were dropped (never run) since the last
+    /// call. Time still passed for them, so `tick` is anchored where the
+    /// audio would be if they had run, and the ticks after it are not
+    /// scheduled in the past.
+    pub fn retime(
+        &self,
+        tick: f64,
+        skipped: f64,
+        now: Duration,
+        tick_rate: f64,
+    ) {
+        let mut clock = self.tick_clock.lock().unwrap();
+        let anchor_sample = if clock.tick_rate > 0.0 {
+            clock.anchor_sample
+                + (tick + skipped - clock.anchor_tick) * self.sample_rate as f64
+                    / clock.tick_rate
+        } else {
+            // ticks were frozen, so only real time has passed
+            clock.anchor_sample
+                + now.saturating_sub(clock.anchor_time).as_secs_f64()
+                    * self.sample_rate as f64
+        };
+        *clock = TickClock {
+            anchor_tick: tick,
+            anchor_sample,
+            anchor_time: now,
+            tick_rate: tick_rate.max(0.0),
+        };
+    }
+
+    /// Get the current effective rate of game ticks (`0.0` if frozen)
+    pub fn tick_rate(&self) -> f64 {
+        self.tick_clock.lock().unwrap().tick_rate
+    }
+
+    /// Get the sample number at which the given game tick happens
+    pub fn tick_to_sample(&self, tick: f64) -> i64 {
+        let clock = self.tick_clock.lock().unwrap();
+        if clock.tick_rate > 0.0 {
+            (clock.anchor_sample
+                + (tick - clock.anchor_tick) * self.sample_rate as f64
+                    / clock.tick_rate) as i64
+        } else {
+            clock.anchor_sample as i64
+        }
+    }
+
+    /// Get the sample number at the given time since the mixer started
+    pub fn time_to_sample(&self, dur: Duration) -> i64 {
+        let seconds = dur.as_secs();
+        let nanos = dur.subsec_nanos();
+        ((seconds * self.sample_rate as u64)
+            + (self.sample_rate as u64 * nanos as u64 / 1_000_000_000))
+            as i64
+    }
+
+    /// Get the (fractional) game tick that corresponds to the given sample number
+    pub fn sample_to_tick(&self, sample: i64) -> f64 {
+        let clock = self.tick_clock.lock().unwrap();
+        if clock.tick_rate > 0.0 {
+            clock.anchor_tick
+                + (sample as f64 - clock.anchor_sample) * clock.tick_rate
+                    / self.sample_rate as f64
+        } else {
+            clock.anchor_tick
+        }
+    }
+
     fn play_at_sample_number<T, S>(
         &self,
         start_at_sample_number: Option<i64>,
         source: T,
+        bus: AudioBus,
         volume: f32,
         pan: f32,
-    ) where
+    ) -> Arc<MixerVoice>
+    where
         T: Source<Item = S> + Send + Sync + 'static,
         S: Sample + Send + 'static,
         MySample: FromSample<S>,
     {
-        if source.channels() > 2 {
-            panic!("GameTickMixer does not support > 2 audio channels!");
-        }
-        let mut source = Box::new(source.convert_samples::<MySample>());
+        let voice = MixerVoice::new(volume, pan);
+        let mut source: BoxedSource = Box::new(Resampler::new(
+            source.convert_samples::<MySample>(),
+            voice.clone(),
+            self.sample_rate,
+        ));
         if let Some(first_sample) = (&mut *source).next() {
             self.pending
                 .lock()
@@ -106,69 +412,95 @@ impl PrecisionMixerController {
                 .push(PrecisionMixerQueuedTrack {
                     start_at_sample_number,
                     first_sample,
-                    volume,
-                    pan,
+                    bus,
+                    voice: voice.clone(),
                     source: Some(source),
                 });
+        } else {
+            voice.done.store(true, MemOrdering::Relaxed);
         }
         self.has_pending.store(true, MemOrdering::SeqCst);
+        voice
     }
 
-    pub fn play_immediately<T, S>(&self, source: T, volume: f32, pan: f32)
+    pub fn play_immediately<T, S>(
+        &self,
+        source: T,
+        bus: AudioBus,
+        volume: f32,
+        pan: f32,
+    ) -> Arc<MixerVoice>
     where
         T: Source<Item = S> + Send + Sync + 'static,
         S: Sample + Send + 'static,
         MySample: FromSample<S>,
     {
-        self.play_at_sample_number(None, source, volume, pan);
+        self.play_at_sample_number(None, source, bus, volume, pan)
+    }
+
+    /// Play a sound starting exactly at the given sample number
+    pub fn play_at_sample<T, S>(
+        &self,
+        sample: i64,
+        source: T,
+        bus: AudioBus,
+        volume: f32,
+        pan: f32,
+    ) -> Arc<MixerVoice>
+    where
+        T: Source<Item = S> + Send + Sync + 'static,
+        S: Sample + Send + 'static,
+        MySample: FromSample<S>,
+    {
+        self.play_at_sample_number(Some(sample), source, bus, volume, pan)
     }
 
     pub fn play_at_time<T, S>(
         &self,
         dur: Duration,
         source: T,
+        bus: AudioBus,
         volume: f32,
         pan: f32,
-    ) where
+    ) -> Arc<MixerVoice>
+    where
         T: Source<Item = S> + Send + Sync + 'static,
         S: Sample + Send + 'static,
         MySample: FromSample<S>,
     {
-        let seconds = dur.as_secs();
-        let nanos = dur.subsec_nanos();
-        let start_at_sample_number = (seconds as u64 * self.sample_rate as u64)
-            + (self.sample_rate as u64 * nanos as u64 / 1_000_000_000);
         self.play_at_sample_number(
-            Some(start_at_sample_number as i64),
+            Some(self.time_to_sample(dur)),
             source,
+            bus,
             volume,
             pan,
-        );
+        )
     }
 
+    /// Play in time with a game tick
     pub fn play_at_tick<T, S>(
         &self,
         tick: u32,
         offset_nanos: i32,
         source: T,
+        bus: AudioBus,
         volume: f32,
         pan: f32,
-    ) where
+    ) -> Arc<MixerVoice>
+    where
         T: Source<Item = S> + Send + Sync + 'static,
         S: Sample + Send + 'static,
         MySample: FromSample<S>,
     {
-        let start_at_sample_number = ((tick as f64 * self.sample_rate as f64
-            / self.tick_rate as f64)
-            as i64
-            + (self.sample_rate as i64 * offset_nanos as i64 / 1_000_000_000))
-            as i64;
+        let start_at_sample_number = self.tick_to_sample(tick as f64)
+            + (self.sample_rate as i64 * offset_nanos as i64 / 1_000_000_000);
         self.play_at_sample_number(
             Some(start_at_sample_number),
             source,
+            bus,
             volume,
             pan,
-        );
+        )
     }
 }
 
@@ -197,11 +529,8 @@ impl Iterator for PrecisionMixer {
             MemOrdering::Relaxed,
         );
 
-        if self.playing.is_empty() {
-            Some(MySample::zero_value())
-        } else {
-            Some(value)
-        }
+        // still output the effects when nothing is playing, for reverb tails
+        Some(value)
     }
 }
 
@@ -233,6 +562,12 @@ impl PrecisionMixer {
             current_channel: 0,
             sample_count: 0,
             playing: Vec::with_capacity(16),
+            effects: std::array::from_fn(|_| {
+                BusEffects::new(
+                    controller.sample_rate,
+                    controller.channels,
+                )
+            }),
             controller: controller.clone(),
         }
     }
@@ -242,9 +577,32 @@ impl PrecisionMixer {
     }
 
     fn mix(&mut self) -> MySample {
-        let mut sum = MySample::zero_value();
+        let mut bus_sums = [MySample::zero_value(); AudioBus::COUNT];
+        // level of everything that other buses duck under
+        let mut key = MySample::zero_value();
         let channels = self.channels();
+        if self.current_channel == 0 {
+            // only at frame boundaries, so both channels get the same values
+            for track in self.playing.iter_mut() {
+                track.volume = track.voice.volume_at(self.sample_count)
+                    * self.controller.effective_bus_gain(track.bus);
+                track.pan = track.voice.pan();
+                track.sidechain = track.voice.is_sidechain();
+                if track.voice.should_stop(self.sample_count) {
+                    track.done = true;
+                }
+            }
+            for (bus, effects) in self.effects.iter_mut().enumerate() {
+                effects.update(
+                    self.controller.bus_params_at(bus, self.sample_count),
+                );
+            }
+        }
         for track in self.playing.iter_mut() {
+            if track.done {
+                continue;
+            }
+            let mut sum = MySample::zero_value();
             let source_channels = track.source.channels();
             let (pan_l, pan_r) = pan_lr(track.pan.clamp(-1.0, 1.0));
             match (channels, source_channels) {
@@ -338,13 +696,29 @@ impl PrecisionMixer {
                         _ => unreachable!(),
                     }
                 },
-                _ => {
-                    panic!("GameTickMixer does not support > 2 audio channels!")
-                },
+                _ => unreachable!("sources are downmixed to stereo"),
+            }
+            bus_sums[track.bus.index()] += sum;
+            if track.sidechain {
+                key += sum.abs();
+            }
+        }
+        self.playing.retain(|track| {
+            if track.done {
+                track.voice.done.store(true, MemOrdering::Relaxed);
+            }
+            !track.done
+        });
+
+        let channel = self.current_channel as usize;
+        let master = AudioBus::Master.index();
+        let mut sum = bus_sums[master];
+        for (bus, value) in bus_sums.into_iter().enumerate() {
+            if bus != master {
+                sum += self.effects[bus].process(channel, value, key);
             }
         }
-        self.playing.retain(|track| !track.done);
-        sum
+        self.effects[master].process(channel, sum, key)
     }
 
     fn process_pending(&mut self) {
@@ -371,6 +745,10 @@ impl PrecisionMixer {
             let Some(mut source) = track.source.take() else {
                 continue;
             };
+            if track.voice.should_stop(self.sample_count) {
+                track.voice.done.store(true, MemOrdering::Relaxed);
+                continue;
+            }
 
             // if we are already late, we have to skip ahead into the source
             let missed_by = self.sample_count - start_at_sample_number;
@@ -389,8 +767,12 @@ impl PrecisionMixer {
             self.playing.push(PrecisionMixerActiveTrack {
                 done: false,
                 current_channel: 0,
-                pan: track.pan,
-                volume: track.volume,
+                pan: track.voice.pan(),
+                sidechain: track.voice.is_sidechain(),
+                volume: track.voice.volume_at(self.sample_count)
+                    * self.controller.effective_bus_gain(track.bus),
+                bus: track.bus,
+                voice: track.voice.clone(),
                 next_sample: track.first_sample,
                 source,
             })
@@ -433,3 +815,46 @@ fn pan_lr(pan: f32) -> (f32, f32) {
         (0.0, 0.0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::TAU;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    #[test]
+    fn fresh_mixer_passes_sound_through() {
+        for bus in AudioBus::iter() {
+            let controller = PrecisionMixerController::new(2, 48_000, 96.0);
+            let mut mixer = PrecisionMixer::new(controller.clone());
+            for param in AudioParam::iter() {
+                assert_eq!(
+                    controller.bus_param(bus, param),
+                    param.default_value(),
+                    "{bus:?} {param:?}"
+                );
+            }
+            let tone: Vec<f32> = (0..4800)
+                .map(|i| 0.5 * (TAU * 1000.0 * i as f32 / 48_000.0).sin())
+                .collect();
+            controller.play_at_sample(
+                0,
+                SamplesBuffer::new(1, 48_000, tone.clone()),
+                bus,
+                1.0,
+                0.0,
+            );
+            for (i, x) in tone.iter().enumerate() {
+                let left = mixer.next().unwrap();
+                let right = mixer.next().unwrap();
+                assert_eq!(
+                    (left, right),
+                    (*x, *x),
+                    "{bus:?} sample {i}"
+                );
+            }
+        }
+    }
+}
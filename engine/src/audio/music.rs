@@ -0,0 +1,744 @@
+//! Beat-synced music
+//!
+//! A [`MusicTrack`] asset describes a piece of music: its tempo, where the
+//! intro ends and which part loops. Tracks are played through the mixer with
+//! sample-accurate looping, so the beat never drifts from the game clock.
+//!
+//! Track changes and stingers go through the [`MusicPlayer`] and can be
+//! delayed to the next beat or bar, and so can fades of individual stems
+//! (for music that adapts to the game). The current position in the music
+//! is available as the [`MusicClock`] resource.
+
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use bevy_common_assets::toml::TomlAssetPlugin;
+use rodio::Source;
+
+use super::bus::AudioBus;
+use super::mixer::MixerVoice;
+use super::PrecisionMixerControl;
+use crate::prelude::*;
+use crate::script::ScriptSet;
+
+pub struct MusicPlugin;
+
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(TomlAssetPlugin::<MusicTrack>::new(&[
+            "music.toml",
+        ]));
+        app.init_resource::<MusicPlayer>();
+        app.init_resource::<MusicClock>();
+        app.add_systems(
+            GameTickUpdate,
+            update_music.in_set(MusicSet).before(ScriptSet::Run),
+        );
+    }
+}
+
+/// When the music is updated and the [`MusicClock`] advances
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MusicSet;
+
+/// Music asset (`*.music.toml`)
+///
+/// All positions are in samples (per channel) of the audio files.
+///
+/// ```toml
+/// audio = "audio.music.Forest"
+/// bpm = 96.0
+/// beats_per_bar = 4
+/// loop_start = 368640
+/// loop_end = 2580480
+/// ```
+#[derive(Asset, Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[derive(TypePath)]
+pub struct MusicTrack {
+    /// Asset key of the audio, for a track that isn't split into stems
+    pub audio: Option<String>,
+    /// Separate parts of the track, played in sync
+    #[serde(default)]
+    pub stems: Vec<MusicStem>,
+    pub bpm: f64,
+    #[serde(default = "default_beats_per_bar")]
+    pub beats_per_bar: u32,
+    /// Where the first bar starts (anything before it is a pickup)
+    #[serde(default)]
+    pub first_beat: u64,
+    /// Where looping jumps back to; everything before it is the intro
+    #[serde(default)]
+    pub loop_start: u64,
+    /// Where to jump back to `loop_start` (default: the end of the audio)
+    pub loop_end: Option<u64>,
+    /// Play once instead of looping
+    #[serde(default)]
+    pub no_loop: bool,
+}
+
+fn default_beats_per_bar() -> u32 {
+    4
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct MusicStem {
+    /// Used to control the stem while it is playing
+    pub name: String,
+    /// Asset key of the audio
+    pub audio: String,
+    #[serde(default = "default_stem_volume")]
+    pub volume: f32,
+}
+
+fn default_stem_volume() -> f32 {
+    1.0
+}
+
+impl MusicTrack {
+    /// All the stems, including `audio` (as a stem called `"main"`)
+    pub fn all_stems(&self) -> impl Iterator<Item = MusicStem> + '_ {
+        self.audio
+            .iter()
+            .map(|audio| {
+                MusicStem {
+                    name: "main".into(),
+                    audio: audio.clone(),
+                    volume: 1.0,
+                }
+            })
+            .chain(self.stems.iter().cloned())
+    }
+}
+
+/// When a music change should happen
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MusicSync {
+    Now,
+    NextBeat,
+    #[default]
+    NextBar,
+}
+
+/// Controls the music
+///
+/// Requests are applied on the next game tick, and scheduled to
+/// happen in the mixer exactly on the requested beat or bar.
+///
+/// Tracks are decoded in the background the first time they are played.
+/// Until that is done, the request to play it and all the requests after
+/// it wait.
+#[derive(Resource, Default)]
+pub struct MusicPlayer {
+    requests: Vec<(MusicRequest, MusicSync)>,
+    current: Option<PlayingMusic>,
+    /// The previous track, until the current one actually starts
+    outgoing: Option<PlayingMusic>,
+    decoded: HashMap<String, DecodedAudio>,
+    decoding: HashMap<String, Task<Option<DecodedAudio>>>,
+}
+
+/// Where the audio of a stem is at
+enum Decode {
+    Ready(DecodedAudio),
+    Pending,
+    Failed,
+}
+
+enum MusicRequest {
+    Play(String),
+    Stop,
+    Stinger { audio: String, volume: f32 },
+    FadeStem { stem: String, gain: f32, beats: f32 },
+}
+
+impl MusicPlayer {
+    /// Switch to another track (by asset key of the [`MusicTrack`])
+    pub fn play(&mut self, key: impl Into<String>, sync: MusicSync) {
+        self.requests.push((MusicRequest::Play(key.into()), sync));
+    }
+
+    pub fn stop(&mut self, sync: MusicSync) {
+        self.requests.push((MusicRequest::Stop, sync));
+    }
+
+    /// Play a one-shot sound over the music (by asset key of the audio)
+    pub fn stinger(
+        &mut self,
+        audio: impl Into<String>,
+        volume: f32,
+        sync: MusicSync,
+    ) {
+        self.requests.push((
+            MusicRequest::Stinger {
+                audio: audio.into(),
+                volume,
+            },
+            sync,
+        ));
+    }
+
+    /// Fade a stem of the current track, over a number of beats
+    ///
+    /// `gain` is relative to the stem's volume in the track
+    /// (`1.0` is as mixed, `0.0` is silent). Does nothing if the current
+    /// track has no such stem.
+    pub fn fade_stem(
+        &mut self,
+        stem: impl Into<String>,
+        gain: f32,
+        beats: f32,
+        sync: MusicSync,
+    ) {
+        self.requests.push((
+            MusicRequest::FadeStem {
+                stem: stem.into(),
+                gain,
+                beats,
+            },
+            sync,
+        ));
+    }
+
+    /// Asset key of the current track
+    pub fn current_key(&self) -> Option<&str> {
+        self.current.as_ref().map(|m| m.key.as_str())
+    }
+
+    /// The mixer voice of a stem of the current track
+    pub fn stem(&self, name: &str) -> Option<&Arc<MixerVoice>> {
+        let current = self.current.as_ref()?;
+        current
+            .stems
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| &s.voice)
+    }
+
+    /// Names of the stems of the current track
+    pub fn stem_names(&self) -> impl Iterator<Item = &str> {
+        self.current
+            .iter()
+            .flat_map(|m| m.stems.iter().map(|s| s.name.as_str()))
+    }
+
+    /// Sample number of the next beat/bar, or `now` if nothing is playing
+    fn sync_sample(&self, now: i64, sync: MusicSync) -> i64 {
+        let Some(current) = &self.current else {
+            return now;
+        };
+        let every = match sync {
+            MusicSync::Now => return now,
+            MusicSync::NextBeat => 1.0,
+            MusicSync::NextBar => current.timing.beats_per_bar as f64,
+        };
+        let beat = current.timing.beat_at(now - current.start_sample);
+        let target = (beat / every).ceil() * every;
+        now + ((target - beat) * current.timing.samples_per_beat).round() as i64
+    }
+
+    /// Collect the audio that finished decoding
+    fn poll_decoding(&mut self) {
+        let mut done = vec![];
+        for (key, task) in self.decoding.iter_mut() {
+            if let Some(result) = block_on(future::poll_once(task)) {
+                done.push((key.clone(), result));
+            }
+        }
+        for (key, result) in done {
+            self.decoding.remove(&key);
+            match result {
+                Some(decoded) => {
+                    self.decoded.insert(key, decoded);
+                },
+                None => {
+                    error!(
+                        "Music audio {:?} could not be decoded",
+                        key
+                    )
+                },
+            }
+        }
+    }
+
+    fn decode(
+        &mut self,
+        key: &str,
+        preloaded: &PreloadedAssets,
+        ass_audio: &Assets<AudioSource>,
+    ) -> Decode {
+        if let Some(decoded) = self.decoded.get(key) {
+            return Decode::Ready(decoded.clone());
+        }
+        if self.decoding.contains_key(key) {
+            return Decode::Pending;
+        }
+        let Some(audio) = preloaded
+            .get_single_assetid::<AudioSource>(key)
+            .and_then(|id| ass_audio.get(id))
+        else {
+            error!("Music audio {:?} is not loaded", key);
+            return Decode::Failed;
+        };
+        // decoding a whole track takes a while, so it is done once,
+        // and not on the game thread
+        let audio = audio.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let decoder = audio.decoder();
+            let channels = decoder.channels();
+            let sample_rate = decoder.sample_rate();
+            let samples = decoder.convert_samples::<f32>().collect::<Vec<_>>();
+            (!samples.is_empty()).then(|| {
+                DecodedAudio {
+                    samples: samples.into(),
+                    channels,
+                    sample_rate,
+                }
+            })
+        });
+        self.decoding.insert(key.to_owned(), task);
+        Decode::Pending
+    }
+}
+
+#[derive(Clone)]
+struct DecodedAudio {
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+struct PlayingMusic {
+    key: String,
+    /// Sample number where the track starts
+    start_sample: i64,
+    timing: MusicTiming,
+    stems: Vec<PlayingStem>,
+}
+
+struct PlayingStem {
+    name: String,
+    voice: Arc<MixerVoice>,
+    /// The volume from the track asset
+    volume: f32,
+}
+
+/// All positions are in mixer samples
+#[derive(Debug, Clone, Copy)]
+struct MusicTiming {
+    bpm: f64,
+    beats_per_bar: u32,
+    samples_per_beat: f64,
+    first_beat: i64,
+    loop_start: i64,
+    /// `None` if it doesn't loop
+    loop_end: Option<i64>,
+}
+
+impl MusicTiming {
+    /// Position in the audio and how many times it has looped
+    fn position(&self, elapsed: i64) -> (i64, i64) {
+        match self.loop_end {
+            Some(loop_end) if elapsed >= loop_end => {
+                let len = (loop_end - self.loop_start).max(1);
+                let since = elapsed - loop_end;
+                (
+                    self.loop_start + since % len,
+                    1 + since / len,
+                )
+            },
+            _ => (elapsed, 0),
+        }
+    }
+
+    /// Beats since the first beat (fractional), counting through loops
+    fn beat_at(&self, elapsed: i64) -> f64 {
+        let (pos, loops) = self.position(elapsed);
+        let beats_per_loop = self
+            .loop_end
+            .map(|end| {
+                ((end - self.loop_start) as f64 / self.samples_per_beat).round()
+            })
+            .unwrap_or(0.0);
+        (pos - self.first_beat) as f64 / self.samples_per_beat
+            + loops as f64 * beats_per_loop
+    }
+}
+
+/// Where we are in the music
+#[derive(Resource, Debug, Default, Clone)]
+pub struct MusicClock {
+    /// Asset key of the track, if any is playing
+    pub key: Option<String>,
+    pub bpm: f64,
+    pub beats_per_bar: u32,
+    /// Beats since the start of the track (keeps counting through loops)
+    pub beat: u64,
+    /// Bars since the start of the track
+    pub bar: u64,
+    /// Beat within the current bar, from 0
+    pub beat_in_bar: u32,
+    /// How far into the current beat (`0.0` to `1.0`)
+    pub beat_phase: f32,
+    /// A new beat started on this tick
+    pub is_new_beat: bool,
+}
+
+impl MusicClock {
+    pub fn is_playing(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// A new bar started on this tick
+    pub fn is_new_bar(&self) -> bool {
+        self.is_new_beat && self.beat_in_bar == 0
+    }
+}
+
+/// Loops a decoded track between its loop points
+struct LoopingSource {
+    audio: DecodedAudio,
+    /// All in interleaved samples
+    pos: usize,
+    loop_start: usize,
+    loop_end: usize,
+    looping: bool,
+}
+
+impl Iterator for LoopingSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.loop_end {
+            if !self.looping {
+                return None;
+            }
+            self.pos = self.loop_start;
+        }
+        let sample = self.audio.samples.get(self.pos).copied();
+        self.pos += 1;
+        sample
+    }
+}
+
+impl Source for LoopingSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.audio.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn update_music(
+    mut player: ResMut<MusicPlayer>,
+    mut clock: ResMut<MusicClock>,
+    q_mixer: Query<&PrecisionMixerControl>,
+    gt: Res<GameTime>,
+    preloaded: Res<PreloadedAssets>,
+    tracks: Res<Assets<MusicTrack>>,
+    ass_audio: Res<Assets<AudioSource>>,
+) {
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let now = ctl.controller.tick_to_sample(gt.tick() as f64);
+    player.poll_decoding();
+
+    let mut requests = std::mem::take(&mut player.requests).into_iter();
+    while let Some((request, sync)) = requests.next() {
+        let at = player.sync_sample(now, sync);
+        match request {
+            MusicRequest::Play(key) => {
+                let Some(track) = preloaded
+                    .get_single_assetid::<MusicTrack>(&key)
+                    .and_then(|id| tracks.get(id))
+                else {
+                    error!("Music track {:?} is not loaded", key);
+                    continue;
+                };
+                let mut pending = false;
+                let stems = track
+                    .all_stems()
+                    .filter_map(|stem| {
+                        match player.decode(&stem.audio, &preloaded, &ass_audio)
+                        {
+                            Decode::Ready(audio) => Some((stem, audio)),
+                            Decode::Pending => {
+                                pending = true;
+                                None
+                            },
+                            Decode::Failed => None,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                // try again next tick, keeping everything after it in order
+                if pending {
+                    player.requests.push((MusicRequest::Play(key), sync));
+                    player.requests.extend(requests);
+                    break;
+                }
+                let Some((_, first)) = stems.first() else {
+                    error!("Music track {:?} has no audio", key);
+                    continue;
+                };
+                let frames = first.samples.len() as i64 / first.channels as i64;
+                let loop_end =
+                    track.loop_end.map(|e| e as i64).unwrap_or(frames);
+                // the audio is resampled to the mixer's rate, so the timing
+                // has to be converted from positions in the file
+                let mixer_rate = ctl.controller.sample_rate() as f64;
+                let to_mixer = |pos: i64| {
+                    (pos as f64 * mixer_rate / first.sample_rate as f64).round()
+                        as i64
+                };
+                let timing = MusicTiming {
+                    bpm: track.bpm,
+                    beats_per_bar: track.beats_per_bar.max(1),
+                    samples_per_beat: mixer_rate * 60.0 / track.bpm.max(1.0),
+                    first_beat: to_mixer(track.first_beat as i64),
+                    loop_start: to_mixer(track.loop_start as i64),
+                    loop_end: (!track.no_loop).then_some(to_mixer(loop_end)),
+                };
+
+                if let Some(current) = player.current.take() {
+                    for stem in current.stems.iter() {
+                        stem.voice.stop_at_sample(at);
+                    }
+                    player.outgoing = Some(current);
+                }
+                let stems = stems
+                    .into_iter()
+                    .map(|(stem, audio)| {
+                        let channels = audio.channels as usize;
+                        let source = LoopingSource {
+                            pos: 0,
+                            loop_start: track.loop_start as usize * channels,
+                            loop_end: (loop_end as usize * channels)
+                                .min(audio.samples.len()),
+                            looping: !track.no_loop,
+                            audio,
+                        };
+                        let voice = ctl.controller.play_at_sample(
+                            at,
+                            source,
+                            AudioBus::Music,
+                            stem.volume,
+                            0.0,
+                        );
+                        PlayingStem {
+                            name: stem.name,
+                            voice,
+                            volume: stem.volume,
+                        }
+                    })
+                    .collect();
+                player.current = Some(PlayingMusic {
+                    key,
+                    start_sample: at,
+                    timing,
+                    stems,
+                });
+            },
+            MusicRequest::Stop => {
+                if let Some(current) = player.current.take() {
+                    for stem in current.stems.iter() {
+                        stem.voice.stop_at_sample(at);
+                    }
+                    player.outgoing = Some(current);
+                }
+            },
+            MusicRequest::Stinger { audio, volume } => {
+                let Some(audio) = preloaded
+                    .get_single_assetid::<AudioSource>(&audio)
+                    .and_then(|id| ass_audio.get(id))
+                else {
+                    error!("Stinger {:?} is not loaded", audio);
+                    continue;
+                };
+                ctl.controller.play_at_sample(
+                    at,
+                    audio.decoder(),
+                    AudioBus::Music,
+                    volume,
+                    0.0,
+                );
+            },
+            MusicRequest::FadeStem { stem, gain, beats } => {
+                let Some(current) = &player.current else {
+                    continue;
+                };
+                let Some(playing) =
+                    current.stems.iter().find(|s| s.name == stem)
+                else {
+                    continue;
+                };
+                let len =
+                    beats.max(0.0) as f64 * current.timing.samples_per_beat;
+                playing.voice.ramp_volume(
+                    playing.volume * gain,
+                    at,
+                    len as i64,
+                );
+            },
+        }
+    }
+
+    // the clock follows whatever is audible right now
+    let started = |m: &PlayingMusic| now >= m.start_sample;
+    if player.current.as_ref().is_some_and(started)
+        || player
+            .outgoing
+            .as_ref()
+            .is_some_and(|m| m.stems.iter().all(|s| s.voice.is_done()))
+    {
+        player.outgoing = None;
+    }
+    let audible = player
+        .current
+        .as_ref()
+        .filter(|m| started(*m))
+        .or(player.outgoing.as_ref());
+    let Some(music) = audible else {
+        *clock = MusicClock::default();
+        return;
+    };
+    let beat = music.timing.beat_at(now - music.start_sample).max(0.0);
+    let whole = beat.floor() as u64;
+    let is_new_beat =
+        clock.key.as_deref() != Some(music.key.as_str()) || whole != clock.beat;
+    *clock = MusicClock {
+        key: Some(music.key.clone()),
+        bpm: music.timing.bpm,
+        beats_per_bar: music.timing.beats_per_bar,
+        beat: whole,
+        bar: whole / music.timing.beats_per_bar as u64,
+        beat_in_bar: (whole % music.timing.beats_per_bar as u64) as u32,
+        beat_phase: beat.fract() as f32,
+        is_new_beat,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 100 samples per beat, 4 beats per bar. The first beat is at 50,
+    /// and it loops 8 beats from 450 to 1250.
+    fn timing() -> MusicTiming {
+        MusicTiming {
+            bpm: 120.0,
+            beats_per_bar: 4,
+            samples_per_beat: 100.0,
+            first_beat: 50,
+            loop_start: 450,
+            loop_end: Some(1250),
+        }
+    }
+
+    fn player(start_sample: i64) -> MusicPlayer {
+        MusicPlayer {
+            current: Some(PlayingMusic {
+                key: "music.test".into(),
+                start_sample,
+                timing: timing(),
+                stems: vec![],
+            }),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn position_loops() {
+        let timing = timing();
+        assert_eq!(timing.position(0), (0, 0));
+        assert_eq!(timing.position(1249), (1249, 0));
+        assert_eq!(timing.position(1250), (450, 1));
+        assert_eq!(timing.position(1300), (500, 1));
+        assert_eq!(timing.position(2049), (1249, 1));
+        assert_eq!(timing.position(2050), (450, 2));
+
+        let once = MusicTiming {
+            loop_end: None,
+            ..timing
+        };
+        assert_eq!(once.position(5000), (5000, 0));
+    }
+
+    #[test]
+    fn beats_count_through_loops() {
+        let timing = timing();
+        assert_eq!(timing.beat_at(50), 0.0);
+        assert_eq!(timing.beat_at(0), -0.5);
+        assert!((timing.beat_at(1249) - 11.99).abs() < 1e-9);
+        // the loop point is on beat 12, and beat 4 again in the audio
+        assert_eq!(timing.beat_at(1250), 12.0);
+        assert_eq!(timing.beat_at(1300), 12.5);
+        assert_eq!(timing.beat_at(2050), 20.0);
+    }
+
+    #[test]
+    fn sync_to_next_beat_and_bar() {
+        let player = player(1000);
+        let now = 1000 + 1000;
+        // beat 9.5
+        assert_eq!(
+            player.sync_sample(now, MusicSync::Now),
+            now
+        );
+        assert_eq!(
+            player.sync_sample(now, MusicSync::NextBeat),
+            now + 50
+        );
+        assert_eq!(
+            player.sync_sample(now, MusicSync::NextBar),
+            now + 250
+        );
+        // on a bar already
+        let on_bar = 1000 + 850;
+        assert_eq!(
+            player.sync_sample(on_bar, MusicSync::NextBar),
+            on_bar
+        );
+    }
+
+    #[test]
+    fn sync_across_the_loop() {
+        let player = player(1000);
+        // beat 11.9, the next bar starts right at the loop point
+        let now = 1000 + 1240;
+        assert_eq!(
+            player.sync_sample(now, MusicSync::NextBeat),
+            1000 + 1250
+        );
+        assert_eq!(
+            player.sync_sample(now, MusicSync::NextBar),
+            1000 + 1250
+        );
+        // beat 12.5, after looping
+        let now = 1000 + 1300;
+        let next_bar = player.sync_sample(now, MusicSync::NextBar);
+        assert_eq!(next_bar, 1000 + 1650);
+        assert_eq!(timing().beat_at(next_bar - 1000), 16.0);
+    }
+
+    #[test]
+    fn sync_without_music() {
+        let player = MusicPlayer::default();
+        assert_eq!(
+            player.sync_sample(1234, MusicSync::NextBar),
+            1234
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Rendering audio without a sound device
+//!
+//! With the [`OfflineAudioPlugin`], the mixer is not connected to an audio
+//! output. Instead, it is pulled on a simulated device clock that follows
+//! the app's real time, and everything it plays is kept in the
+//! [`OfflineAudio`] resource, to be checked by tests or written to a WAV file.
+//!
+//! Use it with a manual `TimeUpdateStrategy` (like headless mode does),
+//! to get exactly the same audio on every run.
+
+use std::path::{Path, PathBuf};
+
+use super::mixer::{MySample, PrecisionMixer, PrecisionMixerController};
+use super::PrecisionMixerControl;
+use crate::prelude::*;
+
+pub struct OfflineAudioPlugin {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// How much faster (or slower, if negative) the simulated device runs
+    /// than the app's clock, as a fraction (`0.001` is 0.1% fast)
+    pub drift: f64,
+    /// Write everything that was played to this WAV file on exit
+    pub output: Option<PathBuf>,
+}
+
+impl Default for OfflineAudioPlugin {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 2,
+            drift: 0.0,
+            output: None,
+        }
+    }
+}
+
+impl Plugin for OfflineAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(OfflineAudio {
+            sample_rate: self.sample_rate,
+            channels: self.channels.clamp(1, 2),
+            drift: self.drift,
+            output: self.output.clone(),
+            mixer: None,
+            samples: Vec::new(),
+            owed: 0.0,
+        });
+        app.add_systems(Startup, setup_offline_mixer);
+        app.add_systems(
+            Update,
+            pull_offline_audio.after(GameTickSet::Post),
+        );
+        app.add_systems(
+            Last,
+            write_offline_audio.run_if(on_event::<bevy::app::AppExit>()),
+        );
+    }
+}
+
+/// The mixer output, when rendering offline
+#[derive(Resource)]
+pub struct OfflineAudio {
+    sample_rate: u32,
+    channels: u16,
+    drift: f64,
+    output: Option<PathBuf>,
+    /// Set up along with the mixer controller
+    mixer: Option<PrecisionMixer>,
+    /// Interleaved
+    samples: Vec<MySample>,
+    /// Fraction of a frame that is due, but wasn't pulled yet
+    owed: f64,
+}
+
+impl OfflineAudio {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Everything rendered so far (interleaved)
+    pub fn samples(&self) -> &[MySample] {
+        &self.samples
+    }
+
+    /// Take everything rendered so far, leaving the buffer empty
+    pub fn take_samples(&mut self) -> Vec<MySample> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Pull a number of frames from the mixer, right now
+    pub fn render(&mut self, frames: usize) {
+        let Some(mixer) = &mut self.mixer else {
+            return;
+        };
+        let len = frames * self.channels as usize;
+        self.samples
+            .extend((0..len).map(|_| mixer.next().unwrap_or(0.0)));
+    }
+
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> AnyResult<()> {
+        write_wav(
+            path,
+            &self.samples,
+            self.channels,
+            self.sample_rate,
+        )
+    }
+}
+
+/// Write samples to a 32-bit float WAV file
+pub fn write_wav(
+    path: impl AsRef<Path>,
+    samples: &[f32],
+    channels: u16,
+    sample_rate: u32,
+) -> AnyResult<()> {
+    const FORMAT_IEEE_FLOAT: u16 = 3;
+
+    let path = path.as_ref();
+    let data_len = (samples.len() * 4) as u32;
+    let frames = samples.len() as u32 / channels.max(1) as u32;
+    let mut out = Vec::with_capacity(samples.len() * 4 + 58);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(50 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&18u32.to_le_bytes());
+    out.extend_from_slice(&FORMAT_IEEE_FLOAT.to_le_bytes());
+    out.extend_from_slice(&channels.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * channels as u32 * 4).to_le_bytes());
+    out.extend_from_slice(&(channels * 4).to_le_bytes());
+    out.extend_from_slice(&32u16.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    // non-PCM formats are supposed to say how many frames there are
+    out.extend_from_slice(b"fact");
+    out.extend_from_slice(&4u32.to_le_bytes());
+    out.extend_from_slice(&frames.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    std::fs::write(path, out)
+        .with_context(|| format!("Cannot write audio to {:?}", path))
+}
+
+/// Set up the mixer, to be pulled by [`pull_offline_audio`]
+///
+/// Takes the place of the one the audio plugin would set up with an output,
+/// so this works without it (and without Bevy's audio), like in tests.
+fn setup_offline_mixer(
+    mut commands: Commands,
+    mut offline: ResMut<OfflineAudio>,
+    gt: Res<GameTime>,
+) {
+    let controller = PrecisionMixerController::new(
+        offline.channels,
+        offline.sample_rate,
+        gt.hz as f32,
+    );
+    offline.mixer = Some(PrecisionMixer::new(controller.clone()));
+    commands.spawn(PrecisionMixerControl { controller });
+}
+
+/// Pull as much audio as a real device would have, since the last frame
+fn pull_offline_audio(
+    time: Res<Time<Real>>,
+    mut offline: ResMut<OfflineAudio>,
+) {
+    let frames = time.delta_seconds_f64()
+        * offline.sample_rate as f64
+        * (1.0 + offline.drift)
+        + offline.owed;
+    let whole = frames.floor();
+    offline.owed = frames - whole;
+    offline.render(whole as usize);
+}
+
+fn write_offline_audio(offline: Res<OfflineAudio>) {
+    let Some(path) = &offline.output else {
+        return;
+    };
+    match offline.write_wav(path) {
+        Ok(()) => info!("Wrote audio to {:?}", path),
+        Err(e) => error!("{:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::super::bus::AudioBus;
+    use super::super::mixer::PrecisionMixerController;
+    use super::*;
+
+    #[test]
+    fn plays_on_the_exact_sample() {
+        let controller = PrecisionMixerController::new(2, 48_000, 96.0);
+        let mut offline = OfflineAudio {
+            sample_rate: 48_000,
+            channels: 2,
+            drift: 0.0,
+            output: None,
+            mixer: Some(PrecisionMixer::new(controller.clone())),
+            samples: Vec::new(),
+            owed: 0.0,
+        };
+        controller.play_at_sample(
+            100,
+            SamplesBuffer::new(1, 48_000, vec![0.5f32; 10]),
+            AudioBus::Sfx,
+            1.0,
+            0.0,
+        );
+        offline.render(200);
+        let first = offline.samples().iter().position(|s| *s != 0.0);
+        assert_eq!(first, Some(100 * 2));
+        let heard = offline.samples().iter().filter(|s| **s != 0.0).count();
+        assert_eq!(heard, 10 * 2);
+        // and the default effects leave it as it was
+        assert_eq!(offline.samples()[200..220], [0.5; 20]);
+    }
+}
@@ -0,0 +1,428 @@
+//! Conversion of sounds to the mixer's sample rate and channels
+//!
+//! Every sound played through the mixer goes through a [`Resampler`]. It
+//! downmixes anything with more than two channels to stereo, and converts
+//! the sample rate using windowed sinc interpolation. The same interpolation
+//! lets every voice play at its own pitch, by reading the source faster or
+//! slower.
+
+use std::collections::VecDeque;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+use std::sync::OnceLock;
+
+use rodio::Source;
+
+use super::mixer::{MixerVoice, MySample};
+use crate::prelude::*;
+
+/// Half the length of the interpolation kernel, in source frames
+const HALF_TAPS: usize = 16;
+/// Entries per unit in the kernel lookup tables
+const TABLE_RES: usize = 256;
+/// How much wider the kernel may get when reading faster than the mixer
+///
+/// Past this, the kernel keeps its length and has fewer zero crossings,
+/// so that very high pitches don't cost hundreds of taps per frame.
+const MAX_STRETCH: f32 = 4.0;
+
+/// Wraps a source to match the mixer
+///
+/// The channel count and sample rate of the source are read once, at the
+/// start. Sources that change them halfway through are not supported.
+pub(super) struct Resampler<S> {
+    source: S,
+    voice: Arc<MixerVoice>,
+    in_channels: usize,
+    out_channels: u16,
+    out_rate: u32,
+    /// Source frames per mixer frame, at normal pitch
+    rate_ratio: f64,
+    /// Source frames (already downmixed) around the current position
+    frames: VecDeque<[MySample; 2]>,
+    /// Frame number of the first entry in `frames`
+    first: i64,
+    /// How many frames have been read from the source
+    read: i64,
+    source_done: bool,
+    /// Position in the source, in (fractional) frames
+    pos: f64,
+    scratch: Vec<MySample>,
+    out: [MySample; 2],
+    out_channel: u16,
+}
+
+impl<S> Resampler<S>
+where
+    S: Source<Item = MySample>,
+{
+    pub(super) fn new(
+        source: S,
+        voice: Arc<MixerVoice>,
+        out_rate: u32,
+    ) -> Self {
+        let in_channels = source.channels().max(1) as usize;
+        let rate_ratio = source.sample_rate() as f64 / out_rate.max(1) as f64;
+        // silence before the start, so the kernel has something to look back at
+        let frames = std::iter::repeat([0.0; 2]).take(HALF_TAPS).collect();
+        Self {
+            source,
+            voice,
+            in_channels,
+            out_channels: in_channels.min(2) as u16,
+            out_rate,
+            rate_ratio,
+            frames,
+            first: -(HALF_TAPS as i64),
+            read: 0,
+            source_done: false,
+            pos: 0.0,
+            scratch: vec![0.0; in_channels],
+            out: [0.0; 2],
+            out_channel: 0,
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<[MySample; 2]> {
+        if self.source_done {
+            return None;
+        }
+        for i in 0..self.in_channels {
+            match self.source.next() {
+                Some(value) => self.scratch[i] = value,
+                // a partial frame at the end is padded with silence
+                None if i > 0 => self.scratch[i] = 0.0,
+                None => {
+                    self.source_done = true;
+                    return None;
+                },
+            }
+        }
+        Some(downmix(&self.scratch))
+    }
+
+    /// Make sure `frames` goes up to frame number `until`
+    fn fill(&mut self, until: i64) {
+        while self.first + (self.frames.len() as i64) <= until {
+            let frame = self.read_frame().unwrap_or([0.0; 2]);
+            if !self.source_done {
+                self.read += 1;
+            }
+            self.frames.push_back(frame);
+        }
+    }
+
+    fn next_frame(&mut self) -> Option<[MySample; 2]> {
+        let step = self.rate_ratio * self.voice.pitch() as f64;
+        // when reading faster than the mixer, cut off what would alias
+        let cutoff = (1.0 / step).min(1.0) as f32;
+        let reach = kernel_span(cutoff).ceil() as i64;
+
+        let n = self.pos.floor() as i64;
+        self.fill(n + reach);
+        if self.source_done && n >= self.read {
+            return None;
+        }
+        while self.first < n + 1 - reach {
+            self.frames.pop_front();
+            self.first += 1;
+        }
+
+        let frac = self.pos - n as f64;
+        let frame = if frac == 0.0 && step == 1.0 {
+            // nothing to convert, which is the common case
+            self.frames[(n - self.first) as usize]
+        } else {
+            let tables = tables();
+            let mut sum = [0.0; 2];
+            let mut weight = 0.0;
+            for (i, frame) in self.frames.iter().enumerate() {
+                let t =
+                    (self.pos - (self.first + i as i64) as f64).abs() as f32;
+                let w = tables.kernel(t, cutoff);
+                sum[0] += frame[0] * w;
+                sum[1] += frame[1] * w;
+                weight += w;
+            }
+            // normalized, so that the gain is the same at any pitch
+            if weight > 0.0 {
+                [sum[0] / weight, sum[1] / weight]
+            } else {
+                [0.0; 2]
+            }
+        };
+        self.pos += step;
+        Some(frame)
+    }
+}
+
+impl<S> Iterator for Resampler<S>
+where
+    S: Source<Item = MySample>,
+{
+    type Item = MySample;
+
+    fn next(&mut self) -> Option<MySample> {
+        if self.out_channel == 0 {
+            self.out = self.next_frame()?;
+        }
+        let value = self.out[self.out_channel as usize];
+        self.out_channel = (self.out_channel + 1) % self.out_channels;
+        Some(value)
+    }
+}
+
+impl<S> Source for Resampler<S>
+where
+    S: Source<Item = MySample>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.out_channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.out_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Mix one frame down to (at most) stereo
+///
+/// Assumes the usual WAV/FLAC channel order: left, right, then center
+/// (except for quad), LFE (with 6 or more channels) and the surrounds.
+/// The LFE is dropped and everything else is folded into left and right.
+fn downmix(input: &[MySample]) -> [MySample; 2] {
+    match input.len() {
+        1 => [input[0], input[0]],
+        2 => [input[0], input[1]],
+        n => {
+            let mut out = [input[0], input[1]];
+            let mut rest = 2;
+            if n != 4 {
+                out[0] += input[2] * FRAC_1_SQRT_2;
+                out[1] += input[2] * FRAC_1_SQRT_2;
+                rest += 1;
+            }
+            if n >= 6 {
+                rest += 1;
+            }
+            for (i, value) in input[rest..].iter().enumerate() {
+                out[i % 2] += value * FRAC_1_SQRT_2;
+            }
+            out
+        },
+    }
+}
+
+struct KernelTables {
+    /// `sin(pi x) / (pi x)`, for `x` from 0 to `HALF_TAPS`
+    sinc: Vec<f32>,
+    /// Blackman window, from the middle (0) to the edge (1)
+    window: Vec<f32>,
+}
+
+impl KernelTables {
+    fn new() -> Self {
+        let sinc = (0..=HALF_TAPS * TABLE_RES + 1)
+            .map(|i| {
+                let x = PI * i as f32 / TABLE_RES as f32;
+                if i == 0 {
+                    1.0
+                } else {
+                    x.sin() / x
+                }
+            })
+            .collect();
+        let window = (0..=TABLE_RES + 1)
+            .map(|i| {
+                let x = PI * (i as f32 / TABLE_RES as f32).min(1.0);
+                0.42 + 0.5 * x.cos() + 0.08 * (2.0 * x).cos()
+            })
+            .collect();
+        Self { sinc, window }
+    }
+
+    /// The (unnormalized) weight of a frame `t` frames away
+    fn kernel(&self, t: f32, cutoff: f32) -> f32 {
+        let span = kernel_span(cutoff);
+        if t >= span {
+            return 0.0;
+        }
+        lookup(&self.window, t / span) * lookup(&self.sinc, t * cutoff)
+    }
+}
+
+/// Half the length of the kernel, in source frames, for a given cutoff
+///
+/// A lower cutoff stretches the sinc, so the kernel gets wider to keep
+/// the same number of zero crossings (and the same filter quality).
+fn kernel_span(cutoff: f32) -> f32 {
+    HALF_TAPS as f32 / cutoff.max(1.0 / MAX_STRETCH)
+}
+
+fn lookup(table: &[f32], x: f32) -> f32 {
+    let x = x * TABLE_RES as f32;
+    let i = x as usize;
+    if i + 1 >= table.len() {
+        return 0.0;
+    }
+    let f = x - i as f32;
+    table[i] + (table[i + 1] - table[i]) * f
+}
+
+fn tables() -> &'static KernelTables {
+    static TABLES: OnceLock<KernelTables> = OnceLock::new();
+    TABLES.get_or_init(KernelTables::new)
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::TAU;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    fn sine(freq: f32, rate: u32, len: usize) -> SamplesBuffer<MySample> {
+        let samples = (0..len)
+            .map(|i| (TAU * freq * i as f32 / rate as f32).sin())
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(1, rate, samples)
+    }
+
+    /// Left channel of everything the resampler puts out
+    fn resample(
+        source: SamplesBuffer<MySample>,
+        out_rate: u32,
+        pitch: f32,
+    ) -> Vec<MySample> {
+        let voice = MixerVoice::new(1.0, 0.0);
+        voice.set_pitch(pitch);
+        let resampler = Resampler::new(source, voice, out_rate);
+        let channels = resampler.channels() as usize;
+        resampler.step_by(channels).collect()
+    }
+
+    /// Frequency from the upward zero crossings, away from the ends
+    fn frequency(samples: &[MySample], rate: u32) -> f32 {
+        let middle = &samples[samples.len() / 4..samples.len() * 3 / 4];
+        let crossings = middle
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        crossings as f32 * rate as f32 / middle.len() as f32
+    }
+
+    fn rms(samples: &[MySample]) -> f32 {
+        let middle = &samples[samples.len() / 4..samples.len() * 3 / 4];
+        let sum: f32 = middle.iter().map(|x| x * x).sum();
+        (sum / middle.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn downmix_mono_and_stereo() {
+        assert_eq!(downmix(&[0.5]), [0.5, 0.5]);
+        assert_eq!(downmix(&[0.25, -0.5]), [0.25, -0.5]);
+    }
+
+    #[test]
+    fn downmix_quad() {
+        // L, R, Ls, Rs: no center
+        let out = downmix(&[0.1, 0.2, 0.3, 0.4]);
+        assert!((out[0] - (0.1 + 0.3 * FRAC_1_SQRT_2)).abs() < 1e-6);
+        assert!((out[1] - (0.2 + 0.4 * FRAC_1_SQRT_2)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn downmix_5_1() {
+        // L, R, C, LFE, Ls, Rs
+        let out = downmix(&[0.1, 0.2, 0.3, 1.0, 0.4, 0.5]);
+        let l = 0.1 + (0.3 + 0.4) * FRAC_1_SQRT_2;
+        let r = 0.2 + (0.3 + 0.5) * FRAC_1_SQRT_2;
+        assert!((out[0] - l).abs() < 1e-6, "{out:?}");
+        assert!((out[1] - r).abs() < 1e-6, "{out:?}");
+        // the LFE is dropped
+        let lfe = downmix(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(lfe, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn passthrough_at_same_rate() {
+        let expected: Vec<MySample> = sine(1000.0, 48_000, 1000).collect();
+        let out = resample(sine(1000.0, 48_000, 1000), 48_000, 1.0);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn convert_44100_to_48000() {
+        let out = resample(
+            sine(1000.0, 44_100, 44_100),
+            48_000,
+            1.0,
+        );
+        // the same length of time, at the new rate
+        assert!(
+            (out.len() as i64 - 48_000).abs() <= 1,
+            "{}",
+            out.len()
+        );
+        let freq = frequency(&out, 48_000);
+        assert!((freq - 1000.0).abs() < 5.0, "{freq} Hz");
+        let rms = rms(&out);
+        assert!(
+            (rms - FRAC_1_SQRT_2).abs() < 0.01,
+            "rms {rms}"
+        );
+    }
+
+    #[test]
+    fn pitch_up_doubles_frequency() {
+        let out = resample(
+            sine(1000.0, 48_000, 48_000),
+            48_000,
+            2.0,
+        );
+        assert!(
+            (out.len() as i64 - 24_000).abs() <= 1,
+            "{}",
+            out.len()
+        );
+        let freq = frequency(&out, 48_000);
+        assert!(
+            (freq - 2000.0).abs() < 10.0,
+            "{freq} Hz"
+        );
+    }
+
+    #[test]
+    fn pitch_up_cuts_what_would_alias() {
+        // at 3x, anything above 8 kHz in the source would fold back,
+        // and a short kernel lets a wide band around that through
+        let kept = resample(
+            sine(7000.0, 48_000, 48_000),
+            48_000,
+            3.0,
+        );
+        let cut = resample(
+            sine(9000.0, 48_000, 48_000),
+            48_000,
+            3.0,
+        );
+        assert!(
+            rms(&kept) > 0.6,
+            "kept rms {}",
+            rms(&kept)
+        );
+        assert!(
+            rms(&cut) < 0.02,
+            "cut rms {}",
+            rms(&cut)
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Positional audio
+//!
+//! Sounds played with `positional = true` get their volume and pan from
+//! where the playing entity is, relative to the [`AudioListener`].
+//! Sounds that are still playing keep following their entity every tick.
+
+use bevy::ecs::system::SystemParam;
+
+use super::mixer::MixerVoice;
+use crate::physics::PhysicsSet;
+use crate::prelude::*;
+
+pub struct SpatialAudioPlugin;
+
+impl Plugin for SpatialAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialAudioConfig>();
+        app.init_resource::<PositionalVoices>();
+        // transforms are only propagated at the end of the frame, so this
+        // sees the same positions as the physics world does
+        app.add_systems(
+            GameTickUpdate,
+            update_positional_voices.after(PhysicsSet),
+        );
+    }
+}
+
+/// Where positional sounds are heard from
+///
+/// There should only be one. Without it, positional sounds play
+/// as if they were not positional.
+#[derive(Component, Default, Debug)]
+pub struct AudioListener;
+
+/// How distance affects positional sounds
+#[derive(Resource, Debug, Clone)]
+pub struct SpatialAudioConfig {
+    /// Closer than this, sounds play at full volume
+    pub min_distance: f32,
+    /// Further than this, sounds are silent
+    pub max_distance: f32,
+    pub rolloff: Rolloff,
+    /// Horizontal offset at which sounds are panned as far as they go
+    pub pan_distance: f32,
+    /// How far sounds can be panned (`1.0` is entirely to one side)
+    pub max_pan: f32,
+}
+
+impl Default for SpatialAudioConfig {
+    fn default() -> Self {
+        Self {
+            min_distance: 32.0,
+            max_distance: 400.0,
+            rolloff: Rolloff::Inverse,
+            pan_distance: 240.0,
+            max_pan: 0.8,
+        }
+    }
+}
+
+/// The shape of the volume curve between `min_distance` and `max_distance`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rolloff {
+    /// Fades out evenly
+    Linear,
+    /// Drops quickly close up, then tails off, like real sound
+    Inverse,
+    /// Stays loud for longer, then drops quickly near the end
+    Quadratic,
+}
+
+impl SpatialAudioConfig {
+    /// Volume multiplier for a sound this far away
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        if distance <= self.min_distance {
+            return 1.0;
+        }
+        if distance >= self.max_distance {
+            return 0.0;
+        }
+        let range = (self.max_distance - self.min_distance).max(f32::EPSILON);
+        let t = ((distance - self.min_distance) / range).clamp(0.0, 1.0);
+        match self.rolloff {
+            Rolloff::Linear => 1.0 - t,
+            // 1/d, shifted and scaled to go from 1 to 0 over the range
+            Rolloff::Inverse => {
+                let d = distance.clamp(self.min_distance, self.max_distance);
+                let min = self.min_distance.max(1.0);
+                let at_max = min / self.max_distance.max(min);
+                ((min / d.max(min) - at_max) / (1.0 - at_max)).max(0.0)
+            },
+            Rolloff::Quadratic => 1.0 - t * t,
+        }
+    }
+
+    /// Pan for a sound this far to the side (negative is left)
+    pub fn pan(&self, offset_x: f32) -> f32 {
+        (offset_x / self.pan_distance.max(f32::EPSILON))
+            .clamp(-self.max_pan, self.max_pan)
+    }
+
+    /// Volume and pan for a sound at `offset` from the listener
+    pub fn volume_pan(&self, offset: Vec2, volume: f32) -> (f32, f32) {
+        (
+            volume * self.attenuation(offset.length()),
+            self.pan(offset.x),
+        )
+    }
+}
+
+/// Sounds that are still following the position of an entity
+#[derive(Resource, Default)]
+pub struct PositionalVoices {
+    voices: Vec<PositionalVoice>,
+}
+
+struct PositionalVoice {
+    voice: Arc<MixerVoice>,
+    source: Entity,
+    /// The volume it was played at, before attenuation
+    volume: f32,
+    /// Where it was last heard from, in case the entity is despawned
+    position: Vec2,
+}
+
+/// Everything needed to play sounds positionally
+#[derive(SystemParam)]
+pub struct SpatialAudio<'w, 's> {
+    config: Res<'w, SpatialAudioConfig>,
+    voices: ResMut<'w, PositionalVoices>,
+    q_listener: Query<'w, 's, &'static GlobalTransform, With<AudioListener>>,
+    q_transform: Query<'w, 's, &'static GlobalTransform>,
+}
+
+impl SpatialAudio<'_, '_> {
+    fn listener(&self) -> Option<Vec2> {
+        self.q_listener
+            .get_single()
+            .ok()
+            .map(|xf| xf.translation().truncate())
+    }
+
+    /// The volume and pan to play a sound from `source` at
+    ///
+    /// `None` if there is no listener, or `source` has no position.
+    pub fn volume_pan(
+        &self,
+        source: Entity,
+        volume: f32,
+    ) -> Option<(f32, f32)> {
+        let listener = self.listener()?;
+        let position = self.q_transform.get(source).ok()?.translation();
+        Some(
+            self.config
+                .volume_pan(position.truncate() - listener, volume),
+        )
+    }
+
+    /// Keep updating a playing sound as `source` moves
+    pub fn follow(
+        &mut self,
+        voice: Arc<MixerVoice>,
+        source: Entity,
+        volume: f32,
+    ) {
+        let Ok(xf) = self.q_transform.get(source) else {
+            return;
+        };
+        let position = xf.translation().truncate();
+        self.voices.voices.push(PositionalVoice {
+            voice,
+            source,
+            volume,
+            position,
+        });
+    }
+}
+
+fn update_positional_voices(
+    mut voices: ResMut<PositionalVoices>,
+    config: Res<SpatialAudioConfig>,
+    q_listener: Query<&GlobalTransform, With<AudioListener>>,
+    q_transform: Query<&GlobalTransform>,
+) {
+    voices.voices.retain(|v| !v.voice.is_done());
+    let Ok(listener) = q_listener.get_single() else {
+        return;
+    };
+    let listener = listener.translation().truncate();
+    for v in voices.voices.iter_mut() {
+        if let Ok(xf) = q_transform.get(v.source) {
+            v.position = xf.translation().truncate();
+        }
+        let (volume, pan) = config.volume_pan(v.position - listener, v.volume);
+        v.voice.set_volume(volume);
+        v.voice.set_pan(pan);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(rolloff: Rolloff) -> SpatialAudioConfig {
+        SpatialAudioConfig {
+            min_distance: 10.0,
+            max_distance: 110.0,
+            rolloff,
+            pan_distance: 100.0,
+            max_pan: 0.8,
+        }
+    }
+
+    #[test]
+    fn attenuation_range() {
+        for rolloff in [Rolloff::Linear, Rolloff::Inverse, Rolloff::Quadratic] {
+            let config = config(rolloff);
+            for d in [0.0, 5.0, 10.0] {
+                assert_eq!(
+                    config.attenuation(d),
+                    1.0,
+                    "{rolloff:?} at {d}"
+                );
+            }
+            for d in [110.0, 200.0, f32::MAX] {
+                assert_eq!(
+                    config.attenuation(d),
+                    0.0,
+                    "{rolloff:?} at {d}"
+                );
+            }
+            // gets quieter all the way
+            let mut last = 1.0;
+            for d in (11..110).map(|d| d as f32) {
+                let a = config.attenuation(d);
+                assert!(
+                    a < last,
+                    "{rolloff:?} at {d}: {a} >= {last}"
+                );
+                last = a;
+            }
+        }
+    }
+
+    #[test]
+    fn attenuation_curves() {
+        // halfway through the range
+        let linear = config(Rolloff::Linear).attenuation(60.0);
+        let inverse = config(Rolloff::Inverse).attenuation(60.0);
+        let quadratic = config(Rolloff::Quadratic).attenuation(60.0);
+        assert!((linear - 0.5).abs() < 1e-6);
+        assert!((quadratic - 0.75).abs() < 1e-6);
+        // (10/60 - 10/110) / (1 - 10/110)
+        assert!(
+            (inverse - 1.0 / 12.0).abs() < 1e-6,
+            "{inverse}"
+        );
+    }
+
+    #[test]
+    fn attenuation_degenerate_range() {
+        let config = SpatialAudioConfig {
+            min_distance: 50.0,
+            max_distance: 50.0,
+            ..config(Rolloff::Inverse)
+        };
+        assert_eq!(config.attenuation(10.0), 1.0);
+        assert_eq!(config.attenuation(100.0), 0.0);
+        assert!(config.attenuation(50.0).is_finite());
+    }
+
+    #[test]
+    fn pan() {
+        let config = config(Rolloff::Linear);
+        assert_eq!(config.pan(0.0), 0.0);
+        assert_eq!(config.pan(50.0), 0.5);
+        assert_eq!(config.pan(-50.0), -0.5);
+        assert_eq!(config.pan(1000.0), 0.8);
+        assert_eq!(config.pan(-1000.0), -0.8);
+    }
+
+    #[test]
+    fn volume_pan() {
+        let config = config(Rolloff::Linear);
+        let (volume, pan) = config.volume_pan(Vec2::new(-30.0, 40.0), 0.5);
+        // 50 away is 40% of the way through the range
+        assert!((volume - 0.3).abs() < 1e-6, "{volume}");
+        assert!((pan - -0.3).abs() < 1e-6, "{pan}");
+    }
+}
@@ -356,9 +356,11 @@ pub fn solve_ballistic_arc_moving(
     let num_times = solve_quartic(
         c0, c1, c2, c3, c4, &mut t1, &mut t2, &mut t3, &mut t4,
     );
+    // the other slots may hold leftovers from solving the resolvent cubic
     let mut times = [t1, t2, t3, t4];
+    let times = &mut times[..num_times.max(0) as usize];
 
-    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    times.sort_by(f64::total_cmp);
 
     let mut solutions = [Vec2::ZERO; 2];
     let mut num_solutions = 0;
@@ -387,3 +389,117 @@ pub fn solve_ballistic_arc_moving(
 
     (s0, s1, num_solutions)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Where a projectile launched from `start` with `vel` is after `t` seconds
+    fn position_at(start: Vec2, vel: Vec2, gravity: f32, t: f32) -> Vec2 {
+        start + vel * t - Vec2::new(0.0, 0.5 * gravity * t * t)
+    }
+
+    /// Closest the projectile gets to `target`, sampling its flight
+    fn closest_approach(
+        start: Vec2,
+        vel: Vec2,
+        gravity: f32,
+        target: Vec2,
+    ) -> f32 {
+        (0..10_000)
+            .map(|i| position_at(start, vel, gravity, i as f32 * 0.001))
+            .map(|pos| pos.distance(target))
+            .fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn stationary_arcs_hit() {
+        let start = Vec2::new(10.0, 20.0);
+        let gravity = 300.0;
+        for target in [
+            Vec2::new(110.0, 20.0),
+            Vec2::new(-60.0, 50.0),
+            Vec2::new(80.0, -40.0),
+        ] {
+            let (low, high, n) =
+                solve_ballistic_arc(start, 200.0, target, gravity);
+            assert_eq!(n, 2, "{target}");
+            assert!(high.y > low.y);
+            for vel in [low, high] {
+                assert!((vel.length() - 200.0).abs() < 0.01);
+                let miss = closest_approach(start, vel, gravity, target);
+                assert!(
+                    miss < 0.5,
+                    "{target} {vel} missed by {miss}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn stationary_out_of_range() {
+        let (_, _, n) = solve_ballistic_arc(
+            Vec2::ZERO,
+            50.0,
+            Vec2::new(1000.0, 0.0),
+            300.0,
+        );
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    fn moving_arcs_intercept() {
+        let start = Vec2::new(0.0, 0.0);
+        let gravity = 300.0;
+        let speed = 250.0;
+        for (target, target_vel) in [
+            (
+                Vec2::new(100.0, 0.0),
+                Vec2::new(40.0, 0.0),
+            ),
+            (
+                Vec2::new(100.0, 30.0),
+                Vec2::new(-60.0, 0.0),
+            ),
+            (
+                Vec2::new(-120.0, -20.0),
+                Vec2::new(30.0, 10.0),
+            ),
+        ] {
+            let (s0, s1, n) = solve_ballistic_arc_moving(
+                start, speed, target, target_vel, gravity,
+            );
+            assert!(n >= 1, "{target} {target_vel}");
+            for vel in [s0, s1].into_iter().take(n) {
+                assert!(
+                    (vel.length() - speed).abs() < 0.5,
+                    "{vel} has the wrong speed"
+                );
+                // the horizontal motion is linear, which gives the time
+                let t = (target.x - start.x) / (vel.x - target_vel.x);
+                assert!(t > 0.0);
+                let hit = position_at(start, vel, gravity, t);
+                let expected = target + target_vel * t;
+                assert!(
+                    hit.distance(expected) < 0.5,
+                    "{vel} at {t}s: {hit} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn moving_matches_stationary() {
+        let start = Vec2::new(0.0, 10.0);
+        let target = Vec2::new(90.0, 40.0);
+        let (low, _, n) = solve_ballistic_arc(start, 200.0, target, 300.0);
+        let (first, _, n_moving) =
+            solve_ballistic_arc_moving(start, 200.0, target, Vec2::ZERO, 300.0);
+        assert_eq!(n, n_moving as i32);
+        // the sooner intercept is the flatter arc
+        assert!(
+            first.distance(low) < 0.5,
+            "{first} != {low}"
+        );
+    }
+}
@@ -287,6 +287,24 @@ impl Quant {
     pub fn check(self, value: i64) -> bool {
         (value - self.offset) % self.n as i64 == 0
     }
+
+    /// Stretch the period by `ratio`, starting from the value `at`
+    ///
+    /// The next match after `at` moves to the same (scaled) distance from
+    /// `at` as before, and the ones after it follow the new period.
+    pub fn rescale(self, at: i64, ratio: f64) -> Quant {
+        if self.n == 0 {
+            return self;
+        }
+        let n = self.n as i64;
+        let until_next = (self.offset - at).rem_euclid(n);
+        let next = at + (until_next as f64 * ratio).round() as i64;
+        let n = ((self.n as f64 * ratio).round() as u64).max(1);
+        Quant {
+            n,
+            offset: next.rem_euclid(n as i64),
+        }
+    }
 }
 
 impl fmt::Display for Quant {
@@ -365,6 +383,25 @@ mod test {
         assert!(x.is_err());
     }
     #[test]
+    fn rescale_quant() {
+        let x = Quant { n: 4, offset: 1 };
+        // matches at 101, 105, ...: the next one is 3 away from 98
+        let y = x.rescale(98, 2.0);
+        assert_eq!(y.n, 8);
+        assert!(y.check(104));
+        assert!(y.check(112));
+        assert!(!y.check(101));
+        // on a match, it stays a match
+        let y = x.rescale(101, 0.5);
+        assert_eq!(y.n, 2);
+        assert!(y.check(101));
+        assert!(y.check(103));
+        // never down to zero
+        let y = Quant { n: 1, offset: 0 }.rescale(7, 0.25);
+        assert_eq!(y.n, 1);
+        assert!(y.check(8));
+    }
+    #[test]
     fn display_timespec() {
         let x = TimeSpec {
             hours: 171,
@@ -26,6 +26,8 @@ use leafwing_input_manager::Actionlike;
 use crate::time::GameTickPost;
 use crate::GameTickSet;
 
+pub mod replay;
+
 /// A [`Plugin`] that collects [`ButtonInput`](bevy::input::ButtonInput) from disparate sources,
 /// producing an [`ActionState`] that can be conveniently checked
 ///
@@ -102,11 +104,17 @@ impl<A: Actionlike + TypePath> Plugin for InputManagerPlugin<A> {
             (
                 swap_to_fixed_update::<A>,
                 // we want to update the ActionState only once, even if the GameTickUpdate schedule runs multiple times
-                update_action_state::<A>,
+                update_action_state::<A>.in_set(InputManagerSystem::Update),
             )
                 .chain()
                 .in_set(GameTickSet::Pre),
         );
+        app.configure_sets(
+            Update,
+            InputManagerSystem::ManualControl
+                .after(InputManagerSystem::Update)
+                .in_set(GameTickSet::Pre),
+        );
 
         app.add_systems(
             GameTickPost,
@@ -119,6 +127,10 @@ impl<A: Actionlike + TypePath> Plugin for InputManagerPlugin<A> {
                 .in_set(InputManagerSystem::Tick)
                 .before(InputManagerSystem::Update),
         );
+        app.configure_sets(
+            GameTickPost,
+            InputManagerSystem::ManualControl.after(InputManagerSystem::Tick),
+        );
         app.add_systems(
             Update,
             swap_to_update::<A>.in_set(GameTickSet::Post),
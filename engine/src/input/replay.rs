@@ -0,0 +1,440 @@
+//! Input recording and deterministic replay
+//!
+//! The [`ActionState`] is captured once per game tick (that is what gameplay
+//! code sees), together with the RNG seed and the starting level. Only ticks
+//! where something changed are stored.
+//!
+//! During replay, the recorded states are applied in
+//! [`InputManagerSystem::ManualControl`], overriding any real input devices.
+
+use std::marker::PhantomData;
+use std::path::Path;
+
+use leafwing_input_manager::action_state::ActionState;
+use leafwing_input_manager::Actionlike;
+use strum::IntoEnumIterator;
+
+use super::InputManagerSystem;
+use crate::prelude::*;
+use crate::time::GameTickPost;
+
+/// Bump this whenever the format of [`InputRecording`] changes
+pub const INPUT_RECORDING_VERSION: u32 = 1;
+
+/// Adds recording/replay support for an `Actionlike` type
+///
+/// Nothing happens unless an [`InputRecorder`] or [`InputReplay`] resource
+/// is inserted.
+pub struct InputReplayPlugin<A: Actionlike> {
+    _phantom: PhantomData<A>,
+}
+
+// Deriving default induces an undesired bound on the generic
+impl<A: Actionlike> Default for InputReplayPlugin<A> {
+    fn default() -> Self {
+        Self {
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<A> Plugin for InputReplayPlugin<A>
+where
+    A: Actionlike + IntoEnumIterator + Serialize + DeserializeOwned,
+{
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            replay_input_first_tick::<A>
+                .run_if(resource_exists::<InputReplay<A>>)
+                .in_set(InputManagerSystem::ManualControl),
+        );
+        app.add_systems(
+            Update,
+            finish_replay::<A>
+                .run_if(resource_exists::<InputReplay<A>>)
+                .in_set(GameTickSet::Post),
+        );
+        app.add_systems(
+            GameTickPost,
+            (
+                record_input::<A>
+                    .run_if(resource_exists::<InputRecorder<A>>)
+                    .before(InputManagerSystem::Tick),
+                replay_input_next_tick::<A>
+                    .run_if(resource_exists::<InputReplay<A>>)
+                    .in_set(InputManagerSystem::ManualControl),
+            ),
+        );
+    }
+}
+
+/// The contents of an input recording file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "A: DeserializeOwned"))]
+pub struct InputRecording<A> {
+    /// Format version, must be [`INPUT_RECORDING_VERSION`]
+    pub version: u32,
+    /// The `GameRng` seed at the start of the recording
+    pub seed: u64,
+    /// Asset key of the level the recording starts in
+    pub level: String,
+    /// Game tick rate the recording was made at
+    pub hz: f64,
+    /// How many ticks the recording covers
+    pub len_ticks: u64,
+    /// The ticks when the input changed, in order
+    pub frames: Vec<InputFrame<A>>,
+}
+
+/// The input state from a given tick onwards
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "A: DeserializeOwned"))]
+pub struct InputFrame<A> {
+    /// Tick number, relative to the start of the recording
+    pub tick: u64,
+    /// All pressed actions, with their axis value
+    pub actions: Vec<(A, f32)>,
+}
+
+#[derive(Deserialize)]
+struct InputRecordingHeader {
+    version: u32,
+}
+
+impl<A: Actionlike + Serialize + DeserializeOwned> InputRecording<A> {
+    pub fn new(seed: u64, level: impl Into<String>, hz: f64) -> Self {
+        Self {
+            version: INPUT_RECORDING_VERSION,
+            seed,
+            level: level.into(),
+            hz,
+            len_ticks: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Read a recording from a file
+    pub fn load(path: impl AsRef<Path>) -> AnyResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| {
+            format!("Cannot read input recording {:?}", path)
+        })?;
+        // check the version first, so that old files give a helpful error
+        let header: InputRecordingHeader = toml::from_str(&text)
+            .with_context(|| format!("Invalid input recording {:?}", path))?;
+        ensure!(
+            header.version == INPUT_RECORDING_VERSION,
+            "Input recording {:?} has version {}, expected {}",
+            path,
+            header.version,
+            INPUT_RECORDING_VERSION
+        );
+        toml::from_str(&text)
+            .with_context(|| format!("Invalid input recording {:?}", path))
+    }
+
+    /// Write the recording to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> AnyResult<()> {
+        let path = path.as_ref();
+        let text = toml::to_string(self)?;
+        std::fs::write(path, text).with_context(|| {
+            format!(
+                "Cannot write input recording {:?}",
+                path
+            )
+        })
+    }
+
+    /// Get the input state at a given tick (relative to the start)
+    pub fn actions_at(&self, tick: u64) -> &[(A, f32)] {
+        let i = self.frames.partition_point(|f| f.tick <= tick);
+        if i == 0 {
+            &[]
+        } else {
+            &self.frames[i - 1].actions
+        }
+    }
+
+    /// Add the input state for a tick; only stored if it changed
+    pub fn push(&mut self, tick: u64, actions: Vec<(A, f32)>) {
+        self.len_ticks = self.len_ticks.max(tick + 1);
+        if self.actions_at(tick) != actions.as_slice() {
+            self.frames.push(InputFrame { tick, actions });
+        }
+    }
+}
+
+/// Insert this resource to start recording
+#[derive(Resource)]
+pub struct InputRecorder<A: Actionlike> {
+    recording: InputRecording<A>,
+    start_tick: u64,
+}
+
+impl<A: Actionlike + Serialize + DeserializeOwned> InputRecorder<A> {
+    /// `start_tick` is the first `GameTime` tick to be recorded
+    pub fn new(recording: InputRecording<A>, start_tick: u64) -> Self {
+        Self {
+            recording,
+            start_tick,
+        }
+    }
+
+    pub fn recording(&self) -> &InputRecording<A> {
+        &self.recording
+    }
+
+    /// Stop recording and get the result
+    pub fn finish(self) -> InputRecording<A> {
+        self.recording
+    }
+}
+
+/// Insert this resource to start a replay
+///
+/// It is removed automatically once the end of the recording is reached.
+#[derive(Resource)]
+pub struct InputReplay<A: Actionlike> {
+    recording: InputRecording<A>,
+    start_tick: u64,
+}
+
+impl<A: Actionlike + Serialize + DeserializeOwned> InputReplay<A> {
+    /// `start_tick` is the `GameTime` tick to play the first recorded tick at
+    pub fn new(recording: InputRecording<A>, start_tick: u64) -> Self {
+        Self {
+            recording,
+            start_tick,
+        }
+    }
+
+    pub fn recording(&self) -> &InputRecording<A> {
+        &self.recording
+    }
+
+    /// Has the given `GameTime` tick gone past the end of the recording?
+    pub fn is_finished(&self, tick: u64) -> bool {
+        tick >= self.start_tick + self.recording.len_ticks
+    }
+}
+
+fn record_input<A>(
+    gt: Res<GameTime>,
+    mut recorder: ResMut<InputRecorder<A>>,
+    q: Query<&ActionState<A>>,
+) where
+    A: Actionlike + IntoEnumIterator + Serialize + DeserializeOwned,
+{
+    let Some(action_state) = q.iter().next() else {
+        return;
+    };
+    let Some(tick) = gt.tick().checked_sub(recorder.start_tick) else {
+        return;
+    };
+    let actions = A::iter()
+        .filter(|action| action_state.pressed(action))
+        .map(|action| {
+            let value = action_state.value(&action);
+            (action, value)
+        })
+        .collect();
+    recorder.recording.push(tick, actions);
+}
+
+/// The first tick of every `GameTickUpdate` batch
+fn replay_input_first_tick<A>(
+    gt: Res<GameTime>,
+    replay: Res<InputReplay<A>>,
+    mut q: Query<&mut ActionState<A>>,
+) where
+    A: Actionlike + IntoEnumIterator + Serialize + DeserializeOwned,
+{
+    apply_replay(&replay, gt.tick(), &mut q);
+}
+
+/// Runs between ticks, after the current one, so prepare the next one
+fn replay_input_next_tick<A>(
+    gt: Res<GameTime>,
+    replay: Res<InputReplay<A>>,
+    mut q: Query<&mut ActionState<A>>,
+) where
+    A: Actionlike + IntoEnumIterator + Serialize + DeserializeOwned,
+{
+    apply_replay(&replay, gt.tick() + 1, &mut q);
+}
+
+fn apply_replay<A>(
+    replay: &InputReplay<A>,
+    tick: u64,
+    q: &mut Query<&mut ActionState<A>>,
+) where
+    A: Actionlike + IntoEnumIterator + Serialize + DeserializeOwned,
+{
+    let Some(tick) = tick.checked_sub(replay.start_tick) else {
+        return;
+    };
+    let actions = replay.recording.actions_at(tick);
+    for mut action_state in q.iter_mut() {
+        for action in A::iter() {
+            match actions.iter().find(|(a, _)| *a == action) {
+                Some((_, value)) => {
+                    action_state.press(&action);
+                    if let Some(data) = action_state.action_data_mut(&action) {
+                        data.value = *value;
+                    }
+                },
+                None => action_state.release(&action),
+            }
+        }
+    }
+}
+
+fn finish_replay<A>(
+    gt: Res<GameTime>,
+    replay: Res<InputReplay<A>>,
+    mut commands: Commands,
+) where
+    A: Actionlike + IntoEnumIterator + Serialize + DeserializeOwned,
+{
+    if replay.is_finished(gt.tick()) {
+        info!("Input replay finished.");
+        commands.remove_resource::<InputReplay<A>>();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use strum_macros::EnumIter;
+
+    use super::*;
+    use crate::time::{run_gametickupdate_schedule, GameTickUpdate};
+
+    #[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect)]
+    #[derive(Serialize, Deserialize, EnumIter)]
+    enum TestAction {
+        Left,
+        Right,
+        Jump,
+    }
+
+    /// The pressed actions, as seen by gameplay code on every tick
+    #[derive(Resource, Default)]
+    struct Seen(Vec<Vec<TestAction>>);
+
+    fn see_input(q: Query<&ActionState<TestAction>>, mut seen: ResMut<Seen>) {
+        let action_state = q.single();
+        let pressed = TestAction::iter()
+            .filter(|action| action_state.pressed(action))
+            .collect();
+        seen.0.push(pressed);
+    }
+
+    /// What the "player" holds down on a given tick
+    fn script(tick: u64) -> Vec<TestAction> {
+        let mut actions = Vec::new();
+        if (3..12).contains(&tick) {
+            actions.push(TestAction::Right);
+        }
+        if tick % 5 == 0 {
+            actions.push(TestAction::Jump);
+        }
+        if tick >= 15 {
+            actions.push(TestAction::Left);
+        }
+        actions
+    }
+
+    /// Runs at 1 Hz, so that every second is exactly one tick
+    fn app() -> App {
+        let mut app = App::new();
+        app.insert_resource(GameTime::new(1.0));
+        app.init_resource::<Seen>();
+        app.add_plugins(InputReplayPlugin::<TestAction>::default());
+        app.add_systems(
+            Update,
+            run_gametickupdate_schedule
+                .after(InputManagerSystem::ManualControl),
+        );
+        app.configure_sets(
+            Update,
+            GameTickSet::Post.after(run_gametickupdate_schedule),
+        );
+        app.add_systems(GameTickUpdate, see_input);
+        app.world.spawn(ActionState::<TestAction>::default());
+        app
+    }
+
+    fn step(app: &mut App, time: &mut Time) {
+        time.advance_by(Duration::from_secs(1));
+        app.world.resource_mut::<GameTime>().update(time);
+        app.update();
+    }
+
+    #[test]
+    fn record_then_replay() {
+        const TICKS: u64 = 20;
+        let mut time = Time::<()>::default();
+
+        let mut app = app();
+        let recording = InputRecording::new(42, "level.01", 1.0);
+        app.insert_resource(InputRecorder::new(recording, 0));
+        for tick in 0..TICKS {
+            let actions = script(tick);
+            let mut q = app.world.query::<&mut ActionState<TestAction>>();
+            let mut action_state = q.single_mut(&mut app.world);
+            for action in TestAction::iter() {
+                if actions.contains(&action) {
+                    action_state.press(&action);
+                } else {
+                    action_state.release(&action);
+                }
+            }
+            step(&mut app, &mut time);
+        }
+        let recorded = app.world.resource::<Seen>().0.clone();
+        let expected: Vec<_> = (0..TICKS).map(script).collect();
+        assert_eq!(recorded, expected);
+        let recording = app
+            .world
+            .remove_resource::<InputRecorder<TestAction>>()
+            .unwrap()
+            .finish();
+        assert_eq!(recording.len_ticks, TICKS);
+
+        let path = std::env::temp_dir().join(format!(
+            "theseeker-replay-test-{}.toml",
+            std::process::id()
+        ));
+        recording.save(&path).unwrap();
+        let loaded = InputRecording::<TestAction>::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.level, "level.01");
+        assert_eq!(loaded.frames, recording.frames);
+
+        // no real input this time, everything comes from the recording
+        let mut time = Time::<()>::default();
+        let mut app = app();
+        app.insert_resource(InputReplay::new(loaded, 0));
+        for _ in 0..TICKS {
+            step(&mut app, &mut time);
+        }
+        assert_eq!(app.world.resource::<Seen>().0, recorded);
+        assert!(!app.world.contains_resource::<InputReplay<TestAction>>());
+    }
+
+    #[test]
+    fn reject_other_versions() {
+        let mut recording = InputRecording::<TestAction>::new(0, "", 96.0);
+        recording.version = INPUT_RECORDING_VERSION - 1;
+        let path = std::env::temp_dir().join(format!(
+            "theseeker-replay-version-{}.toml",
+            std::process::id()
+        ));
+        recording.save(&path).unwrap();
+        let result = InputRecording::<TestAction>::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
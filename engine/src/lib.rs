@@ -23,6 +23,7 @@ pub mod prelude {
     pub use crate::assets::{AssetKey, AssetsSet, PreloadedAssets};
     pub use crate::condition::*;
     pub use crate::data::Quant;
+    pub use crate::rng::GameRng;
     pub use crate::time::{
         at_tick_multiples, GameTickEventClearSet, GameTickSet, GameTickUpdate,
         GameTime, GameTimeAppExt,
@@ -42,6 +43,7 @@ pub mod data;
 pub mod gent;
 pub mod input;
 pub mod physics;
+pub mod rng;
 pub mod script;
 pub mod time;
 
@@ -51,6 +53,7 @@ impl PluginGroup for EnginePlugins {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<Self>()
             .add(crate::time::GameTimePlugin)
+            .add(crate::rng::GameRngPlugin)
             .add(crate::script::ScriptPlugin)
             .add(crate::animation::SpriteAnimationPlugin)
             .add(crate::audio::AudioPlugin)
@@ -4,10 +4,17 @@ use bevy::prelude::*;
 use bevy::transform::TransformSystem::TransformPropagate;
 use rapier2d::na::{Unit, UnitComplex};
 use rapier2d::parry;
+use rapier2d::parry::query::TOIStatus;
 use rapier2d::prelude::{Collider as RapierCollider, *};
 
+use self::controller::KinematicControllerPlugin;
+use self::layers::{CollisionLayerRegistry, CollisionLayersPlugin};
 use crate::prelude::{GameTickUpdate, HashMap};
 use crate::script::ScriptSet;
+use crate::time::GameTimeAppExt;
+
+pub mod controller;
+pub mod layers;
 
 /// A manual implementation of rapier to only use the features required by our project
 ///
@@ -16,8 +23,14 @@ pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins((
+            CollisionLayersPlugin,
+            KinematicControllerPlugin,
+        ));
         app.insert_resource(PhysicsWorld::default());
         app.init_resource::<SpriteShapeMap>();
+        app.add_gametick_event::<CollisionStarted>();
+        app.add_gametick_event::<CollisionEnded>();
         app.add_systems(Startup, init_physics_world);
         app.configure_sets(
             GameTickUpdate,
@@ -30,6 +43,9 @@ impl Plugin for PhysicsPlugin {
                     .before(PhysicsSet)
                     .after(ScriptSet::Run),
                 update_query_pipeline.in_set(PhysicsSet),
+                update_collision_events
+                    .in_set(PhysicsSet)
+                    .after(update_query_pipeline),
             ),
         );
         #[cfg(feature = "dev")]
@@ -39,12 +55,21 @@ impl Plugin for PhysicsPlugin {
             GameTickUpdate,
             debug_colliders.after(PhysicsSet),
         );
+        #[cfg(feature = "dev")]
+        app.add_systems(
+            Update,
+            debug_collision_layers
+                .run_if(resource_changed::<CollisionLayerRegistry>),
+        );
     }
 }
 
 #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PhysicsSet;
 
+// These are the built-in layers of the `CollisionLayerRegistry`;
+// prefer `CollisionLayers` with layer names for new colliders.
+
 /// The player collision group
 pub const PLAYER: Group = Group::from_bits_truncate(0b0001);
 /// The enemy collision group
@@ -63,6 +88,9 @@ pub const ENEMY_INSIDE: Group = Group::from_bits_truncate(0b1000000);
 /// Combination of ENEMY and ENEMY_INSIDE,
 /// used for checking the players attacks
 pub const ENEMY_HURT: Group = Group::from_bits_truncate(0b1000010);
+/// Platforms that can only be landed on from above;
+/// shape casts moving upwards or sideways (or starting inside) pass through them
+pub const ONE_WAY: Group = Group::from_bits_truncate(0b10000000);
 
 #[derive(Resource, Default)]
 pub struct SpriteShapeMap {
@@ -164,6 +192,21 @@ impl Collider {
         )
     }
 
+    /// A convex polygon, with points relative to the transform
+    ///
+    /// Returns `None` if the points are degenerate (eg: all on a line).
+    pub fn convex_polygon(
+        points: &[Vec2],
+        interaction: InteractionGroups,
+    ) -> Option<Self> {
+        let points: Vec<_> = points.iter().map(|p| point![p.x, p.y]).collect();
+        Some(Self(
+            ColliderBuilder::convex_hull(&points)?
+                .collision_groups(interaction)
+                .build(),
+        ))
+    }
+
     /// You can use this if you want to use an animation collider
     ///
     /// Note: not actually empty, makes a 10x10cube.
@@ -176,6 +219,45 @@ impl Collider {
     }
 }
 
+/// Entities with this and a [`Collider`] get [`CollisionStarted`] and [`CollisionEnded`]
+/// events, whenever they start/stop overlapping another collider
+///
+/// Their collider's own [`InteractionGroups`] decide what they can collide with.
+#[derive(Component, Default)]
+pub struct CollisionEvents;
+
+/// An entity with [`CollisionEvents`] started overlapping another collider
+///
+/// If both entities have [`CollisionEvents`], each of them gets its own event.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CollisionStarted {
+    /// The entity with [`CollisionEvents`]
+    pub entity: Entity,
+    pub other: Entity,
+    pub groups: InteractionGroups,
+    pub other_groups: InteractionGroups,
+}
+
+/// An entity with [`CollisionEvents`] stopped overlapping another collider
+///
+/// Also sent if either of them was despawned or lost its collider.
+/// The groups are the ones from when the collision started.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CollisionEnded {
+    /// The entity with [`CollisionEvents`]
+    pub entity: Entity,
+    pub other: Entity,
+    pub groups: InteractionGroups,
+    pub other_groups: InteractionGroups,
+}
+
+/// Marks a [`Collider`] that never moves, like level geometry
+///
+/// Its transform is only read when the collider is added (or the [`Collider`] is changed),
+/// so the query pipeline doesn't have to check it every tick.
+#[derive(Component, Default)]
+pub struct StaticCollider;
+
 /// Just a wrapper that lets us treat rapiers collider handle as a component
 #[derive(Component)]
 pub struct ColliderHandle(pub rapier2d::prelude::ColliderHandle);
@@ -231,6 +313,104 @@ pub struct PhysicsWorld {
     id_tracker: HashMap<Entity, rapier2d::prelude::ColliderHandle>,
 }
 
+/// Filter options for the [`PhysicsWorld`] queries, built up like:
+///
+/// ```ignore
+/// PhysicsQuery::new(InteractionGroups::new(PLAYER, ENEMY | GROUND))
+///     .exclude(player)
+///     .predicate(&|e| !q_dead.contains(e))
+///     .max_results(3)
+/// ```
+#[derive(Clone)]
+pub struct PhysicsQuery<'a> {
+    interaction: InteractionGroups,
+    exclude: Vec<Entity>,
+    predicate: Option<&'a dyn Fn(Entity) -> bool>,
+    sensors: bool,
+    max_results: usize,
+}
+
+impl<'a> PhysicsQuery<'a> {
+    pub fn new(interaction: InteractionGroups) -> Self {
+        Self {
+            interaction,
+            exclude: Vec::new(),
+            predicate: None,
+            sensors: true,
+            max_results: usize::MAX,
+        }
+    }
+
+    /// Ignore this entity's collider
+    pub fn exclude(mut self, entity: Entity) -> Self {
+        self.exclude.push(entity);
+        self
+    }
+
+    /// Ignore the colliders of all these entities
+    pub fn exclude_all(
+        mut self,
+        entities: impl IntoIterator<Item = Entity>,
+    ) -> Self {
+        self.exclude.extend(entities);
+        self
+    }
+
+    /// Only consider entities for which this returns true
+    ///
+    /// Use this for requiring or forbidding marker components,
+    /// eg. `&|e| q_enemies.contains(e)`.
+    pub fn predicate(mut self, predicate: &'a dyn Fn(Entity) -> bool) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Should colliders marked as sensors be included? (default: yes)
+    pub fn sensors(mut self, sensors: bool) -> Self {
+        self.sensors = sensors;
+        self
+    }
+
+    /// Limit how many hits are returned by the queries that return many
+    pub fn max_results(mut self, max_results: usize) -> Self {
+        self.max_results = max_results;
+        self
+    }
+
+    fn maybe_exclude(
+        interaction: InteractionGroups,
+        exclude: Option<Entity>,
+    ) -> Self {
+        Self::new(interaction).exclude_all(exclude)
+    }
+
+    /// Calls `f` with the equivalent rapier filter, that additionally skips the `skip` colliders
+    fn with_filter<R>(
+        &self,
+        skip: &[rapier2d::prelude::ColliderHandle],
+        f: impl FnOnce(QueryFilter) -> R,
+    ) -> R {
+        let predicate = |handle, collider: &RapierCollider| {
+            if skip.contains(&handle) {
+                return false;
+            }
+            let Ok(entity) = Entity::try_from_bits(collider.user_data as u64)
+            else {
+                return false;
+            };
+            !self.exclude.contains(&entity)
+                && self.predicate.map_or(true, |predicate| predicate(entity))
+        };
+        let mut filter = QueryFilter::new()
+            .groups(self.interaction)
+            .predicate(&predicate);
+        if !self.sensors {
+            filter = filter.exclude_sensors();
+        }
+        f(filter)
+    }
+}
+
 impl PhysicsWorld {
     pub fn shape_cast(
         &self,
@@ -241,28 +421,96 @@ impl PhysicsWorld {
         interaction: InteractionGroups,
         exclude: Option<Entity>,
     ) -> Option<(Entity, parry::query::TOI)> {
-        let mut filter = QueryFilter::new().groups(interaction);
-        if let Some(exclude) = exclude {
-            // Entity might not be added yet; or even exist.
-            if let Some(col_id) = self.id_tracker.get(&exclude) {
-                filter = filter.exclude_collider(*col_id)
-            }
-        }
-        let result = self.query_pipeline.cast_shape(
-            &self.rb_set,
-            &self.col_set,
-            &into_vec(origin).into(),
-            &into_vec(direction.xy()).into(),
+        self.shape_cast_with(
+            origin,
+            direction,
             shape,
             max_toi,
-            true,
-            filter,
-        );
-        if let Some((collider, toi)) = result {
-            let entity: Entity = self.collider2entity(collider)?;
-            Some((entity, toi))
-        } else {
-            None
+            &PhysicsQuery::maybe_exclude(interaction, exclude),
+        )
+    }
+
+    pub fn shape_cast_with(
+        &self,
+        origin: Vec2,
+        direction: Direction2d,
+        shape: &dyn Shape,
+        max_toi: f32,
+        query: &PhysicsQuery,
+    ) -> Option<(Entity, parry::query::TOI)> {
+        let (collider, toi) = self.shape_cast_skipping(
+            origin,
+            direction,
+            shape,
+            max_toi,
+            query,
+            &mut Vec::new(),
+        )?;
+        let entity: Entity = self.collider2entity(collider)?;
+        Some((entity, toi))
+    }
+
+    /// All the colliders the shape would hit, nearest first
+    pub fn shape_cast_all(
+        &self,
+        origin: Vec2,
+        direction: Direction2d,
+        shape: &dyn Shape,
+        max_toi: f32,
+        query: &PhysicsQuery,
+    ) -> Vec<(Entity, parry::query::TOI)> {
+        let mut skip = Vec::new();
+        let mut hits = Vec::new();
+        while hits.len() < query.max_results {
+            let Some((collider, toi)) = self.shape_cast_skipping(
+                origin, direction, shape, max_toi, query, &mut skip,
+            ) else {
+                break;
+            };
+            skip.push(collider);
+            if let Some(entity) = self.collider2entity(collider) {
+                hits.push((entity, toi));
+            }
+        }
+        hits
+    }
+
+    /// Shape cast that ignores the `skip` colliders
+    ///
+    /// One way colliders that don't block this cast are skipped too (and added to `skip`),
+    /// and the cast is repeated without them.
+    fn shape_cast_skipping(
+        &self,
+        origin: Vec2,
+        direction: Direction2d,
+        shape: &dyn Shape,
+        max_toi: f32,
+        query: &PhysicsQuery,
+        skip: &mut Vec<rapier2d::prelude::ColliderHandle>,
+    ) -> Option<(
+        rapier2d::prelude::ColliderHandle,
+        parry::query::TOI,
+    )> {
+        loop {
+            let (collider, toi) = query.with_filter(skip, |filter| {
+                self.query_pipeline.cast_shape(
+                    &self.rb_set,
+                    &self.col_set,
+                    &into_vec(origin).into(),
+                    &into_vec(direction.xy()).into(),
+                    shape,
+                    max_toi,
+                    true,
+                    filter,
+                )
+            })?;
+            let blocks = direction.y < 0.0
+                && toi.status != TOIStatus::Penetrating
+                && toi.normal1.y > 0.5;
+            if blocks || !self.is_one_way(collider) {
+                return Some((collider, toi));
+            }
+            skip.push(collider);
         }
     }
 
@@ -275,24 +523,37 @@ impl PhysicsWorld {
         interaction: InteractionGroups,
         exclude: Option<Entity>,
     ) -> Option<(Entity, parry::query::RayIntersection)> {
-        let mut filter = QueryFilter::new().groups(interaction);
-        if let Some(exclude) = exclude {
-            if let Some(col_id) = self.id_tracker.get(&exclude) {
-                filter = filter.exclude_collider(*col_id)
-            }
-        }
+        self.ray_cast_with(
+            origin,
+            cast,
+            max_toi,
+            solid,
+            &PhysicsQuery::maybe_exclude(interaction, exclude),
+        )
+    }
+
+    pub fn ray_cast_with(
+        &self,
+        origin: Vec2,
+        cast: Vec2,
+        max_toi: f32,
+        solid: bool,
+        query: &PhysicsQuery,
+    ) -> Option<(Entity, parry::query::RayIntersection)> {
         let ray = Ray::new(
             into_vec(origin).into(),
             into_vec(cast).into(),
         );
-        let result = self.query_pipeline.cast_ray_and_get_normal(
-            &self.rb_set,
-            &self.col_set,
-            &ray,
-            max_toi,
-            solid,
-            filter,
-        );
+        let result = query.with_filter(&[], |filter| {
+            self.query_pipeline.cast_ray_and_get_normal(
+                &self.rb_set,
+                &self.col_set,
+                &ray,
+                max_toi,
+                solid,
+                filter,
+            )
+        });
         if let Some((collider, intersection)) = result {
             let entity: Entity = self.collider2entity(collider)?;
             Some((entity, intersection))
@@ -301,6 +562,56 @@ impl PhysicsWorld {
         }
     }
 
+    /// All the colliders the ray hits, nearest first
+    pub fn ray_cast_all(
+        &self,
+        origin: Vec2,
+        cast: Vec2,
+        max_toi: f32,
+        solid: bool,
+        query: &PhysicsQuery,
+    ) -> Vec<(Entity, parry::query::RayIntersection)> {
+        let ray = Ray::new(
+            into_vec(origin).into(),
+            into_vec(cast).into(),
+        );
+        let mut hits = Vec::new();
+        query.with_filter(&[], |filter| {
+            self.query_pipeline.intersections_with_ray(
+                &self.rb_set,
+                &self.col_set,
+                &ray,
+                max_toi,
+                solid,
+                filter,
+                |collider, intersection| {
+                    if let Some(entity) = self.collider2entity(collider) {
+                        hits.push((entity, intersection));
+                    }
+                    true
+                },
+            )
+        });
+        hits.sort_by(|(_, a), (_, b)| a.toi.total_cmp(&b.toi));
+        hits.truncate(query.max_results);
+        hits
+    }
+
+    /// Get the collision groups of an entity's collider
+    pub fn collision_groups(
+        &self,
+        entity: Entity,
+    ) -> Option<InteractionGroups> {
+        let handle = self.id_tracker.get(&entity)?;
+        Some(self.col_set.get(*handle)?.collision_groups())
+    }
+
+    fn is_one_way(&self, handle: rapier2d::prelude::ColliderHandle) -> bool {
+        self.col_set.get(handle).is_some_and(|collider| {
+            collider.collision_groups().memberships.contains(ONE_WAY)
+        })
+    }
+
     pub fn intersect(
         &self,
         origin: Vec2,
@@ -308,25 +619,39 @@ impl PhysicsWorld {
         interaction: InteractionGroups,
         exclude: Option<Entity>,
     ) -> Vec<Entity> {
-        let mut filter = QueryFilter::new().groups(interaction);
-        if let Some(exclude) = exclude {
-            if let Some(col_id) = self.id_tracker.get(&exclude) {
-                filter = filter.exclude_collider(*col_id)
-            }
-        }
-        let mut intersections = Vec::new();
-        self.query_pipeline.intersections_with_shape(
-            &self.rb_set,
-            &self.col_set,
-            &into_vec(origin).into(),
+        self.intersect_with(
+            origin,
             shape,
-            filter,
-            |collider| {
-                let entity: Entity = self.collider2entity(collider).unwrap();
-                intersections.push(entity);
-                true
-            },
-        );
+            &PhysicsQuery::maybe_exclude(interaction, exclude),
+        )
+    }
+
+    pub fn intersect_with(
+        &self,
+        origin: Vec2,
+        shape: &dyn Shape,
+        query: &PhysicsQuery,
+    ) -> Vec<Entity> {
+        let mut intersections = Vec::new();
+        query.with_filter(&[], |filter| {
+            self.query_pipeline.intersections_with_shape(
+                &self.rb_set,
+                &self.col_set,
+                &into_vec(origin).into(),
+                shape,
+                filter,
+                |collider| {
+                    // nothing at all, if there is no room for results
+                    if intersections.len() >= query.max_results {
+                        return false;
+                    }
+                    let entity: Entity =
+                        self.collider2entity(collider).unwrap();
+                    intersections.push(entity);
+                    intersections.len() < query.max_results
+                },
+            )
+        });
         intersections
     }
 
@@ -334,22 +659,29 @@ impl PhysicsWorld {
         &self,
         point: Vec2,
         interaction: InteractionGroups,
-        // TODO: might want to be able to exclude multiple entities?
         exclude: Option<Entity>,
     ) -> Option<(Entity, parry::query::PointProjection)> {
-        let mut filter = QueryFilter::new().groups(interaction);
-        if let Some(exclude) = exclude {
-            if let Some(col_id) = self.id_tracker.get(&exclude) {
-                filter = filter.exclude_collider(*col_id)
-            }
-        }
-        if let Some((collider, point)) = self.query_pipeline.project_point(
-            &self.rb_set,
-            &self.col_set,
-            &into_vec(point).into(),
-            true,
-            filter,
-        ) {
+        self.point_project_with(
+            point,
+            &PhysicsQuery::maybe_exclude(interaction, exclude),
+        )
+    }
+
+    pub fn point_project_with(
+        &self,
+        point: Vec2,
+        query: &PhysicsQuery,
+    ) -> Option<(Entity, parry::query::PointProjection)> {
+        let result = query.with_filter(&[], |filter| {
+            self.query_pipeline.project_point(
+                &self.rb_set,
+                &self.col_set,
+                &into_vec(point).into(),
+                true,
+                filter,
+            )
+        });
+        if let Some((collider, point)) = result {
             let entity: Entity = self.collider2entity(collider)?;
             Some((entity, point))
         } else {
@@ -391,7 +723,8 @@ fn init_physics_world(mut world: ResMut<PhysicsWorld>) {
     query_pipeline.update(&rb_set, &col_set);
 }
 
-/// Updates the pipeline by reading all the positions/components with colliders
+/// Updates the pipeline with the colliders that were added, changed, moved or removed
+/// since the last tick
 ///
 /// Make sure if you are reading from this in a system, you run after this finishes
 ///
@@ -400,13 +733,18 @@ fn init_physics_world(mut world: ResMut<PhysicsWorld>) {
 pub fn update_query_pipeline(
     // Mutable reference because collider data is stored in an Arena that pipeline modifies
     mut world: ResMut<PhysicsWorld>,
-    phys_obj_query: Query<(
-        Entity,
-        &Transform,
-        Ref<GlobalTransform>,
-        Ref<Collider>,
-        Option<&ColliderHandle>,
-    )>,
+    // Only what changed since the last tick needs to be synced;
+    // static colliders don't follow their transform after being added
+    phys_obj_query: Query<
+        (Entity, &GlobalTransform, Ref<Collider>),
+        Or<(
+            Changed<Collider>,
+            (
+                Changed<GlobalTransform>,
+                Without<StaticCollider>,
+            ),
+        )>,
+    >,
     mut removed: RemovedComponents<Collider>,
     mut commands: Commands,
 ) {
@@ -417,96 +755,143 @@ pub fn update_query_pipeline(
         rb_set,
         id_tracker,
     } = &mut *world;
-    // query_pipeline.cast_shape()
+    // Removals go first, in case the collider was replaced on the same entity
+    let mut removed_colliders = vec![];
+    for removed in removed.read() {
+        let Some(removed_id) = id_tracker.remove(&removed) else {
+            continue;
+        };
+        removed_colliders.push(removed_id);
+        col_set.remove(removed_id, islands, rb_set, false);
+    }
+
     let mut modified_colliders = vec![];
-    for (entity, trnsfm, transform, collider_info, handle) in &phys_obj_query {
-        let col_id = if collider_info.is_added() && handle.is_none() {
+    for (entity, transform, collider_info) in &phys_obj_query {
+        let col_id = if let Some(col_id) = id_tracker.get(&entity).copied() {
+            if collider_info.is_changed() {
+                let old_entity = col_set.get(col_id).unwrap().user_data;
+                *col_set.get_mut(col_id).unwrap() = collider_info.0.clone();
+                col_set.get_mut(col_id).unwrap().user_data = old_entity;
+            }
+            col_id
+        } else {
             let col_id = col_set.insert(collider_info.0.clone());
-            modified_colliders.push(col_id);
             id_tracker.insert(entity, col_id);
-            commands
-                .get_entity(entity)
-                .unwrap()
-                .insert(ColliderHandle(col_id));
+            commands.entity(entity).insert(ColliderHandle(col_id));
             // Sets the user associated data on the collider to the entity id
             // so that when we get a query result with a collider id we can lookup
             // what entity its associated with.
             col_set.get_mut(col_id).unwrap().user_data =
                 entity.to_bits() as u128;
-            // println!("new collider added: {col_id:?}");
-
-            col_set
-                .get_mut(col_id)
-                .unwrap()
-                .set_translation(into_vec(transform.translation().xy()));
-            col_set
-                .get_mut(col_id)
-                .unwrap()
-                .set_rotation(UnitComplex::new(
-                    transform
-                        .compute_transform()
-                        .rotation
-                        .to_euler(EulerRot::XYZ)
-                        .2,
-                ));
-
             col_id
-        } else {
-            handle.unwrap().0
         };
 
-        if collider_info.is_changed() {
-            let old_entity = col_set.get(col_id).unwrap().user_data;
-            *col_set.get_mut(col_id).unwrap() = collider_info.0.clone();
-            col_set.get_mut(col_id).unwrap().user_data = old_entity;
-            modified_colliders.push(col_id);
-        }
-        if transform.is_changed() {
-            col_set
-                .get_mut(col_id)
-                .unwrap()
-                .set_translation(into_vec(transform.translation().xy()));
-            col_set
-                .get_mut(col_id)
-                .unwrap()
-                .set_rotation(UnitComplex::new(
-                    transform
-                        .compute_transform()
-                        .rotation
-                        .to_euler(EulerRot::XYZ)
-                        .2,
-                ));
-            modified_colliders.push(col_id);
-        }
+        // New or replaced colliders need their position set, too
+        let collider = col_set.get_mut(col_id).unwrap();
+        collider.set_translation(into_vec(transform.translation().xy()));
+        collider.set_rotation(UnitComplex::new(
+            transform
+                .compute_transform()
+                .rotation
+                .to_euler(EulerRot::XYZ)
+                .2,
+        ));
+        modified_colliders.push(col_id);
     }
-    let mut removed_colliders = vec![];
-    // Go through the col_set, and see if any are there that aren't in our collider_set.
-    for removed in removed.read() {
-        let Some(removed_id) = id_tracker.get(&removed).copied() else {
+
+    if !modified_colliders.is_empty() || !removed_colliders.is_empty() {
+        query_pipeline.update_incremental(
+            &col_set,
+            modified_colliders.as_slice(),
+            removed_colliders.as_slice(),
+            true,
+        );
+    }
+}
+
+/// Tracks the overlapping pairs of colliders for [`CollisionEvents`] entities,
+/// and sends events when they change
+///
+/// Runs after [`update_query_pipeline`], so it sees the current tick's positions.
+pub fn update_collision_events(
+    world: Res<PhysicsWorld>,
+    query: Query<Entity, With<CollisionEvents>>,
+    mut contacts: Local<
+        HashMap<(Entity, Entity), (InteractionGroups, InteractionGroups)>,
+    >,
+    mut evw_started: EventWriter<CollisionStarted>,
+    mut evw_ended: EventWriter<CollisionEnded>,
+) {
+    let mut current = HashMap::new();
+    for entity in &query {
+        let Some(handle) = world.id_tracker.get(&entity) else {
             continue;
         };
-        id_tracker.remove(&removed);
-        removed_colliders.push(removed_id);
-        col_set.remove(removed_id, islands, rb_set, false);
+        let Some(collider) = world.col_set.get(*handle) else {
+            continue;
+        };
+        let groups = collider.collision_groups();
+        let filter =
+            QueryFilter::new().groups(groups).exclude_collider(*handle);
+        world.query_pipeline.intersections_with_shape(
+            &world.rb_set,
+            &world.col_set,
+            collider.position(),
+            collider.shape(),
+            filter,
+            |other_handle| {
+                let Some(other) = world.collider2entity(other_handle) else {
+                    return true;
+                };
+                let other_groups =
+                    world.col_set[other_handle].collision_groups();
+                current.insert((entity, other), (groups, other_groups));
+                true
+            },
+        );
+    }
+
+    for (&(entity, other), &(groups, other_groups)) in contacts.iter() {
+        if !current.contains_key(&(entity, other)) {
+            evw_ended.send(CollisionEnded {
+                entity,
+                other,
+                groups,
+                other_groups,
+            });
+        }
+    }
+    for (&(entity, other), groups) in current.iter_mut() {
+        if let Some(started) = contacts.get(&(entity, other)) {
+            // keep the groups from when the collision started
+            *groups = *started;
+            continue;
+        }
+        evw_started.send(CollisionStarted {
+            entity,
+            other,
+            groups: groups.0,
+            other_groups: groups.1,
+        });
     }
-    query_pipeline.update_incremental(
-        &col_set,
-        modified_colliders.as_slice(),
-        removed_colliders.as_slice(),
-        true,
-    );
+    *contacts = current;
 }
 
 #[derive(Default, Reflect, GizmoConfigGroup)]
 struct PhsyicsCollidersGizmos {}
 
 /// Draws colliders using bevy's gizmos to assist with debugging.
+///
+/// Each collider is colored by its (first) collision layer,
+/// see [`debug_collision_layers`] for which color is which.
 pub fn debug_colliders(
     world: ResMut<PhysicsWorld>,
+    registry: Res<CollisionLayerRegistry>,
     // mut gizmos: Gizmos,
     mut collider_gizmos: Gizmos<PhsyicsCollidersGizmos>,
 ) {
     for (handle, collider) in world.col_set.iter() {
+        let color = registry.color(collider.collision_groups().memberships);
         let pos = Vec2::new(
             collider.position().translation.x,
             collider.position().translation.y,
@@ -519,7 +904,7 @@ pub fn debug_colliders(
                 pos.extend(0.0002),
                 Quat::from_rotation_z(rotation - PI),
                 half_extents * 2.0,
-                Color::GREEN,
+                color,
             );
         }
         if let Some(convex) = collider.shared_shape().as_convex_polygon() {
@@ -533,13 +918,50 @@ pub fn debug_colliders(
                 collider_gizmos.line(
                     Vec2::new(pos.x + start.x, pos.y + start.y).extend(0.0002),
                     Vec2::new(pos.x + end.x, pos.y + end.y).extend(0.0002),
-                    Color::GREEN,
+                    color,
                 );
             }
         }
     }
 }
 
+/// Logs the name and debug color of every collision layer
+pub fn debug_collision_layers(registry: Res<CollisionLayerRegistry>) {
+    for (name, group) in registry.layers() {
+        let [r, g, b, _] = registry.color(group).as_rgba_u8();
+        debug!(
+            "Collision layer '{}' (#{:02x}{:02x}{:02x}) collides with: {}",
+            name,
+            r,
+            g,
+            b,
+            registry.describe(registry.filter(name)),
+        );
+    }
+}
+
+/// Surfaces steeper than this (in radians from horizontal) are walls,
+/// anything flatter can be walked on
+pub const MAX_SLOPE_ANGLE: f32 = PI / 3.0;
+
+/// Can a gent walk on a surface with this normal?
+pub fn is_walkable(normal: Vec2) -> bool {
+    normal.y >= MAX_SLOPE_ANGLE.cos()
+}
+
+/// Redirects a velocity along a walkable surface, keeping its horizontal speed
+///
+/// Used so that gents walk up and down slopes, instead of into or off of them.
+/// Against a vertical surface, there is no slope to walk along, and the
+/// velocity is returned unchanged.
+pub fn project_on_slope(velocity: Vec2, normal: Vec2) -> Vec2 {
+    let tangent = Vec2::new(normal.y, -normal.x);
+    if tangent.x.abs() < f32::EPSILON {
+        return velocity;
+    }
+    tangent * (velocity.x / tangent.x)
+}
+
 /// Utility to convert from [`Vec2`] to rapier compatible structure
 pub fn into_vec(vec: Vec2) -> Vector<f32> {
     vector![vec.x, vec.y]
@@ -555,3 +977,61 @@ pub fn into_vec2(vec: Unit<Vector<f32>>) -> Vec2 {
 /// Doesn't do anything on its own, but character controllers use it.
 #[derive(Component, Deref, DerefMut, Debug)]
 pub struct LinearVelocity(pub Vec2);
+
+/// Knockback that can be applied to a gent. Velocity is applied Once and then blocks horizontal movement.
+#[derive(Component, Default, Clone, Copy, Debug)]
+pub struct Knockback {
+    pub ticks: u32,
+    pub max_ticks: u32,
+    pub strength: Vec2,
+}
+
+impl Knockback {
+    pub fn new(strength: Vec2, max_ticks: u32) -> Self {
+        Self {
+            ticks: 0,
+            max_ticks,
+            strength,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn intersect_up_to_max_results() {
+        let mut world = World::new();
+        world.insert_resource(PhysicsWorld::default());
+        // three blocks on top of each other
+        for _ in 0..3 {
+            world.spawn((
+                Collider::cuboid(
+                    8.0,
+                    8.0,
+                    InteractionGroups::new(GROUND, Group::all()),
+                ),
+                GlobalTransform::default(),
+            ));
+        }
+        world.run_system_once(update_query_pipeline);
+        world.run_system_once(init_physics_world);
+
+        let physics = world.resource::<PhysicsWorld>();
+        let shape = parry::shape::Ball::new(1.0);
+        let count = |max_results| {
+            let query = PhysicsQuery::new(InteractionGroups::new(
+                Group::all(),
+                GROUND,
+            ))
+            .max_results(max_results);
+            physics.intersect_with(Vec2::ZERO, &shape, &query).len()
+        };
+        assert_eq!(count(0), 0);
+        assert_eq!(count(2), 2);
+        assert_eq!(count(usize::MAX), 3);
+    }
+}
@@ -0,0 +1,587 @@
+//! A shared kinematic character controller
+//!
+//! Gents with a [`KinematicController`], a [`Collider`] and a [`LinearVelocity`]
+//! get moved by their velocity every tick, sliding along whatever they hit.
+//! The controller reports what the gent is touching, so gameplay code only
+//! has to decide on a velocity.
+//!
+//! It also applies [`Knockback`]. Gameplay code that wants a gent to resist
+//! being pushed (eg. while blocking) gives it a knockback with no strength.
+
+use bevy::transform::TransformSystem::TransformPropagate;
+use rapier2d::parry::query::TOIStatus;
+use rapier2d::prelude::{Group, InteractionGroups, Shape};
+
+use super::{
+    into_vec2, is_walkable, Collider, Knockback, LinearVelocity, PhysicsSet,
+    PhysicsWorld, GROUND, MAX_SLOPE_ANGLE, ONE_WAY,
+};
+use crate::prelude::*;
+
+pub struct KinematicControllerPlugin;
+
+impl Plugin for KinematicControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(
+            GameTickUpdate,
+            KinematicSet.before(TransformPropagate).before(PhysicsSet),
+        );
+        app.add_systems(
+            GameTickUpdate,
+            kinematic_controller.in_set(KinematicSet),
+        );
+    }
+}
+
+/// When [`KinematicController`]s move their entities
+///
+/// Systems that set the velocity of controlled gents should run before this.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KinematicSet;
+
+/// Moves the entity by its [`LinearVelocity`], colliding with the world
+///
+/// Also handles [`Knockback`]: the velocity is set to its strength once,
+/// and the horizontal velocity is zeroed when it runs out.
+///
+/// While grounded, it follows the ground down slopes (and small steps)
+/// instead of walking off into the air.
+#[derive(Component, Debug, Clone)]
+pub struct KinematicController {
+    /// What the gent collides with
+    pub interaction: InteractionGroups,
+    /// Gap kept between the collider and whatever it touches,
+    /// so it doesn't start the next cast already penetrating
+    pub skin_width: f32,
+    /// Ledges up to this high get stepped onto instead of blocking
+    pub step_height: f32,
+    /// Downwards acceleration, in px/s²
+    pub gravity: f32,
+    /// How many times it can hit something and slide along it in one tick
+    pub max_slides: u32,
+
+    // These are updated every tick
+    pub grounded: bool,
+    /// Normal of the ground, if `grounded`
+    pub ground_normal: Vec2,
+    /// What the gent is standing on, if `grounded`
+    pub ground: Option<Entity>,
+    /// Touching a wall on the left
+    pub wall_left: bool,
+    /// Touching a wall on the right
+    pub wall_right: bool,
+    pub ceiling: bool,
+}
+
+impl Default for KinematicController {
+    fn default() -> Self {
+        Self {
+            interaction: InteractionGroups::new(Group::all(), GROUND | ONE_WAY),
+            skin_width: 0.1,
+            step_height: 0.0,
+            gravity: 0.0,
+            max_slides: 4,
+            grounded: false,
+            ground_normal: Vec2::Y,
+            ground: None,
+            wall_left: false,
+            wall_right: false,
+            ceiling: false,
+        }
+    }
+}
+
+impl KinematicController {
+    pub fn new(interaction: InteractionGroups) -> Self {
+        Self {
+            interaction,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_step_height(mut self, step_height: f32) -> Self {
+        self.step_height = step_height;
+        self
+    }
+
+    pub fn with_gravity(mut self, gravity: f32) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    /// Touching a wall on either side
+    pub fn on_wall(&self) -> bool {
+        self.wall_left || self.wall_right
+    }
+
+    fn reset_contacts(&mut self) {
+        self.grounded = false;
+        self.ground_normal = Vec2::Y;
+        self.ground = None;
+        self.wall_left = false;
+        self.wall_right = false;
+        self.ceiling = false;
+    }
+
+    /// Updates the contact flags from the normal of something we hit
+    fn record_contact(&mut self, hit: Entity, normal: Vec2) {
+        if is_walkable(normal) {
+            self.grounded = true;
+            self.ground_normal = normal;
+            self.ground = Some(hit);
+        } else if normal.y < -0.5 {
+            self.ceiling = true;
+        } else if normal.x > 0.0 {
+            self.wall_left = true;
+        } else if normal.x < 0.0 {
+            self.wall_right = true;
+        }
+    }
+}
+
+pub fn kinematic_controller(
+    mut query: Query<(
+        Entity,
+        &mut KinematicController,
+        &mut LinearVelocity,
+        &mut Transform,
+        &Collider,
+        Option<&mut Knockback>,
+    )>,
+    time: Res<GameTime>,
+    physics: Res<PhysicsWorld>,
+    mut commands: Commands,
+) {
+    let dt = 1.0 / time.hz as f32;
+    for (
+        entity,
+        mut controller,
+        mut velocity,
+        mut transform,
+        collider,
+        knockback,
+    ) in query.iter_mut()
+    {
+        if let Some(mut knockback) = knockback {
+            knockback.ticks += 1;
+            if knockback.is_added() {
+                velocity.0 = knockback.strength;
+            }
+            if knockback.ticks > knockback.max_ticks {
+                velocity.x = 0.;
+                commands.entity(entity).remove::<Knockback>();
+            }
+        }
+        velocity.y -= controller.gravity * dt;
+
+        let was_grounded = controller.grounded;
+        controller.reset_contacts();
+        let shape = collider.0.shared_shape().clone();
+        let mut pos = transform.translation.xy();
+        let mut motion = velocity.0 * dt;
+
+        for _ in 0..controller.max_slides {
+            let Ok(dir) = Direction2d::new(motion) else {
+                break;
+            };
+            let dist = motion.length();
+            let Some((hit_entity, hit)) = physics.shape_cast(
+                pos,
+                dir,
+                &*shape,
+                dist + controller.skin_width,
+                controller.interaction,
+                Some(entity),
+            ) else {
+                pos += motion;
+                break;
+            };
+            if hit.status == TOIStatus::Penetrating {
+                // already inside something, let it move out on its own
+                pos += motion;
+                break;
+            }
+            let normal = into_vec2(hit.normal1);
+            let travel = (hit.toi - controller.skin_width).clamp(0.0, dist);
+            pos += *dir * travel;
+            let remaining = *dir * (dist - travel);
+
+            let is_wall = !is_walkable(normal) && normal.y > -0.5;
+            if is_wall && was_grounded {
+                if let Some(stepped) = step_up(
+                    &physics,
+                    &controller,
+                    entity,
+                    &*shape,
+                    pos,
+                    remaining,
+                ) {
+                    pos = stepped;
+                    controller.grounded = true;
+                    break;
+                }
+            }
+
+            controller.record_contact(hit_entity, normal);
+            motion = remaining - normal * remaining.dot(normal);
+            let into_surface = velocity.dot(normal);
+            if into_surface < 0.0 {
+                velocity.0 -= normal * into_surface;
+            }
+        }
+        // anything left after `max_slides` is dropped, rather than
+        // moving without checking for collisions
+
+        // resting on the ground doesn't move into it, so look for it,
+        // and walking down a slope moves away from it, so follow it down
+        if !controller.grounded && velocity.y <= 0.0 {
+            let follow = if was_grounded {
+                (velocity.x * dt).abs() * MAX_SLOPE_ANGLE.tan()
+            } else {
+                0.0
+            };
+            if let Some((hit_entity, hit)) = physics.shape_cast(
+                pos,
+                Direction2d::NEG_Y,
+                &*shape,
+                controller.skin_width * 2.0 + follow,
+                controller.interaction,
+                Some(entity),
+            ) {
+                let normal = into_vec2(hit.normal1);
+                if hit.status != TOIStatus::Penetrating && is_walkable(normal) {
+                    controller.record_contact(hit_entity, normal);
+                    pos.y -= (hit.toi - controller.skin_width).max(0.0);
+                }
+            }
+        }
+
+        transform.translation = pos.extend(transform.translation.z);
+    }
+}
+
+/// Tries to step over a ledge: up, forward, and back down onto it
+///
+/// Returns the new position if there was room to do so.
+fn step_up(
+    physics: &PhysicsWorld,
+    controller: &KinematicController,
+    entity: Entity,
+    shape: &dyn Shape,
+    pos: Vec2,
+    remaining: Vec2,
+) -> Option<Vec2> {
+    if controller.step_height <= 0.0 || remaining.x == 0.0 {
+        return None;
+    }
+    let cast = |origin: Vec2, dir: Direction2d, dist: f32| {
+        physics.shape_cast(
+            origin,
+            dir,
+            shape,
+            dist,
+            controller.interaction,
+            Some(entity),
+        )
+    };
+    if cast(
+        pos,
+        Direction2d::Y,
+        controller.step_height,
+    )
+    .is_some()
+    {
+        return None;
+    }
+    let up = pos + Vec2::Y * controller.step_height;
+    let forward_dir = if remaining.x > 0.0 {
+        Direction2d::X
+    } else {
+        Direction2d::NEG_X
+    };
+    // always move at least a little forward, or we'd land back where we were
+    let forward_dist = remaining.x.abs().max(controller.skin_width * 2.0);
+    if cast(
+        up,
+        forward_dir,
+        forward_dist + controller.skin_width,
+    )
+    .is_some()
+    {
+        return None;
+    }
+    let forward = up + *forward_dir * forward_dist;
+    let (_, hit) = cast(
+        forward,
+        Direction2d::NEG_Y,
+        controller.step_height + controller.skin_width,
+    )?;
+    if hit.status == TOIStatus::Penetrating
+        || !is_walkable(into_vec2(hit.normal1))
+    {
+        return None;
+    }
+    let drop = (hit.toi - controller.skin_width).max(0.0);
+    Some(forward - Vec2::Y * drop)
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::schedule::ExecutorKind;
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::physics::{init_physics_world, update_query_pipeline, ENEMY};
+
+    /// 96 Hz, so a velocity of 96 px/s moves 1 px per tick
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert_resource(PhysicsWorld::default());
+        world.insert_resource(GameTime::new(96.0));
+        world
+    }
+
+    fn spawn_block(world: &mut World, min: Vec2, max: Vec2) {
+        let size = max - min;
+        world.spawn((
+            Collider::cuboid(
+                size.x,
+                size.y,
+                InteractionGroups::new(GROUND, Group::all()),
+            ),
+            GlobalTransform::from_translation(((min + max) * 0.5).extend(0.0)),
+        ));
+    }
+
+    /// An 8x8 gent, centered on `pos`
+    fn spawn_gent(
+        world: &mut World,
+        pos: Vec2,
+        velocity: Vec2,
+        controller: KinematicController,
+    ) -> Entity {
+        world
+            .spawn((
+                controller,
+                LinearVelocity(velocity),
+                Transform::from_translation(pos.extend(0.0)),
+                Collider::cuboid(
+                    8.0,
+                    8.0,
+                    InteractionGroups::new(ENEMY, GROUND),
+                ),
+            ))
+            .id()
+    }
+
+    fn step(world: &mut World) {
+        world.run_system_once(update_query_pipeline);
+        world.run_system_once(init_physics_world);
+        world.run_system_once(kinematic_controller);
+    }
+
+    fn get(world: &World, e: Entity) -> (Vec2, Vec2, &KinematicController) {
+        (
+            world.get::<Transform>(e).unwrap().translation.xy(),
+            world.get::<LinearVelocity>(e).unwrap().0,
+            world.get::<KinematicController>(e).unwrap(),
+        )
+    }
+
+    #[test]
+    fn slide_along_ground() {
+        let mut world = world();
+        // ground with its top at y = 0
+        spawn_block(
+            &mut world,
+            Vec2::new(-100.0, -20.0),
+            Vec2::new(100.0, 0.0),
+        );
+        // 1 px above the ground, falling 10 px per tick, moving 1 px right
+        let gent = spawn_gent(
+            &mut world,
+            Vec2::new(0.0, 5.0),
+            Vec2::new(96.0, -960.0),
+            KinematicController::default(),
+        );
+        step(&mut world);
+        let (pos, velocity, controller) = get(&world, gent);
+        assert!(controller.grounded);
+        assert!(!controller.on_wall());
+        assert_eq!(controller.ground_normal, Vec2::Y);
+        // the fall is stopped, but not the walk
+        assert!((pos.y - 4.1).abs() < 0.05, "{}", pos.y);
+        assert!(pos.x > 0.8 && pos.x < 1.01, "{}", pos.x);
+        assert!(velocity.y.abs() < 0.001);
+        assert_eq!(velocity.x, 96.0);
+    }
+
+    #[test]
+    fn stop_at_wall() {
+        let mut world = world();
+        // a wall with its left side at x = 15
+        spawn_block(
+            &mut world,
+            Vec2::new(15.0, -50.0),
+            Vec2::new(25.0, 50.0),
+        );
+        let gent = spawn_gent(
+            &mut world,
+            Vec2::new(10.0, 0.0),
+            Vec2::new(960.0, 0.0),
+            KinematicController::default(),
+        );
+        step(&mut world);
+        let (pos, velocity, controller) = get(&world, gent);
+        assert!(controller.wall_right);
+        assert!(!controller.wall_left);
+        assert!(!controller.grounded);
+        assert!((pos.x - 10.9).abs() < 0.05, "{}", pos.x);
+        assert!(velocity.x.abs() < 0.001);
+    }
+
+    #[test]
+    fn step_up_ledge() {
+        let mut world = world();
+        spawn_block(
+            &mut world,
+            Vec2::new(-100.0, -20.0),
+            Vec2::new(100.0, 0.0),
+        );
+        // a 3 px high ledge, starting at x = 15
+        spawn_block(
+            &mut world,
+            Vec2::new(15.0, -20.0),
+            Vec2::new(55.0, 3.0),
+        );
+        let controller = KinematicController::default().with_step_height(5.0);
+        // resting on the ground
+        let gent = spawn_gent(
+            &mut world,
+            Vec2::new(10.0, 4.1),
+            Vec2::ZERO,
+            controller.clone(),
+        );
+        // same, but the ledge is too high for it
+        let blocked = spawn_gent(
+            &mut world,
+            Vec2::new(-10.0, 4.1),
+            Vec2::ZERO,
+            controller.with_step_height(2.0),
+        );
+        step(&mut world);
+        assert!(get(&world, gent).2.grounded);
+        assert!(get(&world, blocked).2.grounded);
+
+        // 5 px per tick towards the ledge, it's 1 px away
+        world.get_mut::<LinearVelocity>(gent).unwrap().0 =
+            Vec2::new(480.0, 0.0);
+        world.get_mut::<Transform>(blocked).unwrap().translation.x = 10.0;
+        world.get_mut::<LinearVelocity>(blocked).unwrap().0 =
+            Vec2::new(480.0, 0.0);
+        step(&mut world);
+
+        let (pos, _, controller) = get(&world, gent);
+        assert!(controller.grounded);
+        assert!(!controller.wall_right);
+        assert!((pos.x - 15.0).abs() < 0.05, "{}", pos.x);
+        assert!((pos.y - 7.1).abs() < 0.05, "{}", pos.y);
+
+        let (pos, _, controller) = get(&world, blocked);
+        assert!(controller.wall_right);
+        assert!((pos.x - 10.9).abs() < 0.05, "{}", pos.x);
+        assert!((pos.y - 4.1).abs() < 0.05, "{}", pos.y);
+    }
+
+    #[test]
+    fn follow_a_step_down() {
+        let mut world = world();
+        // 2 px lower from x = 10 on
+        spawn_block(
+            &mut world,
+            Vec2::new(-100.0, -20.0),
+            Vec2::new(10.0, 0.0),
+        );
+        spawn_block(
+            &mut world,
+            Vec2::new(10.0, -20.0),
+            Vec2::new(100.0, -2.0),
+        );
+        let gent = spawn_gent(
+            &mut world,
+            Vec2::new(5.0, 4.1),
+            Vec2::ZERO,
+            KinematicController::default(),
+        );
+        step(&mut world);
+        assert!(get(&world, gent).2.grounded);
+
+        // 5 px per tick, so it is past the edge after two ticks
+        world.get_mut::<LinearVelocity>(gent).unwrap().0 =
+            Vec2::new(480.0, 0.0);
+        step(&mut world);
+        step(&mut world);
+        let (pos, _, controller) = get(&world, gent);
+        assert!(controller.grounded);
+        assert!((pos.x - 15.0).abs() < 0.05, "{}", pos.x);
+        assert!((pos.y - 2.1).abs() < 0.05, "{}", pos.y);
+
+        // but not when it wasn't on the ground to begin with
+        world.get_mut::<Transform>(gent).unwrap().translation =
+            Vec3::new(-50.0, 7.0, 0.0);
+        world.get_mut::<LinearVelocity>(gent).unwrap().0 = Vec2::ZERO;
+        step(&mut world);
+        assert!(!get(&world, gent).2.grounded);
+        world.get_mut::<LinearVelocity>(gent).unwrap().0 =
+            Vec2::new(480.0, 0.0);
+        step(&mut world);
+        let (pos, _, controller) = get(&world, gent);
+        assert!(!controller.grounded);
+        assert!((pos.y - 7.0).abs() < 0.05, "{}", pos.y);
+    }
+
+    #[test]
+    fn knockback() {
+        let mut world = world();
+        let gent = spawn_gent(
+            &mut world,
+            Vec2::ZERO,
+            Vec2::ZERO,
+            KinematicController::default(),
+        );
+        world
+            .entity_mut(gent)
+            .insert(Knockback::new(Vec2::new(96.0, 0.0), 2));
+        // a schedule, so the knockback is only new the first time
+        let mut schedule = Schedule::default();
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        schedule.add_systems(
+            (
+                update_query_pipeline,
+                init_physics_world,
+                kinematic_controller,
+            )
+                .chain(),
+        );
+
+        schedule.run(&mut world);
+        assert_eq!(
+            get(&world, gent).1,
+            Vec2::new(96.0, 0.0)
+        );
+        // gameplay code can still change the velocity afterwards
+        world.get_mut::<LinearVelocity>(gent).unwrap().0 = Vec2::new(48.0, 0.0);
+        schedule.run(&mut world);
+        assert_eq!(
+            get(&world, gent).1,
+            Vec2::new(48.0, 0.0)
+        );
+        assert!(world.get::<Knockback>(gent).is_some());
+
+        schedule.run(&mut world);
+        let (pos, velocity, _) = get(&world, gent);
+        assert_eq!(velocity.x, 0.0);
+        assert!((pos.x - 1.5).abs() < 0.001, "{}", pos.x);
+        assert!(world.get::<Knockback>(gent).is_none());
+    }
+}
@@ -0,0 +1,480 @@
+//! Named collision layers
+//!
+//! Instead of building [`InteractionGroups`] bitmasks by hand, colliders can
+//! be described by the names of the layers they belong to, like
+//! `CollisionLayers::new("enemy_attack")`. What each layer collides with
+//! comes from a `*.layers.toml` config asset. The matrix is symmetric: if
+//! `a` collides with `b`, then `b` collides with `a`.
+//!
+//! The first layers are the built-in ones, in the same order as the
+//! [`PLAYER`](super::PLAYER), [`ENEMY`](super::ENEMY), ... constants, so code
+//! that still uses the constants keeps working. New layers go after them.
+
+use bevy_common_assets::toml::TomlAssetPlugin;
+use rapier2d::prelude::{Group, InteractionGroups};
+
+use super::Collider;
+use crate::prelude::*;
+
+/// The layers that always exist, in bit order
+///
+/// These must match the bit constants in [`super`].
+pub const BUILTIN_LAYERS: [&str; 8] = [
+    "player",
+    "enemy",
+    "player_attack",
+    "enemy_attack",
+    "ground",
+    "sensor",
+    "enemy_inside",
+    "one_way",
+];
+
+/// Rapier only has 32 bits for groups
+pub const MAX_LAYERS: usize = 32;
+
+pub struct CollisionLayersPlugin;
+
+impl Plugin for CollisionLayersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(
+            TomlAssetPlugin::<CollisionLayersConfig>::new(&["layers.toml"]),
+        );
+        app.init_resource::<CollisionLayerRegistry>();
+        app.add_systems(
+            Update,
+            (
+                load_collision_layers,
+                reapply_collision_layers
+                    .run_if(resource_changed::<CollisionLayerRegistry>),
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Collision layer config asset
+///
+/// ```toml
+/// layers = ["player", "enemy", ..., "one_way", "enemy_projectile"]
+///
+/// [collides]
+/// player = ["enemy", "ground"]
+/// enemy_projectile = ["player", "ground"]
+/// ```
+#[derive(Asset, Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[derive(TypePath)]
+pub struct CollisionLayersConfig {
+    /// All the layer names, in bit order. Must start with [`BUILTIN_LAYERS`].
+    pub layers: Vec<String>,
+    /// For each layer, the layers it collides with
+    #[serde(default)]
+    pub collides: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Error)]
+pub enum CollisionLayersError {
+    #[error("Too many layers ({0}), at most 32 are supported")]
+    TooMany(usize),
+    #[error(
+        "Layer {index} must be the built-in layer '{expected}', got '{got}'"
+    )]
+    Builtin {
+        index: usize,
+        expected: &'static str,
+        got: String,
+    },
+    #[error("Layer '{0}' is defined more than once")]
+    Duplicate(String),
+    #[error("Unknown layer '{0}' in the collision matrix")]
+    Unknown(String),
+}
+
+/// The names of all collision layers and what they collide with
+///
+/// Until the config asset is loaded, this has only the built-in layers,
+/// set up the way they were used before the config existed.
+#[derive(Resource, Debug, Clone)]
+pub struct CollisionLayerRegistry {
+    names: Vec<String>,
+    /// The filter of each layer, by bit index
+    filters: Vec<Group>,
+}
+
+impl Default for CollisionLayerRegistry {
+    fn default() -> Self {
+        let collides: [(&str, &[&str]); 8] = [
+            (
+                "player",
+                &[
+                    "enemy",
+                    "enemy_attack",
+                    "sensor",
+                    "enemy_inside",
+                    "ground",
+                    "one_way",
+                ],
+            ),
+            (
+                "enemy",
+                &[
+                    "player",
+                    "player_attack",
+                    "enemy_inside",
+                    "ground",
+                    "one_way",
+                ],
+            ),
+            (
+                "player_attack",
+                &["enemy", "enemy_inside", "ground"],
+            ),
+            ("enemy_attack", &["player", "ground"]),
+            ("ground", &BUILTIN_LAYERS),
+            (
+                "sensor",
+                &["player", "enemy", "enemy_inside", "ground"],
+            ),
+            (
+                "enemy_inside",
+                &["player", "enemy", "player_attack", "ground"],
+            ),
+            ("one_way", &BUILTIN_LAYERS),
+        ];
+        let config = CollisionLayersConfig {
+            layers: BUILTIN_LAYERS.iter().map(|s| s.to_string()).collect(),
+            collides: collides
+                .into_iter()
+                .map(|(layer, others)| {
+                    (
+                        layer.to_string(),
+                        others.iter().map(|s| s.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        };
+        Self::from_config(&config).unwrap()
+    }
+}
+
+impl CollisionLayerRegistry {
+    pub fn from_config(
+        config: &CollisionLayersConfig,
+    ) -> Result<Self, CollisionLayersError> {
+        if config.layers.len() > MAX_LAYERS {
+            return Err(CollisionLayersError::TooMany(
+                config.layers.len(),
+            ));
+        }
+        for (index, expected) in BUILTIN_LAYERS.iter().enumerate() {
+            let got = config.layers.get(index).map(String::as_str);
+            if got != Some(*expected) {
+                return Err(CollisionLayersError::Builtin {
+                    index,
+                    expected,
+                    got: got.unwrap_or_default().to_string(),
+                });
+            }
+        }
+        for (i, name) in config.layers.iter().enumerate() {
+            if config.layers[..i].contains(name) {
+                return Err(CollisionLayersError::Duplicate(
+                    name.clone(),
+                ));
+            }
+        }
+
+        let mut registry = Self {
+            names: config.layers.clone(),
+            filters: vec![Group::empty(); config.layers.len()],
+        };
+        for (layer, others) in config.collides.iter() {
+            let a = registry
+                .index(layer)
+                .ok_or_else(|| CollisionLayersError::Unknown(layer.clone()))?;
+            for other in others {
+                let b = registry.index(other).ok_or_else(|| {
+                    CollisionLayersError::Unknown(other.clone())
+                })?;
+                // symmetric, so both ways
+                registry.filters[a] |= bit(b);
+                registry.filters[b] |= bit(a);
+            }
+        }
+        Ok(registry)
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// The group bit of a layer, or an empty group if there is no such layer
+    pub fn layer(&self, name: &str) -> Group {
+        match self.index(name) {
+            Some(i) => bit(i),
+            None => {
+                warn!("Unknown collision layer '{}'", name);
+                Group::empty()
+            },
+        }
+    }
+
+    /// Everything a layer collides with
+    pub fn filter(&self, name: &str) -> Group {
+        self.index(name)
+            .map(|i| self.filters[i])
+            .unwrap_or(Group::empty())
+    }
+
+    /// The interaction groups for a collider in the given layers
+    pub fn groups(&self, layers: &CollisionLayers) -> InteractionGroups {
+        let mut groups = InteractionGroups::none();
+        for name in layers.layers.iter() {
+            groups.memberships |= self.layer(name);
+            groups.filter |= self.filter(name);
+        }
+        groups
+    }
+
+    /// The interaction groups for a query (ray cast, shape cast, ...) made
+    /// on behalf of a collider in `layer`, that only hits `targets`
+    pub fn query_groups(
+        &self,
+        layer: &str,
+        targets: &[&str],
+    ) -> InteractionGroups {
+        let mut groups = InteractionGroups::none();
+        groups.memberships = self.layer(layer);
+        for name in targets {
+            groups.filter |= self.layer(name);
+        }
+        groups
+    }
+
+    /// The group bits of several layers together
+    pub fn layer_set(&self, names: &[&str]) -> Group {
+        names.iter().fold(Group::empty(), |group, name| {
+            group | self.layer(name)
+        })
+    }
+
+    /// Shorthand for [`Collider::empty`] in the given layers
+    pub fn empty_collider(&self, layers: &CollisionLayers) -> Collider {
+        Collider::empty(self.groups(layers))
+    }
+
+    /// All the layers, in bit order
+    pub fn layers(&self) -> impl Iterator<Item = (&str, Group)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), bit(i)))
+    }
+
+    /// Layer names in a group, like `"enemy | enemy_inside"`
+    pub fn describe(&self, group: Group) -> String {
+        let names = self
+            .names
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| group.contains(bit(*i)))
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(" | ")
+        }
+    }
+
+    /// A distinct debug color for the first layer in a group
+    pub fn color(&self, group: Group) -> Color {
+        let i = group.bits().trailing_zeros();
+        if i as usize >= self.names.len() {
+            return Color::GRAY;
+        }
+        // golden angle, so neighbouring layers get very different hues
+        Color::hsl((i as f32 * 137.5) % 360.0, 0.9, 0.55)
+    }
+}
+
+fn bit(index: usize) -> Group {
+    Group::from_bits_truncate(1 << index)
+}
+
+/// The named layers a collider is in
+///
+/// Resolve to [`InteractionGroups`] with [`CollisionLayerRegistry::groups`]
+/// when spawning the collider. Keep this component on the entity too,
+/// so the groups get updated when the layer config is reloaded.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct CollisionLayers {
+    layers: Vec<&'static str>,
+}
+
+impl CollisionLayers {
+    pub fn new(layer: &'static str) -> Self {
+        Self {
+            layers: vec![layer],
+        }
+    }
+
+    /// Also be a member of another layer
+    pub fn with(mut self, layer: &'static str) -> Self {
+        self.layers.push(layer);
+        self
+    }
+}
+
+fn load_collision_layers(
+    mut ev_asset: EventReader<AssetEvent<CollisionLayersConfig>>,
+    cfgs: Res<Assets<CollisionLayersConfig>>,
+    mut registry: ResMut<CollisionLayerRegistry>,
+) {
+    for ev in ev_asset.read() {
+        if let AssetEvent::Added { id } | AssetEvent::Modified { id } = ev {
+            let Some(cfg) = cfgs.get(*id) else {
+                continue;
+            };
+            match CollisionLayerRegistry::from_config(cfg) {
+                Ok(new) => *registry = new,
+                Err(err) => {
+                    error!(
+                        "Invalid collision layers config: {}",
+                        err
+                    )
+                },
+            }
+        }
+    }
+}
+
+fn reapply_collision_layers(
+    registry: Res<CollisionLayerRegistry>,
+    mut query: Query<(&CollisionLayers, &mut Collider)>,
+) {
+    for (layers, mut collider) in query.iter_mut() {
+        let groups = registry.groups(layers);
+        if collider.0.collision_groups() != groups {
+            collider.0.set_collision_groups(groups);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(collides: &[(&str, &[&str])]) -> CollisionLayersConfig {
+        CollisionLayersConfig {
+            layers: BUILTIN_LAYERS.iter().map(|s| s.to_string()).collect(),
+            collides: collides
+                .iter()
+                .map(|(layer, others)| {
+                    (
+                        layer.to_string(),
+                        others.iter().map(|s| s.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn matrix_is_symmetric() {
+        let registry = CollisionLayerRegistry::from_config(&config(&[
+            ("player", &["enemy", "ground"]),
+            ("sensor", &["player"]),
+        ]))
+        .unwrap();
+        for (a, bit_a) in registry.layers() {
+            for (b, bit_b) in registry.layers() {
+                assert_eq!(
+                    registry.filter(a).contains(bit_b),
+                    registry.filter(b).contains(bit_a),
+                    "{} / {}",
+                    a,
+                    b
+                );
+            }
+        }
+        assert!(registry.filter("ground").contains(registry.layer("player")));
+        assert!(registry.filter("enemy").contains(registry.layer("player")));
+        assert!(!registry.filter("enemy").contains(registry.layer("ground")));
+    }
+
+    #[test]
+    fn reject_invalid_configs() {
+        let err = CollisionLayerRegistry::from_config(&config(&[(
+            "player",
+            &["lava"],
+        )]));
+        assert!(
+            matches!(err, Err(CollisionLayersError::Unknown(l)) if l == "lava")
+        );
+        let err = CollisionLayerRegistry::from_config(&config(&[(
+            "lava",
+            &["player"],
+        )]));
+        assert!(
+            matches!(err, Err(CollisionLayersError::Unknown(l)) if l == "lava")
+        );
+
+        let mut cfg = config(&[]);
+        cfg.layers.swap(0, 1);
+        assert!(matches!(
+            CollisionLayerRegistry::from_config(&cfg),
+            Err(CollisionLayersError::Builtin { index: 0, .. })
+        ));
+        let mut cfg = config(&[]);
+        cfg.layers.push("ground".into());
+        assert!(matches!(
+            CollisionLayerRegistry::from_config(&cfg),
+            Err(CollisionLayersError::Duplicate(_))
+        ));
+    }
+
+    #[test]
+    fn default_matches_asset() {
+        let asset: CollisionLayersConfig = toml::from_str(include_str!(
+            "../../../assets/collision.layers.toml"
+        ))
+        .unwrap();
+        let from_asset = CollisionLayerRegistry::from_config(&asset).unwrap();
+        let default = CollisionLayerRegistry::default();
+        assert_eq!(
+            default.layers().collect::<Vec<_>>(),
+            from_asset.layers().collect::<Vec<_>>()
+        );
+        for (name, _) in default.layers() {
+            assert_eq!(
+                default.filter(name),
+                from_asset.filter(name),
+                "{}: {} / {}",
+                name,
+                default.describe(default.filter(name)),
+                from_asset.describe(from_asset.filter(name)),
+            );
+        }
+    }
+
+    #[test]
+    fn query_groups() {
+        let registry = CollisionLayerRegistry::default();
+        let groups = registry.query_groups("enemy", &["ground", "one_way"]);
+        assert_eq!(
+            groups.memberships,
+            registry.layer("enemy")
+        );
+        assert_eq!(
+            groups.filter,
+            registry.layer_set(&["ground", "one_way"])
+        );
+        let groups = registry.groups(&CollisionLayers::new("enemy_attack"));
+        assert_eq!(
+            groups.filter,
+            registry.layer_set(&["player", "ground"])
+        );
+    }
+}
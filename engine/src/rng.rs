@@ -0,0 +1,50 @@
+//! Seeded random number generation for gameplay
+//!
+//! Anything that affects the simulation should use [`GameRng`] instead of
+//! `thread_rng()`, so that a play session can be reproduced from its seed
+//! (see [`crate::input::replay`]). Purely cosmetic randomness doesn't matter.
+
+use rand::rngs::StdRng;
+
+use crate::prelude::*;
+
+pub struct GameRngPlugin;
+
+impl Plugin for GameRngPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameRng>();
+    }
+}
+
+/// The RNG for everything that affects gameplay
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameRng {
+    seed: u64,
+    #[deref]
+    rng: StdRng,
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(thread_rng().gen())
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Get the seed that the RNG was (last) initialized with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restart the random sequence from a new seed
+    pub fn reseed(&mut self, seed: u64) {
+        *self = Self::new(seed);
+    }
+}
@@ -6,6 +6,7 @@ use bevy::ecs::system::{StaticSystemParam, SystemParam};
 use crate::assets::config::DynamicConfigValue;
 use crate::assets::script::ScriptConfig;
 use crate::prelude::*;
+use crate::time::TickRateChanged;
 
 pub mod common;
 pub mod label;
@@ -74,6 +75,10 @@ impl ScriptAppExt for App {
                 script_driver_system::<T>.in_set(ScriptSet::Run),
             ),
         );
+        self.add_systems(
+            Update,
+            script_tick_rate_system::<T>.in_set(GameTickSet::Pre),
+        );
         self
     }
 }
@@ -164,6 +169,11 @@ pub trait ScriptTracker: Default + Send + Sync + 'static {
         Default::default()
     }
     fn clear_slots(&mut self, _timing: ScriptActionTiming) {}
+    /// The game tick rate has changed, at `tick`
+    ///
+    /// Anything still pending should be rescaled by `ratio` (new rate / old rate),
+    /// so that it happens at the same real time as before.
+    fn rescale_ticks(&mut self, _tick: u64, _ratio: f64) {}
     fn do_start(
         &mut self,
         _entity: Entity,
@@ -435,6 +445,42 @@ fn script_changeover_system<T: ScriptAsset>(
     }
 }
 
+fn script_tick_rate_system<T: ScriptAsset>(
+    mut evr_rate: EventReader<TickRateChanged>,
+    mut q_script: Query<&mut ScriptPlayer<T>>,
+) {
+    for ev in evr_rate.read() {
+        for mut player in &mut q_script {
+            match &mut player.state {
+                ScriptPlayerState::Starting { runtime }
+                | ScriptPlayerState::Playing { runtime }
+                | ScriptPlayerState::Stopping { runtime }
+                | ScriptPlayerState::ChangingHandle {
+                    old_runtime: runtime,
+                    ..
+                }
+                | ScriptPlayerState::ChangingKey {
+                    old_runtime: runtime,
+                    ..
+                } => {
+                    runtime.tracker.rescale_ticks(ev.tick, ev.ratio());
+                },
+                ScriptPlayerState::PrePlayHandle {
+                    old_runtime: Some(runtime),
+                    ..
+                }
+                | ScriptPlayerState::PrePlayKey {
+                    old_runtime: Some(runtime),
+                    ..
+                } => {
+                    runtime.tracker.rescale_ticks(ev.tick, ev.ratio());
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
 fn script_driver_system<T: ScriptAsset>(
     mut q_script: Query<(Entity, &mut ScriptPlayer<T>)>,
     mut params: ParamSet<(
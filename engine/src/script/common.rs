@@ -2,6 +2,9 @@ use bevy::ecs::system::lifetimeless::*;
 
 use super::*;
 use crate::assets::script::*;
+use crate::audio::bus::AudioBus;
+use crate::audio::music::{MusicClock, MusicPlayer};
+use crate::audio::spatial::SpatialAudio;
 use crate::audio::PrecisionMixerControl;
 use crate::data::OneOrMany;
 use crate::script::label::EntityLabels;
@@ -24,6 +27,7 @@ pub struct CommonScriptTracker {
     tick_actions: Vec<(u64, ActionId)>,
     time_actions: Vec<(Duration, ActionId)>,
     tickquant_actions: Vec<(Quant, ActionId)>,
+    beatquant_actions: Vec<(Quant, ActionId)>,
     slot_enable_actions: HashMap<String, Vec<ActionId>>,
     slot_disable_actions: HashMap<String, Vec<ActionId>>,
     start_actions: Vec<ActionId>,
@@ -65,7 +69,11 @@ impl ScriptTracker for CommonScriptTracker {
     );
     type RunIf = CommonScriptRunIf;
     type Settings = CommonScriptSettings;
-    type UpdateParam = (SRes<Time>, SRes<GameTime>);
+    type UpdateParam = (
+        SRes<Time>,
+        SRes<GameTime>,
+        SRes<MusicClock>,
+    );
 
     fn init(
         &mut self,
@@ -142,6 +150,9 @@ impl ScriptTracker for CommonScriptTracker {
             CommonScriptRunIf::TickQuant(quant) => {
                 self.tickquant_actions.push((*quant, action_id));
             },
+            CommonScriptRunIf::BeatQuant(quant) => {
+                self.beatquant_actions.push((*quant, action_id));
+            },
             CommonScriptRunIf::Millis(millis) => {
                 match millis {
                     OneOrMany::Single(millis) => {
@@ -212,7 +223,7 @@ impl ScriptTracker for CommonScriptTracker {
         &mut self,
         _entity: Entity,
         _settings: &Self::Settings,
-        (time, game_time): &mut <Self::UpdateParam as SystemParam>::Item<
+        (time, game_time, music): &mut <Self::UpdateParam as SystemParam>::Item<
             '_,
             '_,
         >,
@@ -265,9 +276,21 @@ impl ScriptTracker for CommonScriptTracker {
                 });
             }
         }
+        // check any beatquant actions
+        if music.is_new_beat {
+            for (quant, action_id) in &self.beatquant_actions {
+                if quant.check(music.beat as i64) {
+                    queue.push(QueuedAction {
+                        timing: ScriptActionTiming::Tick(game_time.tick()),
+                        action: *action_id,
+                    });
+                }
+            }
+        }
         if self.next_time_id >= self.time_actions.len()
             && self.next_tick_id >= self.tick_actions.len()
             && self.tickquant_actions.is_empty()
+            && self.beatquant_actions.is_empty()
         {
             ScriptUpdateResult::Finished
         } else {
@@ -287,7 +310,7 @@ impl ScriptTracker for CommonScriptTracker {
         &mut self,
         _entity: Entity,
         _settings: &Self::Settings,
-        (_time, game_time): &mut <Self::UpdateParam as SystemParam>::Item<
+        (_time, game_time, _music): &mut <Self::UpdateParam as SystemParam>::Item<
             '_,
             '_,
         >,
@@ -307,7 +330,7 @@ impl ScriptTracker for CommonScriptTracker {
         &mut self,
         _entity: Entity,
         _settings: &Self::Settings,
-        (_time, game_time): &mut <Self::UpdateParam as SystemParam>::Item<
+        (_time, game_time, _music): &mut <Self::UpdateParam as SystemParam>::Item<
             '_,
             '_,
         >,
@@ -358,6 +381,28 @@ impl ScriptTracker for CommonScriptTracker {
         self.slots_enabled.contains(slot)
     }
 
+    fn rescale_ticks(&mut self, tick: u64, ratio: f64) {
+        let rescale =
+            |remaining: u64| (remaining as f64 * ratio).round() as u64;
+        // offsets of pending actions are relative to `start_tick`;
+        // only the part that hasn't elapsed yet should be rescaled
+        let elapsed = tick.saturating_sub(self.start_tick);
+        for (offset, _) in self.tick_actions[self.next_tick_id..].iter_mut() {
+            if *offset > elapsed {
+                *offset = elapsed + rescale(*offset - elapsed);
+            }
+        }
+        for (delayed_tick, _) in self.q_delayed.iter_mut() {
+            if *delayed_tick > tick {
+                *delayed_tick = tick + rescale(*delayed_tick - tick);
+            }
+        }
+        // tick quants are checked against the absolute tick
+        for (quant, _) in self.tickquant_actions.iter_mut() {
+            *quant = quant.rescale(tick as i64, ratio);
+        }
+    }
+
     fn take_slots(&mut self, timing: ScriptActionTiming) -> HashSet<String> {
         for slot in self.slots_enabled.iter() {
             if let Some(actions) = self.slot_disable_actions.get(slot) {
@@ -390,7 +435,11 @@ impl ScriptRunIf for CommonScriptRunIf {
 }
 
 impl ScriptActionParams for CommonScriptParams {
-    type ShouldRunParam = (SRes<Time>, SRes<GameTime>);
+    type ShouldRunParam = (
+        SRes<Time>,
+        SRes<GameTime>,
+        SResMut<GameRng>,
+    );
     type Tracker = CommonScriptTracker;
 
     fn should_run(
@@ -398,7 +447,7 @@ impl ScriptActionParams for CommonScriptParams {
         _entity: Entity,
         tracker: &mut Self::Tracker,
         action_id: ActionId,
-        (_time, game_time): &mut <Self::ShouldRunParam as SystemParam>::Item<
+        (_time, game_time, rng): &mut <Self::ShouldRunParam as SystemParam>::Item<
             '_,
             '_,
         >,
@@ -499,8 +548,8 @@ impl ScriptActionParams for CommonScriptParams {
         {
             return Err(ScriptUpdateResult::NormalRun);
         }
+        // from the game's RNG, so that replays take the same branches
         if let Some(rng_pct) = &self.rng_pct {
-            let mut rng = thread_rng();
             if !rng.gen_bool((*rng_pct as f64 / 100.0).clamp(0.0, 1.0)) {
                 return Err(ScriptUpdateResult::NormalRun);
             }
@@ -518,6 +567,9 @@ impl ScriptAction for CommonScriptAction {
         SRes<EntityLabels>,
         SCommands,
         SQuery<&'static PrecisionMixerControl>,
+        SpatialAudio<'static, 'static>,
+        SResMut<MusicPlayer>,
+        SResMut<GameRng>,
     );
     type Tracker = CommonScriptTracker;
 
@@ -534,6 +586,9 @@ impl ScriptAction for CommonScriptAction {
             ref elabels,
             ref mut commands,
             q_mixer,
+            ref mut spatial,
+            ref mut music,
+            ref mut rng,
         ): &mut <Self::Param as SystemParam>::Item<'_, '_>,
     ) -> ScriptUpdateResult {
         match self {
@@ -583,10 +638,24 @@ impl ScriptAction for CommonScriptAction {
                 asset_key,
                 volume,
                 pan,
+                positional,
+                bus,
+                pitch,
+                pitch_variation,
+                sidechain,
             } => {
                 use rand::seq::SliceRandom;
-                let volume = volume.unwrap_or(1.0);
-                let pan = pan.unwrap_or(0.0);
+                let bus =
+                    bus.unwrap_or_else(|| AudioBus::from_asset_key(asset_key));
+                let base_volume = volume.unwrap_or(1.0);
+                let (volume, pan) = if *positional {
+                    // without a listener, play it as if it wasn't positional
+                    spatial
+                        .volume_pan(entity, base_volume)
+                        .unwrap_or((base_volume, pan.unwrap_or(0.0)))
+                } else {
+                    (base_volume, pan.unwrap_or(0.0))
+                };
                 let sounds: Vec<&AudioSource> = preloaded
                     .get_multi_asset(asset_key)
                     .unwrap_or(&[])
@@ -595,46 +664,105 @@ impl ScriptAction for CommonScriptAction {
                         ass_audio.get(h_untyped.id().typed::<AudioSource>())
                     })
                     .collect();
-                if let Some(sound) = sounds.choose(&mut rand::thread_rng()) {
+                if let Some(sound) = sounds.choose(&mut ***rng) {
                     let ctl = q_mixer.single();
-                    match timing {
+                    let voice = match timing {
                         ScriptActionTiming::Unknown => {
                             ctl.controller.play_immediately(
                                 sound.decoder(),
+                                bus,
                                 volume,
                                 pan,
-                            );
+                            )
                         },
                         ScriptActionTiming::UnknownTick => {
                             ctl.controller.play_at_tick(
                                 gt.tick() as u32,
                                 0,
                                 sound.decoder(),
+                                bus,
                                 volume,
                                 pan,
-                            );
+                            )
                         },
                         ScriptActionTiming::Time(time) => {
                             ctl.controller.play_at_time(
                                 time,
                                 sound.decoder(),
+                                bus,
                                 volume,
                                 pan,
-                            );
+                            )
                         },
                         ScriptActionTiming::Tick(tick) => {
                             ctl.controller.play_at_tick(
                                 tick as u32,
                                 0,
                                 sound.decoder(),
+                                bus,
                                 volume,
                                 pan,
-                            );
+                            )
                         },
+                    };
+                    let variation = pitch_variation.unwrap_or(0.0).abs();
+                    let pitch = pitch.unwrap_or(1.0)
+                        * (1.0 + rng.gen_range(-variation..=variation));
+                    voice.set_pitch(pitch);
+                    voice.set_sidechain(*sidechain);
+                    if *positional {
+                        spatial.follow(voice, entity, base_volume);
                     }
                 }
                 ScriptUpdateResult::NormalRun
             },
+            CommonScriptAction::PlayMusic { asset_key, sync } => {
+                music.play(asset_key.as_str(), *sync);
+                ScriptUpdateResult::NormalRun
+            },
+            CommonScriptAction::StopMusic { sync } => {
+                music.stop(*sync);
+                ScriptUpdateResult::NormalRun
+            },
+            CommonScriptAction::PlayStinger {
+                asset_key,
+                volume,
+                sync,
+            } => {
+                music.stinger(
+                    asset_key.as_str(),
+                    volume.unwrap_or(1.0),
+                    *sync,
+                );
+                ScriptUpdateResult::NormalRun
+            },
+            CommonScriptAction::SetAudioParam {
+                bus,
+                param,
+                value,
+                fade,
+            } => {
+                let ctl = q_mixer.single();
+                let start = match timing {
+                    ScriptActionTiming::Unknown => {
+                        ctl.controller.sample_count()
+                    },
+                    ScriptActionTiming::UnknownTick => {
+                        ctl.controller.tick_to_sample(gt.tick() as f64)
+                    },
+                    ScriptActionTiming::Time(time) => {
+                        ctl.controller.time_to_sample(time)
+                    },
+                    ScriptActionTiming::Tick(tick) => {
+                        ctl.controller.tick_to_sample(tick as f64)
+                    },
+                };
+                let len = fade.unwrap_or(0.0).max(0.0)
+                    * ctl.controller.sample_rate() as f32;
+                ctl.controller
+                    .ramp_bus_param(*bus, *param, *value, start, len as i64);
+                ScriptUpdateResult::NormalRun
+            },
         }
     }
 }
@@ -837,6 +965,11 @@ impl<T: ScriptTracker> ScriptTracker for ExtendedScriptTracker<T> {
         self.common.clear_slots(timing);
         self.extended.clear_slots(timing);
     }
+
+    fn rescale_ticks(&mut self, tick: u64, ratio: f64) {
+        self.common.rescale_ticks(tick, ratio);
+        self.extended.rescale_ticks(tick, ratio);
+    }
 }
 
 impl<T: ScriptRunIf> ScriptRunIf for ExtendedScriptRunIf<T> {
@@ -949,3 +1082,33 @@ impl ScriptAsset for Script {
         builder
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rescale_ticks() {
+        let mut tracker =
+            CommonScriptTracker::new_with_offset(100, Duration::ZERO);
+        tracker.tick_actions = vec![(5, 0), (20, 1), (30, 2)];
+        tracker.next_tick_id = 1;
+        tracker.q_delayed = vec![(104, 3), (116, 4)];
+        tracker.tickquant_actions = vec![(Quant { n: 8, offset: 3 }, 5)];
+        // half the tick rate, 10 ticks in
+        tracker.rescale_ticks(110, 0.5);
+        assert_eq!(
+            tracker.tick_actions,
+            vec![(5, 0), (15, 1), (20, 2)]
+        );
+        assert_eq!(
+            tracker.q_delayed,
+            vec![(104, 3), (113, 4)]
+        );
+        // it was going to run at 115, every 8 ticks
+        let quant = tracker.tickquant_actions[0].0;
+        assert_eq!(quant.n, 4);
+        assert!(quant.check(113));
+        assert!(quant.check(117));
+    }
+}
@@ -1,3 +1,7 @@
+use bevy::diagnostic::{
+    Diagnostic, DiagnosticMeasurement, DiagnosticPath, DiagnosticsStore,
+    RegisterDiagnostic,
+};
 use bevy::ecs::schedule::{ExecutorKind, ScheduleLabel};
 
 use crate::prelude::*;
@@ -15,6 +19,13 @@ impl Plugin for GameTimePlugin {
             s.set_executor_kind(ExecutorKind::SingleThreaded);
         });
         app.init_resource::<GameTime>();
+        app.init_resource::<TickRateDependents>();
+        app.add_event::<TickRateChanged>();
+        app.register_diagnostic(
+            Diagnostic::new(GAMETICK_TIME).with_suffix("ms"),
+        );
+        app.register_diagnostic(Diagnostic::new(GAMETICK_COUNT));
+        app.register_diagnostic(Diagnostic::new(GAMETICK_DROPPED));
         app.add_systems(
             Update,
             (
@@ -44,12 +55,26 @@ pub enum GameTickSet {
     Post,
 }
 
+/// How long it took to run a single game tick (`GameTickUpdate` + `GameTickPost`)
+pub const GAMETICK_TIME: DiagnosticPath =
+    DiagnosticPath::const_new("gametick/time");
+/// How many game ticks were run in a Bevy frame update
+pub const GAMETICK_COUNT: DiagnosticPath =
+    DiagnosticPath::const_new("gametick/count");
+/// How many game ticks were dropped in a Bevy frame update
+pub const GAMETICK_DROPPED: DiagnosticPath =
+    DiagnosticPath::const_new("gametick/dropped");
+
 /// This is when old "game tick events" are cleared (in `GameTickUpdate` schedule)
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct GameTickEventClearSet;
 
 pub trait GameTimeAppExt {
     fn add_gametick_event<T: Event>(&mut self) -> &mut Self;
+    /// Declare that something relies on tick counts tuned for [`BASE_TICK_RATE`]
+    ///
+    /// A warning is logged for it whenever the tick rate is changed away from that.
+    fn warn_on_tick_rate_change(&mut self, what: &'static str) -> &mut Self;
 }
 
 impl GameTimeAppExt for App {
@@ -65,6 +90,14 @@ impl GameTimeAppExt for App {
         }
         self
     }
+
+    fn warn_on_tick_rate_change(&mut self, what: &'static str) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(TickRateDependents::default)
+            .0
+            .push(what);
+        self
+    }
 }
 
 fn minimal_event_update_system<T: Event>(mut events: ResMut<Events<T>>) {
@@ -80,26 +113,97 @@ pub struct GameTickUpdate;
 #[derive(ScheduleLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct GameTickPost;
 
+/// The tick rate that all the tick counts in the game (configs, scripts,
+/// gameplay code) were designed for
+pub const BASE_TICK_RATE: f64 = 96.0;
+
+/// Sent (in [`GameTickSet::Pre`]) when the tick rate was changed with [`GameTime::set_tick_rate`]
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TickRateChanged {
+    pub old_hz: f64,
+    pub new_hz: f64,
+    /// The tick at which the change happened
+    pub tick: u64,
+}
+
+impl TickRateChanged {
+    /// Multiply a number of ticks by this to get the same real duration at the new rate
+    pub fn ratio(&self) -> f64 {
+        self.new_hz / self.old_hz
+    }
+}
+
+/// Things that rely on tick counts, to warn about when the tick rate changes
+#[derive(Resource, Default)]
+struct TickRateDependents(Vec<&'static str>);
+
+/// A temporary override of the rate at which game ticks are produced
+///
+/// Used for hit-stop (`scale` of `0.0`) and slow motion. The duration counts
+/// real (unscaled) time, so that a hit-stop ends even though no ticks run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeScaleRequest {
+    /// Multiplier for the tick rate while the request is active
+    pub scale: f64,
+    /// How much (real) time is left until the request expires
+    pub remaining: Duration,
+    /// If multiple requests are active, the highest priority one wins
+    pub priority: u8,
+}
+
+/// What to do when a Bevy frame update needs more than `max_ticks_per_frame` ticks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpPolicy {
+    /// Throw away the extra ticks; the game falls behind real time
+    #[default]
+    Drop,
+    /// Keep the extra ticks and run them over the following frames
+    Spread,
+}
+
 #[derive(Resource, Debug)]
 pub struct GameTime {
     /// The time base rate
+    ///
+    /// To change it while the game is running, use [`GameTime::set_tick_rate`].
     pub hz: f64,
+    /// Upper bound on how many ticks can run in one Bevy frame update
+    ///
+    /// Prevents the game from trying to fast-forward through hundreds of ticks
+    /// after a hitch (or a breakpoint), which would only make things worse.
+    /// `None` means unlimited.
+    pub max_ticks_per_frame: Option<u64>,
+    /// How to deal with ticks beyond `max_ticks_per_frame`
+    pub catch_up: CatchUpPolicy,
     tick: u64,
     new_ticks: u64,
     total_ticks: u64,
     overstep: f64,
     last_update: Duration,
+    time_scale: f64,
+    scale_requests: Vec<TimeScaleRequest>,
+    dropped_ticks: u64,
+    last_dropped_ticks: u64,
+    /// (old hz, tick) of a tick rate change that hasn't been announced yet
+    rate_change: Option<(f64, u64)>,
 }
 
 impl Default for GameTime {
     fn default() -> Self {
         Self {
-            hz: 96.0,
+            hz: BASE_TICK_RATE,
+            max_ticks_per_frame: Some(12),
+            catch_up: CatchUpPolicy::Drop,
             tick: 0,
             new_ticks: 0,
             total_ticks: 0,
             overstep: 0.0,
             last_update: Duration::new(0, 0),
+            time_scale: 1.0,
+            scale_requests: Vec::new(),
+            dropped_ticks: 0,
+            last_dropped_ticks: 0,
+            rate_change: None,
         }
     }
 }
@@ -122,10 +226,29 @@ impl GameTime {
     }
 
     /// Get the number of new ticks to be simulated this Bevy frame update
+    ///
+    /// Never more than `max_ticks_per_frame`.
     pub fn new_ticks(&self) -> u64 {
         self.new_ticks
     }
 
+    /// Get the number of ticks that are waiting to be run in later frame updates
+    ///
+    /// Only ever non-zero with `CatchUpPolicy::Spread`.
+    pub fn deferred_ticks(&self) -> u64 {
+        self.total_ticks - self.tick - self.new_ticks
+    }
+
+    /// Get the total number of ticks that were dropped because of `max_ticks_per_frame`
+    pub fn dropped_ticks(&self) -> u64 {
+        self.dropped_ticks
+    }
+
+    /// Get the number of ticks that were dropped during this Bevy frame update
+    pub fn last_dropped_ticks(&self) -> u64 {
+        self.last_dropped_ticks
+    }
+
     /// Get the total target number of ticks as of this Bevy frame update
     pub fn total_ticks(&self) -> u64 {
         self.total_ticks
@@ -142,25 +265,141 @@ impl GameTime {
     }
 
     /// Reset tick counters to zero, set the last update to now, keep the `hz` value
+    ///
+    /// Also keeps the global time scale and catch-up settings, but drops any
+    /// pending hit-stop/slow motion.
     pub fn reset(&mut self, now: Duration) {
         *self = Self {
             hz: self.hz,
+            max_ticks_per_frame: self.max_ticks_per_frame,
+            catch_up: self.catch_up,
+            time_scale: self.time_scale,
             last_update: now,
             ..Default::default()
         };
     }
 
+    /// Change the tick rate while the game is running
+    ///
+    /// Unlike setting `hz` directly, this sends a [`TickRateChanged`] event, so that
+    /// script timers and audio scheduling can adjust to keep their real-time durations.
+    pub fn set_tick_rate(&mut self, hz: f64) {
+        if hz <= 0.0 || hz == self.hz {
+            return;
+        }
+        // if changed multiple times before the event is sent, keep the original rate
+        let (old_hz, tick) = self.rate_change.unwrap_or((self.hz, self.tick));
+        self.rate_change = Some((old_hz, tick));
+        self.hz = hz;
+    }
+
+    /// Get the global time scale (ignoring any temporary requests)
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    /// Set the global time scale
+    ///
+    /// `1.0` is normal speed, `0.5` is half speed, `0.0` pauses tick production.
+    pub fn set_time_scale(&mut self, scale: f64) {
+        self.time_scale = scale.max(0.0);
+    }
+
+    /// Get the time scale that is currently in effect
+    ///
+    /// This is the scale of the highest priority active request (the most
+    /// recent one, if there is a tie), or the global time scale if there are none.
+    pub fn effective_time_scale(&self) -> f64 {
+        self.scale_requests
+            .iter()
+            .max_by_key(|r| r.priority)
+            .map(|r| r.scale)
+            .unwrap_or(self.time_scale)
+    }
+
+    /// Are ticks currently not being produced at all?
+    pub fn is_frozen(&self) -> bool {
+        self.effective_time_scale() == 0.0
+    }
+
+    /// Temporarily change the rate of game ticks, for `duration` of real time
+    ///
+    /// Takes effect starting from the next Bevy frame update.
+    pub fn request_time_scale(
+        &mut self,
+        scale: f64,
+        duration: Duration,
+        priority: u8,
+    ) {
+        self.scale_requests.push(TimeScaleRequest {
+            scale: scale.max(0.0),
+            remaining: duration,
+            priority,
+        });
+    }
+
+    /// Freeze game ticks for `duration` of real time
+    ///
+    /// Bevy's `Update` keeps running, so UI and graphics are unaffected.
+    pub fn request_hitstop(&mut self, duration: Duration, priority: u8) {
+        self.request_time_scale(0.0, duration, priority);
+    }
+
+    /// Get the currently active temporary time scale requests
+    pub fn time_scale_requests(&self) -> &[TimeScaleRequest] {
+        &self.scale_requests
+    }
+
+    /// Cancel all hit-stop/slow motion requests
+    pub fn clear_time_scale_requests(&mut self) {
+        self.scale_requests.clear();
+    }
+
     /// Every Bevy frame, this gets called to advance the tick counters
     pub fn update(&mut self, time: &Time) {
         let now = time.elapsed();
         let delta = now - self.last_update;
         self.last_update = now;
 
-        let delta_f64 = delta.as_secs_f64();
+        // split the frame wherever a request expires,
+        // so that each part of it advances at the correct rate
+        let mut delta_left = delta;
+        let mut delta_f64 = 0.0;
+        while delta_left > Duration::ZERO {
+            let step = self
+                .scale_requests
+                .iter()
+                .map(|r| r.remaining)
+                .min()
+                .unwrap_or(delta_left)
+                .min(delta_left);
+            delta_f64 += step.as_secs_f64() * self.effective_time_scale();
+            for request in self.scale_requests.iter_mut() {
+                request.remaining -= step;
+            }
+            self.scale_requests.retain(|r| r.remaining > Duration::ZERO);
+            delta_left -= step;
+        }
+
         let new_ticks = delta_f64 * self.hz + self.overstep;
         self.total_ticks += new_ticks as u64;
         self.overstep = new_ticks.fract();
         self.new_ticks = self.total_ticks - self.tick;
+        self.last_dropped_ticks = 0;
+
+        if let Some(max) = self.max_ticks_per_frame {
+            if self.new_ticks > max {
+                match self.catch_up {
+                    CatchUpPolicy::Drop => {
+                        self.last_dropped_ticks = self.new_ticks - max;
+                        self.dropped_ticks += self.last_dropped_ticks;
+                        self.total_ticks = self.tick + max;
+                    },
+                    CatchUpPolicy::Spread => {},
+                }
+                self.new_ticks = max;
+            }
+        }
     }
 
     /// returns the amount of time since start; ie: ticks * tick length
@@ -175,20 +414,78 @@ impl GameTime {
 }
 
 /// Update `GameTime` every frame
-pub fn update_gametime(time: Res<Time>, mut gametime: ResMut<GameTime>) {
+pub fn update_gametime(
+    time: Res<Time>,
+    mut gametime: ResMut<GameTime>,
+    mut evw_rate: EventWriter<TickRateChanged>,
+    dependents: Res<TickRateDependents>,
+) {
+    if let Some((old_hz, tick)) = gametime.rate_change.take() {
+        let new_hz = gametime.hz;
+        if new_hz != old_hz {
+            info!(
+                "Tick rate changed: {} Hz -> {} Hz",
+                old_hz, new_hz
+            );
+            if new_hz != BASE_TICK_RATE {
+                for what in dependents.0.iter() {
+                    warn!(
+                        "{} relies on tick counts tuned for {} Hz and will behave differently at {} Hz!",
+                        what, BASE_TICK_RATE, new_hz
+                    );
+                }
+            }
+            evw_rate.send(TickRateChanged {
+                old_hz,
+                new_hz,
+                tick,
+            });
+        }
+    }
     gametime.update(&time);
 }
 
 /// Our alternative to Bevy's fixed timestep, based on `GameTime`
+///
+/// Runs `new_ticks` ticks, so never more than `max_ticks_per_frame`.
 pub fn run_gametickupdate_schedule(world: &mut World) {
+    let gametime = world.resource::<GameTime>();
+    let end = gametime.tick + gametime.new_ticks;
+    let dropped = gametime.last_dropped_ticks;
+    let mut count = 0;
     loop {
         let gametime = world.resource::<GameTime>();
-        if gametime.tick >= gametime.total_ticks {
+        if gametime.tick >= end {
             break;
         }
+        let start = Instant::now();
         world.run_schedule(GameTickUpdate);
         world.run_schedule(GameTickPost);
         world.resource_mut::<GameTime>().tick += 1;
+        count += 1;
+        add_diagnostic_measurement(
+            world,
+            &GAMETICK_TIME,
+            start.elapsed().as_secs_f64() * 1000.0,
+        );
+    }
+    add_diagnostic_measurement(world, &GAMETICK_COUNT, count as f64);
+    add_diagnostic_measurement(world, &GAMETICK_DROPPED, dropped as f64);
+}
+
+fn add_diagnostic_measurement(
+    world: &mut World,
+    path: &DiagnosticPath,
+    value: f64,
+) {
+    let Some(mut store) = world.get_resource_mut::<DiagnosticsStore>() else {
+        return;
+    };
+    if let Some(diagnostic) = store.get_mut(path).filter(|d| d.is_enabled) {
+        diagnostic.add_measurement(DiagnosticMeasurement {
+            time: Instant::now(),
+            value,
+        });
     }
 }
 
@@ -198,3 +495,87 @@ pub fn at_tick_multiples(quant: Quant) -> impl FnMut(Res<GameTime>) -> bool {
         (gametime.tick() + quant.offset as u64) % quant.n as u64 == 0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn advance(gt: &mut GameTime, time: &mut Time, millis: u64) {
+        time.advance_by(Duration::from_millis(millis));
+        gt.update(time);
+        gt.tick += gt.new_ticks;
+    }
+
+    #[test]
+    fn time_scale() {
+        let mut time = Time::<()>::default();
+        let mut gt = GameTime::new(100.0);
+        advance(&mut gt, &mut time, 100);
+        assert_eq!(gt.total_ticks(), 10);
+        gt.set_time_scale(0.5);
+        advance(&mut gt, &mut time, 100);
+        assert_eq!(gt.new_ticks(), 5);
+        gt.set_time_scale(0.0);
+        advance(&mut gt, &mut time, 100);
+        assert_eq!(gt.new_ticks(), 0);
+    }
+
+    #[test]
+    fn hitstop() {
+        let mut time = Time::<()>::default();
+        let mut gt = GameTime::new(100.0);
+        gt.request_hitstop(Duration::from_millis(50), 0);
+        assert!(gt.is_frozen());
+        // the request expires partway through the frame
+        advance(&mut gt, &mut time, 100);
+        assert_eq!(gt.new_ticks(), 5);
+        assert!(!gt.is_frozen());
+        assert!(gt.time_scale_requests().is_empty());
+    }
+
+    #[test]
+    fn time_scale_priority() {
+        let mut time = Time::<()>::default();
+        let mut gt = GameTime::new(100.0);
+        gt.request_time_scale(0.5, Duration::from_millis(200), 1);
+        gt.request_hitstop(Duration::from_millis(100), 0);
+        assert_eq!(gt.effective_time_scale(), 0.5);
+        gt.request_hitstop(Duration::from_millis(100), 1);
+        assert_eq!(gt.effective_time_scale(), 0.0);
+        advance(&mut gt, &mut time, 200);
+        assert_eq!(gt.new_ticks(), 5);
+        assert_eq!(gt.effective_time_scale(), 1.0);
+    }
+
+    #[test]
+    fn catch_up_drop() {
+        let mut time = Time::<()>::default();
+        let mut gt = GameTime::new(100.0);
+        gt.max_ticks_per_frame = Some(5);
+        gt.catch_up = CatchUpPolicy::Drop;
+        advance(&mut gt, &mut time, 100);
+        assert_eq!(gt.new_ticks(), 5);
+        assert_eq!(gt.last_dropped_ticks(), 5);
+        advance(&mut gt, &mut time, 50);
+        assert_eq!(gt.new_ticks(), 5);
+        assert_eq!(gt.last_dropped_ticks(), 0);
+        assert_eq!(gt.dropped_ticks(), 5);
+        assert_eq!(gt.tick(), 10);
+    }
+
+    #[test]
+    fn catch_up_spread() {
+        let mut time = Time::<()>::default();
+        let mut gt = GameTime::new(100.0);
+        gt.max_ticks_per_frame = Some(5);
+        gt.catch_up = CatchUpPolicy::Spread;
+        advance(&mut gt, &mut time, 100);
+        assert_eq!(gt.new_ticks(), 5);
+        assert_eq!(gt.deferred_ticks(), 5);
+        advance(&mut gt, &mut time, 0);
+        assert_eq!(gt.new_ticks(), 5);
+        assert_eq!(gt.deferred_ticks(), 0);
+        assert_eq!(gt.dropped_ticks(), 0);
+        assert_eq!(gt.tick(), 10);
+    }
+}
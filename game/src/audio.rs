@@ -1,62 +1,482 @@
+use std::path::PathBuf;
+
+use strum::IntoEnumIterator;
+use theseeker_engine::audio::bus::{AudioBus, AudioSettings};
+use theseeker_engine::audio::dsp::AudioParam;
+use theseeker_engine::audio::music::{MusicPlayer, MusicSync};
 use theseeker_engine::audio::PrecisionMixerControl;
 
 use crate::prelude::*;
 
+pub mod calibration;
+
 pub struct AudioPlugin;
 
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut App) {
+        app.register_clicommand_noargs("volume", cli_volume_noargs);
+        app.register_clicommand_args("volume", cli_volume_args);
+        app.register_clicommand_args("mute", cli_mute);
+        app.register_clicommand_args("music", cli_music);
+        app.register_clicommand_args("fx", cli_fx);
+        app.register_clicommand_noargs("audio_sync", cli_audio_sync);
+        app.register_clicommand_noargs(
+            "audio_latency",
+            cli_audio_latency_noargs,
+        );
+        app.register_clicommand_args("audio_latency", cli_audio_latency_args);
+        app.add_plugins(calibration::AudioCalibrationPlugin);
+        app.init_resource::<AudioSync>();
+        app.init_resource::<Muffle>();
+        app.add_systems(Startup, load_audio_settings);
         app.add_systems(PreUpdate, manage_audio_delay);
+        app.add_systems(
+            Update,
+            (
+                save_audio_settings.run_if(resource_changed::<AudioSettings>),
+                muffle_audio,
+            ),
+        );
+    }
+}
+
+/// Where the user's audio settings are kept between runs
+fn audio_settings_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "TheSeeker")
+        .map(|dirs| dirs.config_dir().join("audio.toml"))
+}
+
+fn load_audio_settings(mut settings: ResMut<AudioSettings>) {
+    let Some(path) = audio_settings_path() else {
+        return;
+    };
+    if !path.exists() {
+        return;
+    }
+    match AudioSettings::load(&path) {
+        Ok(loaded) => *settings = loaded,
+        Err(e) => error!("{:#}", e),
+    }
+}
+
+fn save_audio_settings(settings: Res<AudioSettings>) {
+    // nothing to save, it was just loaded (or is the default)
+    if settings.is_added() {
+        return;
+    }
+    let Some(path) = audio_settings_path() else {
+        return;
+    };
+    if let Err(e) = settings.save(&path) {
+        error!("{:#}", e);
+    }
+}
+
+/// Reasons to muffle everything, besides being paused
+///
+/// Each is kept up to date by the part of the game it comes from (see
+/// `water` and `game_over`), so this module doesn't need to know how.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Muffle {
+    /// The player is below the surface of some water
+    pub underwater: bool,
+    /// The game over screen is up
+    pub game_over: bool,
+}
+
+impl Muffle {
+    fn any(&self) -> bool {
+        self.underwater || self.game_over
+    }
+}
+
+/// Put a low-pass on the master bus while paused or for any [`Muffle`]
+fn muffle_audio(
+    q_mixer: Query<&PrecisionMixerControl>,
+    reasons: Res<Muffle>,
+    app_state: Res<State<AppState>>,
+    game_state: Res<State<GameState>>,
+    mut muffled: Local<bool>,
+) {
+    // low-pass cutoff on the master bus when muffled (in Hz)
+    const CUTOFF: f32 = 400.0;
+    // seconds to fully fade in or out
+    const FADE: f32 = 0.25;
+
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let muffle = *app_state.get() == AppState::InGame
+        && (*game_state.get() == GameState::Paused || reasons.any());
+    if muffle == *muffled {
+        return;
+    }
+    *muffled = muffle;
+    let cutoff = if muffle {
+        CUTOFF
+    } else {
+        AudioParam::Lowpass.default_value()
+    };
+    ctl.controller.ramp_bus_param(
+        AudioBus::Master,
+        AudioParam::Lowpass,
+        cutoff,
+        ctl.controller.sample_count(),
+        (FADE * ctl.controller.sample_rate() as f32) as i64,
+    );
+}
+
+fn cli_volume_noargs(settings: Res<AudioSettings>) {
+    for bus in AudioBus::iter() {
+        let bus_settings = settings.bus(bus);
+        let name: &str = bus.into();
+        info!(
+            "{}: {}{}",
+            name,
+            bus_settings.volume,
+            if bus_settings.muted { " (muted)" } else { "" }
+        );
+    }
+}
+
+fn cli_volume_args(
+    In(args): In<Vec<String>>,
+    mut settings: ResMut<AudioSettings>,
+) {
+    if args.len() != 2 {
+        error!("\"volume <bus> <0-1>\"");
+        return;
+    }
+    let Ok(bus) = args[0].parse::<AudioBus>() else {
+        error!("Unknown audio bus: {:?}", args[0]);
+        return;
+    };
+    let Ok(volume) = args[1].parse::<f32>() else {
+        error!("Invalid volume: {:?}", args[1]);
+        return;
+    };
+    settings.bus_mut(bus).volume = volume.clamp(0.0, 1.0);
+}
+
+fn cli_music(In(args): In<Vec<String>>, mut music: ResMut<MusicPlayer>) {
+    let sync = match args.get(1).map(String::as_str) {
+        None | Some("bar") => MusicSync::NextBar,
+        Some("beat") => MusicSync::NextBeat,
+        Some("now") => MusicSync::Now,
+        Some(_) => {
+            error!("\"music <track|stop> [bar|beat|now]\"");
+            return;
+        },
+    };
+    match args.first().map(String::as_str) {
+        Some("stop") => music.stop(sync),
+        Some(key) => music.play(key, sync),
+        None => error!("\"music <track|stop> [bar|beat|now]\""),
+    }
+}
+
+fn cli_fx(In(args): In<Vec<String>>, q_mixer: Query<&PrecisionMixerControl>) {
+    if args.len() < 2 || args.len() > 3 {
+        error!("\"fx <bus> <param> [value]\"");
+        return;
     }
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let Ok(bus) = args[0].parse::<AudioBus>() else {
+        error!("Unknown audio bus: {:?}", args[0]);
+        return;
+    };
+    let Ok(param) = args[1].parse::<AudioParam>() else {
+        error!("Unknown audio param: {:?}", args[1]);
+        return;
+    };
+    let Some(value) = args.get(2) else {
+        info!(
+            "{}",
+            ctl.controller.bus_param(bus, param)
+        );
+        return;
+    };
+    let Ok(value) = value.parse::<f32>() else {
+        error!("Invalid value: {:?}", value);
+        return;
+    };
+    ctl.controller.set_bus_param(bus, param, value);
+}
+
+fn cli_mute(In(args): In<Vec<String>>, mut settings: ResMut<AudioSettings>) {
+    let Some(Ok(bus)) = args.first().map(|s| s.parse::<AudioBus>()) else {
+        error!("\"mute <bus>\"");
+        return;
+    };
+    let bus_settings = settings.bus_mut(bus);
+    bus_settings.muted = !bus_settings.muted;
 }
 
-#[derive(Default)]
-struct DelayManagerState {
+/// How the audio clock is keeping up with the game
+///
+/// Sounds are scheduled on game ticks, but the mixer plays them by its own
+/// sample clock. The audio is kept a few ticks behind the game (the target
+/// delay), so that sounds for the current tick can still be played on time.
+/// The target grows whenever the game or the audio moves by more ticks than
+/// that in one frame, and shrinks again after 16 calm frames in a row. If
+/// the audio drifts too far from the game, its clock is reset.
+///
+/// The output latency (from the audio settings) counts towards the target:
+/// everything is heard that much later anyway, so the audio clock is kept
+/// that much less behind the game. The target never goes below it, so that
+/// the clock never gets ahead of the game.
+#[derive(Resource, Debug, Default)]
+pub struct AudioSync {
+    /// The game tick the audio has got to
+    pub audio_tick: u64,
+    pub game_tick: u64,
+    /// How many ticks after the game the audio is meant to be heard
+    pub target: u64,
+    /// The latency offset, in ticks
+    pub lead: i64,
+    /// How many times the audio clock had to be reset
+    pub resets: u32,
+    /// Frames in a row where the target could have been lower
     hyst_counter: u8,
-    last_gt: u64,
-    last_at: u64,
-    target: u64,
+}
+
+impl AudioSync {
+    /// How many ticks behind the game the audio actually is
+    pub fn lag(&self) -> i64 {
+        self.game_tick as i64 - self.audio_tick as i64
+    }
+
+    /// How many ticks behind the game the audio clock is meant to be
+    pub fn behind(&self) -> u64 {
+        (self.target as i64 - self.lead).max(0) as u64
+    }
 }
 
 fn manage_audio_delay(
     gt: Res<GameTime>,
     q_mixer: Query<&PrecisionMixerControl>,
-    mut state: Local<DelayManagerState>,
+    mut sync: ResMut<AudioSync>,
 ) {
     let Ok(ctl) = q_mixer.get_single() else {
         return;
     };
     let sample = ctl.controller.sample_count();
-    let samples_per_tick = ctl.controller.sample_rate() as f64 / gt.hz;
-    let atick = (sample as f64 / samples_per_tick) as u64;
-
-    let gt_step = gt.tick() - state.last_gt;
-    let at_step = atick.max(state.last_at) - state.last_at;
-    if gt_step < state.target && at_step < state.target {
-        state.hyst_counter += 1;
-        if state.hyst_counter == 16 {
-            state.target -= 1;
-            state.hyst_counter = 0;
+    let atick = ctl.controller.sample_to_tick(sample).max(0.0) as u64;
+
+    sync.lead = (ctl.controller.latency_offset() as f64 * gt.hz).round() as i64;
+    sync.target = sync.target.max(sync.lead.max(0) as u64);
+
+    // how far behind the audio clock is, is what has to cover the steps
+    let gt_step = gt.tick() - sync.game_tick;
+    let at_step = atick.max(sync.audio_tick) - sync.audio_tick;
+    let behind = sync.behind();
+    if gt_step < behind && at_step < behind {
+        sync.hyst_counter += 1;
+        if sync.hyst_counter == 16 {
+            sync.target -= 1;
+            sync.hyst_counter = 0;
         }
     } else {
-        state.hyst_counter = 0;
+        sync.hyst_counter = 0;
     }
-    if gt_step > state.target || at_step > state.target {
-        state.target += 1;
+    if gt_step > behind || at_step > behind {
+        sync.target += 1;
     }
 
-    state.last_gt = gt.tick();
-    state.last_at = atick;
+    sync.game_tick = gt.tick();
+    sync.audio_tick = atick;
 
+    let behind = sync.behind();
     let range_max = gt.tick();
-    let range_min = gt.tick().max(state.target * 2) - state.target * 2;
+    let range_min = gt.tick().saturating_sub(behind * 2);
 
     if (atick > range_max || atick < range_min) && !ctl.controller.has_playing()
     {
-        eprintln!("AUDIO RESET");
-        let new_atick = gt.tick().max(state.target) - state.target;
-        ctl.controller
-            .reset_sample_counter(new_atick as i64 * samples_per_tick as i64);
-        state.last_at = new_atick;
+        let new_atick = gt.tick().saturating_sub(behind);
+        debug!(
+            "Audio clock reset: audio tick {}, game tick {}, target delay {}",
+            atick,
+            gt.tick(),
+            sync.target,
+        );
+        ctl.controller.reset_sample_counter(
+            ctl.controller.tick_to_sample(new_atick as f64),
+        );
+        sync.audio_tick = new_atick;
+        sync.resets += 1;
+    }
+}
+
+fn cli_audio_sync(
+    sync: Res<AudioSync>,
+    q_mixer: Query<&PrecisionMixerControl>,
+) {
+    info!(
+        "Audio tick: {}, game tick: {} (lag {}, target {}, lead {}), resets: {}",
+        sync.audio_tick,
+        sync.game_tick,
+        sync.lag(),
+        sync.target,
+        sync.lead,
+        sync.resets,
+    );
+    if let Ok(ctl) = q_mixer.get_single() {
+        info!(
+            "Latency offset: {:.1} ms",
+            ctl.controller.latency_offset() * 1000.0
+        );
+    }
+}
+
+fn cli_audio_latency_noargs(settings: Res<AudioSettings>) {
+    info!(
+        "Latency offset: {:.1} ms",
+        settings.latency_ms
+    );
+}
+
+fn cli_audio_latency_args(
+    In(args): In<Vec<String>>,
+    mut settings: ResMut<AudioSettings>,
+) {
+    let [ms] = args.as_slice() else {
+        error!("\"audio_latency [milliseconds]\"");
+        return;
+    };
+    let Ok(ms) = ms.parse::<f32>() else {
+        error!("Invalid latency: {:?}", ms);
+        return;
+    };
+    settings.latency_ms = ms;
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::time::TimeUpdateStrategy;
+    use rodio::buffer::SamplesBuffer;
+    use theseeker_engine::audio::render::{OfflineAudio, OfflineAudioPlugin};
+    use theseeker_engine::time::GameTimePlugin;
+
+    use super::*;
+
+    /// Mixer samples per game tick, at 48 kHz and 96 Hz
+    const SAMPLES_PER_TICK: i64 = 500;
+    /// Ticks between clicks
+    const CLICK_EVERY: u64 = 24;
+
+    /// Frames already rendered when each click was played
+    #[derive(Resource, Default)]
+    struct Clicks(Vec<i64>);
+
+    fn click(
+        gt: Res<GameTime>,
+        q_mixer: Query<&PrecisionMixerControl>,
+        offline: Res<OfflineAudio>,
+        mut clicks: ResMut<Clicks>,
+    ) {
+        if gt.tick() % CLICK_EVERY != 0 {
+            return;
+        }
+        let Ok(ctl) = q_mixer.get_single() else {
+            return;
+        };
+        ctl.controller.play_at_tick(
+            gt.tick() as u32,
+            0,
+            SamplesBuffer::new(1, 48_000, vec![0.5f32; 100]),
+            AudioBus::Sfx,
+            1.0,
+            0.0,
+        );
+        let rendered = offline.samples().len() / offline.channels() as usize;
+        clicks.0.push(rendered as i64);
+    }
+
+    /// Run like headless mode does, one tick per frame, for `ticks` ticks
+    ///
+    /// Returns when each click was played (in frames already rendered
+    /// then) and the frame it was heard at, and the number of clock resets.
+    fn run(drift: f64, ticks: u64) -> (Vec<(i64, i64)>, u32) {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            GameTimePlugin,
+            OfflineAudioPlugin { drift, ..default() },
+        ));
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(
+            Duration::from_nanos((1_000_000_000.0f64 / 96.0).ceil() as u64),
+        ));
+        app.init_resource::<AudioSync>();
+        app.init_resource::<Clicks>();
+        app.add_systems(PreUpdate, manage_audio_delay);
+        app.add_systems(GameTickUpdate, click);
+        while app.world.resource::<GameTime>().tick() < ticks {
+            app.update();
+        }
+
+        let offline = app.world.resource::<OfflineAudio>();
+        let channels = offline.channels() as usize;
+        let onsets = offline
+            .samples()
+            .chunks(channels)
+            .map(|frame| frame[0] != 0.0)
+            .enumerate()
+            .scan(false, |was_on, (i, on)| {
+                let onset = on && !*was_on;
+                *was_on = on;
+                Some((i as i64, onset))
+            })
+            .filter(|(_, onset)| *onset)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let clicks = &app.world.resource::<Clicks>().0;
+        assert_eq!(
+            onsets.len(),
+            clicks.len(),
+            "every click is heard"
+        );
+        let heard = clicks
+            .iter()
+            .zip(onsets)
+            .map(|(rendered, onset)| (*rendered, onset))
+            .collect();
+        (
+            heard,
+            app.world.resource::<AudioSync>().resets,
+        )
+    }
+
+    #[test]
+    fn onsets_on_tick_without_drift() {
+        let (heard, resets) = run(0.0, 96 * 5);
+        assert_eq!(heard.len(), 20);
+        // the frames for a tick are rendered right after it,
+        // and the click starts at the first of them
+        for (rendered, onset) in heard {
+            assert_eq!(onset, rendered);
+        }
+        assert_eq!(resets, 0);
+    }
+
+    #[test]
+    fn onsets_follow_ticks_with_drift() {
+        // 1% off either way is 10 ticks over 10 seconds, if nothing corrects it
+        for drift in [0.01, -0.01] {
+            let (heard, resets) = run(drift, 96 * 10);
+            assert_eq!(heard.len(), 40);
+            for (rendered, onset) in heard {
+                let lag = onset - rendered;
+                assert!(
+                    (0..=2 * SAMPLES_PER_TICK).contains(&lag),
+                    "drift {drift}: heard {lag} samples late"
+                );
+            }
+            assert!(
+                resets > 0,
+                "drift {drift}: never corrected"
+            );
+        }
     }
 }
@@ -0,0 +1,264 @@
+//! Audio latency calibration (tap to the beat)
+//!
+//! A metronome plays, and the player taps along with what they hear.
+//! How late the taps are compared to when the clicks were sent to the
+//! audio output is how long the sound takes to get to the player's ears
+//! (plus their reaction, which is about the same as with any other sound).
+//! It is stored in the audio settings, and the audio clock is kept that much
+//! closer to the game (see [`AudioSync`](super::AudioSync)), so that sounds
+//! timed to game ticks line up with it on any hardware.
+
+use rodio::source::{SineWave, Source};
+use theseeker_engine::audio::bus::{AudioBus, AudioSettings};
+use theseeker_engine::audio::PrecisionMixerControl;
+
+use crate::prelude::*;
+
+pub struct AudioCalibrationPlugin;
+
+impl Plugin for AudioCalibrationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_clicommand_noargs("calibrate_audio", start_calibration);
+        app.add_systems(
+            Update,
+            update_calibration.run_if(resource_exists::<AudioCalibration>),
+        );
+    }
+}
+
+/// How many clicks the metronome plays
+const CLICKS: usize = 16;
+/// Seconds between clicks
+const INTERVAL: f32 = 0.6;
+/// Fewer taps than this (that are close enough to a click) is a failed run
+const MIN_TAPS: usize = 8;
+
+/// A calibration run, for as long as it is going
+#[derive(Resource)]
+pub struct AudioCalibration {
+    /// Sample numbers the clicks were scheduled at
+    clicks: Vec<i64>,
+    /// Sample numbers the mixer was at when the player tapped
+    taps: Vec<i64>,
+    screen: Entity,
+}
+
+#[derive(Component)]
+struct CalibrationText;
+
+fn start_calibration(
+    mut commands: Commands,
+    q_mixer: Query<&PrecisionMixerControl>,
+    asset_server: Res<AssetServer>,
+    calibration: Option<Res<AudioCalibration>>,
+) {
+    if calibration.is_some() {
+        return;
+    }
+    let Ok(ctl) = q_mixer.get_single() else {
+        error!("Cannot calibrate without audio");
+        return;
+    };
+    let interval = (INTERVAL * ctl.controller.sample_rate() as f32) as i64;
+    // leave a moment before the first click
+    let start = ctl.controller.sample_count() + interval * 2;
+    let clicks: Vec<_> =
+        (0..CLICKS as i64).map(|i| start + i * interval).collect();
+    for &at in &clicks {
+        let click = SineWave::new(1000.0)
+            .take_duration(Duration::from_millis(30))
+            .amplify(0.5);
+        ctl.controller
+            .play_at_sample(at, click, AudioBus::Ui, 1.0, 0.0);
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("font/Tektur-Regular.ttf"),
+        font_size: 32.0,
+        color: Default::default(),
+    };
+    let screen = commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.8)),
+            z_index: ZIndex::Global(i32::MAX - 900),
+            ..default()
+        })
+        .with_children(|screen| {
+            screen.spawn(TextBundle::from_section(
+                "Tap SPACE (or click) on every beat you hear",
+                style.clone(),
+            ));
+            screen.spawn((
+                CalibrationText,
+                TextBundle::from_section(
+                    format!("0 / {}", CLICKS),
+                    style.clone(),
+                ),
+            ));
+            screen.spawn(TextBundle::from_section(
+                "ESC to cancel",
+                TextStyle {
+                    font_size: 20.0,
+                    ..style
+                },
+            ));
+        })
+        .id();
+
+    commands.insert_resource(AudioCalibration {
+        clicks,
+        taps: Vec::with_capacity(CLICKS),
+        screen,
+    });
+}
+
+fn update_calibration(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    q_mixer: Query<&PrecisionMixerControl>,
+    mut q_text: Query<&mut Text, With<CalibrationText>>,
+    mut calibration: ResMut<AudioCalibration>,
+    mut settings: ResMut<AudioSettings>,
+) {
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let now = ctl.controller.sample_count();
+    if keys.just_pressed(KeyCode::Escape) {
+        info!("Audio calibration cancelled");
+        end_calibration(&mut commands, &calibration);
+        return;
+    }
+    if keys.just_pressed(KeyCode::Space)
+        || mouse.just_pressed(MouseButton::Left)
+    {
+        calibration.taps.push(now);
+        if let Ok(mut text) = q_text.get_single_mut() {
+            text.sections[0].value = format!(
+                "{} / {}",
+                calibration.taps.len().min(CLICKS),
+                CLICKS
+            );
+        }
+    }
+
+    // wait until a tap for the last click would have been late anyway
+    let interval = (INTERVAL * ctl.controller.sample_rate() as f32) as i64;
+    let last = calibration.clicks.last().copied().unwrap_or_default();
+    if now < last + interval {
+        return;
+    }
+    match measure_latency(
+        &calibration.clicks,
+        &calibration.taps,
+        interval,
+    ) {
+        Some(samples) => {
+            let ms =
+                samples as f32 * 1000.0 / ctl.controller.sample_rate() as f32;
+            info!("Audio latency calibrated: {:.1} ms", ms);
+            settings.latency_ms = ms;
+        },
+        None => error!("Audio calibration failed: too few taps on the beat"),
+    }
+    end_calibration(&mut commands, &calibration);
+}
+
+fn end_calibration(commands: &mut Commands, calibration: &AudioCalibration) {
+    commands.entity(calibration.screen).despawn_recursive();
+    commands.remove_resource::<AudioCalibration>();
+}
+
+/// The typical (median) delay from a click to the tap for it, in samples
+///
+/// Each tap goes with the nearest click. Taps that are more than half an
+/// interval away from every click are ignored.
+fn measure_latency(clicks: &[i64], taps: &[i64], interval: i64) -> Option<i64> {
+    let mut delays: Vec<i64> = taps
+        .iter()
+        .filter_map(|tap| {
+            clicks
+                .iter()
+                .map(|click| tap - click)
+                .min_by_key(|delay| delay.abs())
+        })
+        .filter(|delay| delay.abs() < interval / 2)
+        .collect();
+    if delays.len() < MIN_TAPS {
+        return None;
+    }
+    delays.sort_unstable();
+    Some(delays[delays.len() / 2])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPACING: i64 = 1000;
+
+    fn clicks() -> Vec<i64> {
+        (0..CLICKS as i64).map(|i| 2000 + i * SPACING).collect()
+    }
+
+    #[test]
+    fn steady_taps() {
+        let clicks = clicks();
+        let taps: Vec<_> = clicks.iter().map(|c| c + 120).collect();
+        assert_eq!(
+            measure_latency(&clicks, &taps, SPACING),
+            Some(120)
+        );
+    }
+
+    #[test]
+    fn median_of_uneven_taps() {
+        let clicks = clicks();
+        // early taps count too, and one wild tap doesn't move the result
+        let delays = [100, 150, -20, 130, 90, 400, 110, 120, 140];
+        let taps: Vec<_> =
+            clicks.iter().zip(delays).map(|(c, d)| c + d).collect();
+        assert_eq!(
+            measure_latency(&clicks, &taps, SPACING),
+            Some(120)
+        );
+    }
+
+    #[test]
+    fn taps_go_with_the_nearest_click() {
+        let clicks = clicks();
+        // just before the next click is early for that one,
+        // not very late for the one before
+        let taps: Vec<_> = clicks.iter().map(|c| c + SPACING - 50).collect();
+        assert_eq!(
+            measure_latency(&clicks, &taps, SPACING),
+            Some(-50)
+        );
+    }
+
+    #[test]
+    fn too_few_taps() {
+        let clicks = clicks();
+        let taps: Vec<_> =
+            clicks.iter().take(MIN_TAPS - 1).map(|c| c + 100).collect();
+        assert_eq!(
+            measure_latency(&clicks, &taps, SPACING),
+            None
+        );
+        // taps between the clicks don't count
+        let taps: Vec<_> = clicks.iter().map(|c| c + SPACING / 2).collect();
+        assert_eq!(
+            measure_latency(&clicks, &taps, SPACING),
+            None
+        );
+    }
+}
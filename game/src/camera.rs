@@ -13,6 +13,10 @@ use crate::graphics::dof::{DepthOfFieldMode, DepthOfFieldSettings};
 // use crate::graphics::post_processing::darkness::DarknessSettings;
 use crate::graphics::post_processing::vignette::VignetteSettings;
 use crate::level::MainBackround;
+use crate::perf::{
+    PerfUiEntryAudioLag, PerfUiEntryAudioResets, PerfUiEntryAudioTarget,
+    PerfUiEntryDroppedTicks,
+};
 use crate::prelude::*;
 
 const PROJECTION_SCALE: f32 = 1.0 / 5.0;
@@ -97,6 +101,10 @@ pub struct GameViewLimits(Rect);
 pub(crate) fn setup_main_camera(mut commands: Commands) {
     commands.spawn((
         PerfUiCompleteBundle::default(),
+        PerfUiEntryDroppedTicks::default(),
+        PerfUiEntryAudioLag::default(),
+        PerfUiEntryAudioTarget::default(),
+        PerfUiEntryAudioResets::default(),
         StateDespawnMarker,
     ));
     let mut camera = Camera2dBundle {
@@ -9,6 +9,8 @@ impl Plugin for DevPlugin {
     fn build(&self, app: &mut App) {
         app.register_clicommand_args("spawn_script", cli_spawn_script);
         app.register_clicommand_args("spawn_anim", cli_spawn_anim);
+        app.register_clicommand_args("timescale", cli_timescale);
+        app.register_clicommand_args("tickrate", cli_tickrate);
         app.add_systems(
             Last,
             debug_progress
@@ -82,6 +84,23 @@ fn cli_spawn_script(In(args): In<Vec<String>>, world: &mut World) {
     world.spawn(ScriptBundle { player });
 }
 
+fn cli_timescale(In(args): In<Vec<String>>, mut time: ResMut<GameTime>) {
+    let Some(Ok(scale)) = args.first().map(|s| s.parse::<f64>()) else {
+        error!("\"timescale <scale>\"");
+        return;
+    };
+    time.set_time_scale(scale);
+    info!("Time scale: {}", time.time_scale());
+}
+
+fn cli_tickrate(In(args): In<Vec<String>>, mut time: ResMut<GameTime>) {
+    let Some(Ok(hz)) = args.first().map(|s| s.parse::<f64>()) else {
+        error!("\"tickrate <hz>\"");
+        return;
+    };
+    time.set_tick_rate(hz);
+}
+
 fn cli_spawn_anim(In(args): In<Vec<String>>, world: &mut World) {
     use theseeker_engine::animation::SpriteAnimationBundle;
     use theseeker_engine::script::ScriptPlayer;
@@ -2,20 +2,27 @@
 //!
 //! Create sub-modules for different aspects of the gameplay.
 
+use self::audio_zone::AudioZoneBundle;
 use self::enemy::{EnemyBlueprintBundle, EnemySpawnerBundle};
 use self::player::PlayerBlueprintBundle;
+use self::trigger::TriggerBundle;
+use self::water::WaterBundle;
 use crate::game::merchant::MerchantBlueprintBundle;
 use crate::game::yak::YakBlueprintBundle;
 use crate::prelude::*;
 
 pub mod attack;
+pub mod audio_zone;
 pub mod enemy;
-mod game_over;
+pub mod game_over;
 pub mod gentstate;
 mod merchant;
+pub mod music;
 pub mod physics;
 pub mod player;
+mod trigger;
 mod wall;
+pub mod water;
 mod xp_orbs;
 mod yak;
 
@@ -30,11 +37,17 @@ impl Plugin for GameplayPlugin {
         app.register_ldtk_int_cell::<wall::WallBundle>(18);
         app.register_ldtk_int_cell::<wall::WallBundle>(19);
         app.register_ldtk_int_cell::<wall::WallBundle>(20);
+        app.register_ldtk_int_cell::<wall::OneWayBundle>(21);
+        app.register_ldtk_int_cell::<wall::SlopeUpRightBundle>(22);
+        app.register_ldtk_int_cell::<wall::SlopeUpLeftBundle>(23);
         app.register_ldtk_entity::<PlayerBlueprintBundle>("Player");
         app.register_ldtk_entity::<MerchantBlueprintBundle>("Merchant");
         app.register_ldtk_entity::<YakBlueprintBundle>("Yak");
         app.register_ldtk_entity::<EnemyBlueprintBundle>("Enemy");
         app.register_ldtk_entity::<EnemySpawnerBundle>("EnemySpawner");
+        app.register_ldtk_entity::<TriggerBundle>("Trigger");
+        app.register_ldtk_entity::<WaterBundle>("Water");
+        app.register_ldtk_entity::<AudioZoneBundle>("AudioZone");
 
         // Add the plugins for each game mechanic
         app.add_plugins((
@@ -46,6 +59,10 @@ impl Plugin for GameplayPlugin {
             wall::WallPlugin,
             game_over::GameOverPlugin,
             xp_orbs::XpPlugin,
+            trigger::TriggerPlugin,
+            water::WaterPlugin,
+            audio_zone::AudioZonePlugin,
+            music::MusicIntensityPlugin,
         ));
     }
 }
@@ -1,5 +1,12 @@
-use theseeker_engine::ballistics_math::solve_ballistic_arc;
-use theseeker_engine::physics::{Collider, LinearVelocity};
+use rapier2d::parry::query::TOIStatus;
+use rapier2d::prelude::InteractionGroups;
+use theseeker_engine::ballistics_math::{
+    solve_ballistic_arc, solve_ballistic_arc_moving,
+};
+use theseeker_engine::physics::layers::CollisionLayerRegistry;
+use theseeker_engine::physics::{
+    into_vec2, Collider, LinearVelocity, PhysicsWorld,
+};
 
 use crate::game::attack::Attack;
 use crate::game::player::PlayerConfig;
@@ -8,6 +15,13 @@ use crate::prelude::*;
 /// Attach this to an [`Attack`] entity to make it move with a fixed initial velocity
 /// (in pixels/s) and despawn on collision. (The despawn on collision logic is
 /// handled by the [`attack_damage`] system)
+///
+/// The movement is swept against the ground, so fast projectiles can't tunnel
+/// through thin walls. When it hits the ground it either bounces (see [`Bounce`])
+/// or sends a [`ProjectileImpact`] and ends its [`Attack`].
+///
+/// Other behaviours are opt-in components: [`Pierce`], [`Bounce`],
+/// [`GravityScale`], [`Homing`] and [`OnImpact`].
 #[derive(Component, Debug)]
 pub struct Projectile {
     pub vel: LinearVelocity,
@@ -27,7 +41,6 @@ impl Projectile {
         gravity: f32,
     ) -> Option<Self> {
         let result = solve_ballistic_arc(start, max_speed, target, gravity);
-        println!("result: {result:?}");
         if result.2 != 0 {
             // use the arc that has the bigger y component
             if result.0.y > result.1.y {
@@ -43,34 +56,384 @@ impl Projectile {
             None
         }
     }
+
+    /// Like [`Projectile::with_vel`], but leads a target moving with `target_vel`
+    pub fn with_vel_leading(
+        target: Vec2,
+        target_vel: Vec2,
+        start: Vec2,
+        max_speed: f32,
+        gravity: f32,
+    ) -> Option<Self> {
+        let result = solve_ballistic_arc_moving(
+            start, max_speed, target, target_vel, gravity,
+        );
+        // the first solution is the one that gets there sooner
+        if result.2 != 0 {
+            Some(Self {
+                vel: LinearVelocity(result.0),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Lets a [`Projectile`] go through this many more targets
+///
+/// Each time the projectile has hit as many targets as [`Attack::max_targets`],
+/// one pierce is used up to allow one more, instead of despawning.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Pierce(pub u32);
+
+/// Makes a [`Projectile`] bounce off the ground instead of stopping
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Bounce {
+    /// How much of the velocity into the surface is kept, 0..=1
+    pub restitution: f32,
+    /// Bounces left, after that it stops on impact like usual
+    pub remaining: u32,
+}
+
+/// Multiplies the gravity applied to a [`Projectile`]
+///
+/// Without this, arrows have no gravity and everything else has normal gravity.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct GravityScale(pub f32);
+
+/// Steers a [`Projectile`] towards a target, keeping its speed
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Homing {
+    pub target: Entity,
+    /// Max turn speed, in radians/s
+    pub turn_rate: f32,
 }
 
+/// Called when a [`Projectile`] stops on the ground, to spawn something there
+/// (explosions, puddles, ...)
+#[derive(Component, Clone, Copy)]
+pub struct OnImpact(pub fn(&mut Commands, &ProjectileImpact));
+
+/// Sent when a [`Projectile`] stops on the ground (not when it bounces)
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ProjectileImpact {
+    pub projectile: Entity,
+    /// Who shot the projectile
+    pub attacker: Entity,
+    /// The ground entity that was hit
+    pub other: Entity,
+    pub position: Vec2,
+    pub normal: Vec2,
+    pub velocity: Vec2,
+}
+
+/// Everything that moves projectiles
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProjectileSet;
+
 /// Marker that identifies arrows shot by the Player.
 #[derive(Component)]
 pub struct Arrow;
 
-/// Applies gravity to the projectile
-/// It will not add gravity to Arrows shot by the Player.
+/// Turns homing projectiles towards their targets
+///
+/// Projectiles affected by gravity aim for where a ballistic arc would
+/// intercept the (possibly moving) target, instead of straight at it.
+pub fn home_projectiles(
+    mut query: Query<(
+        &Transform,
+        &mut Projectile,
+        &Homing,
+        Option<&GravityScale>,
+        Has<Arrow>,
+    )>,
+    q_target: Query<(
+        &GlobalTransform,
+        Option<&LinearVelocity>,
+    )>,
+    config: Res<PlayerConfig>,
+    time: Res<GameTime>,
+) {
+    for (transform, mut projectile, homing, gravity_scale, arrow) in
+        query.iter_mut()
+    {
+        let Ok((target_transform, target_vel)) = q_target.get(homing.target)
+        else {
+            continue;
+        };
+        let pos = transform.translation.xy();
+        let target = target_transform.translation().xy();
+        let target_vel = target_vel.map(|v| v.0).unwrap_or(Vec2::ZERO);
+        let speed = projectile.vel.length();
+        let gravity = gravity_scale(gravity_scale, arrow)
+            * config.fall_accel
+            * time.hz as f32;
+        let desired = if gravity > 0.0 {
+            Projectile::with_vel_leading(
+                target, target_vel, pos, speed, gravity,
+            )
+            .map(|p| p.vel.0)
+        } else {
+            None
+        }
+        .unwrap_or((target - pos).normalize_or_zero() * speed);
+
+        let current_angle = projectile.vel.y.atan2(projectile.vel.x);
+        let turn = Vec2::from_angle(current_angle)
+            .angle_between(desired)
+            .clamp(
+                -homing.turn_rate / time.hz as f32,
+                homing.turn_rate / time.hz as f32,
+            );
+        if desired != Vec2::ZERO {
+            projectile.vel.0 = Vec2::from_angle(current_angle + turn) * speed;
+        }
+    }
+}
+
+fn gravity_scale(gravity_scale: Option<&GravityScale>, arrow: bool) -> f32 {
+    match gravity_scale {
+        Some(scale) => scale.0,
+        None if arrow => 0.0,
+        None => 1.0,
+    }
+}
+
+/// Applies gravity to the projectile and moves it, stopping at the ground
+/// It will not add gravity to Arrows shot by the Player, unless they have
+/// a [`GravityScale`].
 pub fn arc_projectile(
-    mut query: Query<
-        (
-            &mut Transform,
-            &mut Projectile,
-            Has<Arrow>,
-        ),
-        With<Attack>,
-    >,
+    mut query: Query<(
+        Entity,
+        &mut Transform,
+        &mut Projectile,
+        &mut Attack,
+        &Collider,
+        Option<&GravityScale>,
+        Option<&mut Bounce>,
+        Option<&OnImpact>,
+        Has<Arrow>,
+    )>,
     config: Res<PlayerConfig>,
     time: Res<GameTime>,
+    spatial_query: Res<PhysicsWorld>,
+    collision_layers: Res<CollisionLayerRegistry>,
+    mut impact_events: EventWriter<ProjectileImpact>,
+    mut commands: Commands,
 ) {
     let fall_accel = config.fall_accel;
-    for (mut transform, mut projectile, arrow) in query.iter_mut() {
-        if !arrow {
-            projectile.vel.0.y -= fall_accel;
+    let ground = collision_layers.layer("ground");
+    for (
+        entity,
+        mut transform,
+        mut projectile,
+        mut attack,
+        collider,
+        gravity,
+        bounce,
+        on_impact,
+        arrow,
+    ) in query.iter_mut()
+    {
+        if attack.current_lifetime >= attack.max_lifetime {
+            continue;
         }
+        projectile.vel.0.y -= fall_accel * gravity_scale(gravity, arrow);
         let z = transform.translation.z;
-        transform.translation = (transform.translation.xy()
-            + *projectile.vel * (1.0 / time.hz as f32))
-            .extend(z);
+        let mut pos = transform.translation.xy();
+        let motion = *projectile.vel * (1.0 / time.hz as f32);
+        let Ok(dir) = Direction2d::new(motion) else {
+            continue;
+        };
+        let interaction = InteractionGroups::new(
+            collider.0.collision_groups().memberships,
+            ground,
+        );
+        let Some((other, hit)) = spatial_query.shape_cast(
+            pos,
+            dir,
+            collider.0.shape(),
+            motion.length(),
+            interaction,
+            Some(entity),
+        ) else {
+            transform.translation = (pos + motion).extend(z);
+            continue;
+        };
+        let normal = if hit.status == TOIStatus::Penetrating {
+            -*dir
+        } else {
+            into_vec2(hit.normal1)
+        };
+        // stop just short of the surface
+        pos += *dir * (hit.toi - 0.01).max(0.0);
+        transform.translation = pos.extend(z);
+
+        if let Some(mut bounce) = bounce {
+            if bounce.remaining > 0 && hit.status != TOIStatus::Penetrating {
+                bounce.remaining -= 1;
+                let vel = projectile.vel.0;
+                projectile.vel.0 =
+                    vel - (1.0 + bounce.restitution) * vel.dot(normal) * normal;
+                continue;
+            }
+        }
+
+        let impact = ProjectileImpact {
+            projectile: entity,
+            attacker: attack.attacker,
+            other,
+            position: pos,
+            normal,
+            velocity: projectile.vel.0,
+        };
+        if let Some(on_impact) = on_impact {
+            (on_impact.0)(&mut commands, &impact);
+        }
+        impact_events.send(impact);
+        projectile.vel.0 = Vec2::ZERO;
+        // let `attack_cleanup` despawn it
+        attack.current_lifetime = attack.max_lifetime;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+    use theseeker_engine::physics::layers::CollisionLayers;
+    use theseeker_engine::physics::update_query_pipeline;
+
+    use super::*;
+
+    /// 96 Hz, so a velocity of 96 px/s moves 1 px per tick. The default
+    /// [`PlayerConfig`] has no gravity.
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert_resource(PhysicsWorld::default());
+        world.insert_resource(GameTime::new(96.0));
+        world.insert_resource(PlayerConfig::default());
+        world.insert_resource(CollisionLayerRegistry::default());
+        world.init_resource::<Events<ProjectileImpact>>();
+        world
+    }
+
+    /// A 2 px thick wall, from x = 50 to 52
+    fn spawn_wall(world: &mut World) -> Entity {
+        let layers = CollisionLayers::new("ground");
+        let groups = world.resource::<CollisionLayerRegistry>().groups(&layers);
+        world
+            .spawn((
+                Collider::cuboid(2.0, 100.0, groups),
+                layers,
+                GlobalTransform::from_translation(Vec3::new(51.0, 0.0, 0.0)),
+            ))
+            .id()
+    }
+
+    /// A 4x4 arrow at the origin, moving 100 px per tick to the right
+    fn spawn_arrow(world: &mut World) -> Entity {
+        let layers = CollisionLayers::new("player_attack");
+        let groups = world.resource::<CollisionLayerRegistry>().groups(&layers);
+        let attacker = world.spawn_empty().id();
+        world
+            .spawn((
+                Arrow,
+                Projectile {
+                    vel: LinearVelocity(Vec2::new(9600.0, 0.0)),
+                },
+                Attack::new(100, attacker),
+                Collider::cuboid(4.0, 4.0, groups),
+                layers,
+                Transform::default(),
+            ))
+            .id()
+    }
+
+    fn step(world: &mut World) {
+        world.run_system_once(update_query_pipeline);
+        world.run_system_once(arc_projectile);
+    }
+
+    fn impacts(world: &World) -> Vec<ProjectileImpact> {
+        let events = world.resource::<Events<ProjectileImpact>>();
+        events.get_reader().read(events).copied().collect()
+    }
+
+    #[test]
+    fn stop_at_thin_wall() {
+        let mut world = world();
+        let wall = spawn_wall(&mut world);
+        let arrow = spawn_arrow(&mut world);
+        step(&mut world);
+
+        // it would have ended up at x = 100, past the wall
+        let x = world.get::<Transform>(arrow).unwrap().translation.x;
+        assert!(x > 47.9 && x <= 48.0, "{x}");
+        let attack = world.get::<Attack>(arrow).unwrap();
+        assert_eq!(
+            attack.current_lifetime,
+            attack.max_lifetime
+        );
+        assert_eq!(
+            world.get::<Projectile>(arrow).unwrap().vel.0,
+            Vec2::ZERO
+        );
+
+        let impacts = impacts(&world);
+        assert_eq!(impacts.len(), 1);
+        assert_eq!(impacts[0].projectile, arrow);
+        assert_eq!(impacts[0].other, wall);
+        assert!(
+            impacts[0].normal.x < -0.99,
+            "{}",
+            impacts[0].normal
+        );
+        assert_eq!(
+            impacts[0].velocity,
+            Vec2::new(9600.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn bounce_off_thin_wall() {
+        let mut world = world();
+        spawn_wall(&mut world);
+        let arrow = spawn_arrow(&mut world);
+        world.entity_mut(arrow).insert(Bounce {
+            restitution: 0.5,
+            remaining: 1,
+        });
+        step(&mut world);
+
+        let x = world.get::<Transform>(arrow).unwrap().translation.x;
+        assert!(x > 47.9 && x <= 48.0, "{x}");
+        let vel = world.get::<Projectile>(arrow).unwrap().vel.0;
+        assert!(
+            (vel - Vec2::new(-4800.0, 0.0)).length() < 0.1,
+            "{vel}"
+        );
+        assert_eq!(
+            world.get::<Bounce>(arrow).unwrap().remaining,
+            0
+        );
+        assert!(impacts(&world).is_empty());
+
+        // on the way back, there is nothing to hit
+        step(&mut world);
+        let x = world.get::<Transform>(arrow).unwrap().translation.x;
+        assert!((x - -2.0).abs() < 0.1, "{x}");
+    }
+
+    #[test]
+    fn fly_when_nothing_is_hit() {
+        let mut world = world();
+        let arrow = spawn_arrow(&mut world);
+        step(&mut world);
+
+        let x = world.get::<Transform>(arrow).unwrap().translation.x;
+        assert_eq!(x, 100.0);
+        let attack = world.get::<Attack>(arrow).unwrap();
+        assert_eq!(attack.current_lifetime, 0);
+        assert!(impacts(&world).is_empty());
     }
 }
@@ -3,11 +3,10 @@ pub mod particles;
 
 use std::mem;
 
-use arc_attack::Arrow;
-use rapier2d::prelude::InteractionGroups;
 use theseeker_engine::gent::Gent;
+use theseeker_engine::physics::layers::CollisionLayerRegistry;
 use theseeker_engine::physics::{
-    update_sprite_colliders, Collider, PhysicsWorld, GROUND, PLAYER_ATTACK,
+    update_sprite_colliders, Collider, PhysicsWorld,
 };
 
 use super::enemy::{Defense, EnemyGfx, EnemyStateSet, JustGotHitMarker};
@@ -15,10 +14,13 @@ use super::gentstate::{Dead, Facing};
 use super::physics::Knockback;
 use super::player::{
     on_hit_exit_stealthing, on_hit_stealth_reset, Passive, Passives, Player,
-    PlayerGfx, PlayerStateSet, StatusModifier,
+    PlayerConfig, PlayerGfx, PlayerStateSet, StatusModifier,
 };
 use crate::camera::CameraShake;
-use crate::game::attack::arc_attack::{arc_projectile, Projectile};
+use crate::game::attack::arc_attack::{
+    arc_projectile, home_projectiles, Pierce, Projectile, ProjectileImpact,
+    ProjectileSet,
+};
 use crate::game::attack::particles::AttackParticlesPlugin;
 use crate::prelude::*;
 
@@ -29,14 +31,18 @@ impl Plugin for AttackPlugin {
         app.register_type::<Crits>();
         app.add_plugins(AttackParticlesPlugin);
         app.add_gametick_event::<DamageInfo>();
+        app.add_gametick_event::<ProjectileImpact>();
         app.init_resource::<KillCount>();
+        app.warn_on_tick_rate_change("Attacks (lifetimes in ticks)");
         app.add_systems(
             GameTickUpdate,
             (
                 // (determine_attack_targets, apply_attack_modifications, apply_attack_damage)
                 // (track_crits, on_hit_player_pushback).in_set(OnAttackFirstHitSet)
                 // (lifesteal, kill_on_damage, damage_flash).in_set(RespondToDamageInfoSet)
-                arc_projectile,
+                (home_projectiles, arc_projectile)
+                    .chain()
+                    .in_set(ProjectileSet),
                 (
                     determine_attack_targets,
                     apply_attack_modifications,
@@ -45,6 +51,7 @@ impl Plugin for AttackPlugin {
                     // OnAttackFirstHitSet
                     track_crits,
                     on_hit_cam_shake,
+                    on_hit_hitstop,
                     on_hit_self_pushback,
                     on_hit_lifesteal,
                     on_hit_stealth_reset,
@@ -62,7 +69,6 @@ impl Plugin for AttackPlugin {
                 // cleanup
                 attack_tick,
                 despawn_projectile,
-                despawn_player_arrows,
                 attack_cleanup,
             )
                 .chain()
@@ -202,7 +208,9 @@ pub fn determine_attack_targets(
         (With<Collider>, With<Health>, With<Gent>),
     >,
     spatial_query: Res<PhysicsWorld>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
+    let ground = collision_layers.layer("ground");
     for (entity, transform, mut attack, collider) in attack_query.iter_mut() {
         let mut newly_collided: HashSet<Entity> = HashSet::default();
         let intersections = spatial_query.intersect(
@@ -211,7 +219,7 @@ pub fn determine_attack_targets(
             collider
                 .0
                 .collision_groups()
-                .with_filter(collider.0.collision_groups().filter | GROUND),
+                .with_filter(collider.0.collision_groups().filter | ground),
             Some(entity),
         );
         let mut targets = intersections
@@ -402,9 +410,13 @@ pub fn apply_attack_damage(
                 attack.damaged_set.insert(t_entity);
                 damage_events.send(damage_info);
 
-                // apply Knockback
+                // apply Knockback, blocking targets hold their ground
                 if let Some(pushback) = maybe_pushback {
-                    commands.entity(t_entity).insert(pushback.0);
+                    let mut knockback = pushback.0;
+                    if is_defending {
+                        knockback.strength = Vec2::ZERO;
+                    }
+                    commands.entity(t_entity).insert(knockback);
                 }
             }
         }
@@ -413,37 +425,19 @@ pub fn apply_attack_damage(
 }
 
 pub fn despawn_projectile(
-    query: Query<(Entity, &Attack), (With<Projectile>, With<Hit>)>,
+    mut query: Query<
+        (Entity, &mut Attack, Option<&mut Pierce>),
+        (With<Projectile>, With<Hit>),
+    >,
     mut commands: Commands,
 ) {
-    for (entity, attack) in query.iter() {
+    for (entity, mut attack, pierce) in query.iter_mut() {
         if attack.damaged_set.len() == attack.max_targets as usize {
-            // Note: purposefully does not despawn child entities, nor remove the
-            // reference, so that child particle systems have the option of lingering
-            commands.entity(entity).despawn();
-        }
-    }
-}
-
-pub fn despawn_player_arrows(
-    query: Query<(Entity, &Transform, &Collider), With<Arrow>>,
-    spatial_query: Res<PhysicsWorld>,
-    mut commands: Commands,
-) {
-    for (entity, transform, collider) in query.iter() {
-        let is_arrow_intersecting_with_ground = !spatial_query
-            .intersect(
-                transform.translation.xy(),
-                collider.0.shape(),
-                InteractionGroups {
-                    memberships: PLAYER_ATTACK,
-                    filter: GROUND,
-                },
-                None,
-            )
-            .is_empty();
-
-        if is_arrow_intersecting_with_ground {
+            if let Some(mut pierce) = pierce.filter(|p| p.0 > 0) {
+                pierce.0 -= 1;
+                attack.max_targets += 1;
+                continue;
+            }
             // Note: purposefully does not despawn child entities, nor remove the
             // reference, so that child particle systems have the option of lingering
             commands.entity(entity).despawn();
@@ -478,6 +472,23 @@ fn on_hit_cam_shake(
     }
 }
 
+/// Briefly freezes game time on first hit if attacker is player
+fn on_hit_hitstop(
+    query: Query<&Attack, Added<Hit>>,
+    p_query: Query<Entity, With<Player>>,
+    config: Res<PlayerConfig>,
+    mut time: ResMut<GameTime>,
+) {
+    for attack in query.iter() {
+        if p_query.get(attack.attacker).is_ok() {
+            time.request_hitstop(
+                Duration::from_secs_f32(config.hitstop_duration.max(0.0)),
+                1,
+            );
+        }
+    }
+}
+
 /// Applies pushback to attacker on first hit of an attack with SelfPushback
 fn on_hit_self_pushback(
     query: Query<(&Attack, &SelfPushback), Added<Hit>>,
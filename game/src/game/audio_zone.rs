@@ -0,0 +1,306 @@
+//! Audio environment zones placed in LDtk
+//!
+//! While the [`AudioListener`] is inside an [`AudioZone`], the zone decides
+//! the ambience loop, and the reverb and filters on the sfx bus. Moving
+//! from one zone to another crossfades to the new zone's settings. Outside
+//! of every zone, there is no ambience and the sfx bus is left dry.
+//!
+//! If zones overlap, the one entered last wins.
+
+use std::str::FromStr;
+
+use rodio::Source;
+use strum_macros::EnumString;
+use theseeker_engine::audio::bus::AudioBus;
+use theseeker_engine::audio::dsp::AudioParam;
+use theseeker_engine::audio::spatial::AudioListener;
+use theseeker_engine::audio::{MixerVoice, PrecisionMixerControl};
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
+};
+use theseeker_engine::physics::{
+    Collider, CollisionEnded, CollisionEvents, CollisionStarted, PhysicsSet,
+};
+
+use crate::prelude::*;
+
+pub struct AudioZonePlugin;
+
+impl Plugin for AudioZonePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ListenerZones>();
+        app.add_systems(
+            GameTickUpdate,
+            (
+                setup_audio_zones,
+                audio_zone_collisions.after(PhysicsSet),
+                apply_audio_zone.after(audio_zone_collisions),
+            )
+                .run_if(in_state(GameState::Playing))
+                .run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(
+            OnExit(AppState::InGame),
+            leave_audio_zones,
+        );
+    }
+}
+
+#[derive(Bundle, LdtkEntity, Default)]
+pub struct AudioZoneBundle {
+    #[with(AudioZone::from_entity_instance)]
+    zone: AudioZone,
+}
+
+/// A rectangle with its own ambience and acoustics
+#[derive(Component, Debug, Clone)]
+pub struct AudioZone {
+    pub size: Vec2,
+    /// Asset key of the ambience to loop while inside
+    pub ambience: Option<String>,
+    pub ambience_volume: f32,
+    pub reverb: ReverbPreset,
+    /// Low-pass cutoff on the sfx bus, in Hz
+    pub lowpass: f32,
+    /// High-pass cutoff on the sfx bus, in Hz
+    pub highpass: f32,
+    /// Seconds to crossfade to this zone's settings when entering it
+    pub fade: f32,
+}
+
+impl Default for AudioZone {
+    fn default() -> Self {
+        Self {
+            size: Vec2::ZERO,
+            ambience: None,
+            ambience_volume: 1.0,
+            reverb: ReverbPreset::None,
+            lowpass: AudioParam::Lowpass.default_value(),
+            highpass: AudioParam::Highpass.default_value(),
+            fade: 1.0,
+        }
+    }
+}
+
+impl AudioZone {
+    pub fn from_entity_instance(entity_instance: &EntityInstance) -> Self {
+        let default = Self::default();
+        let float = |field: &str, default: f32| {
+            entity_instance
+                .get_float_field(field)
+                .copied()
+                .unwrap_or(default)
+        };
+        let reverb = entity_instance
+            .get_enum_field("reverb")
+            .ok()
+            .and_then(|s| ReverbPreset::from_str(s).ok())
+            .unwrap_or_default();
+        Self {
+            size: Vec2::new(
+                entity_instance.width as f32,
+                entity_instance.height as f32,
+            ),
+            ambience: entity_instance
+                .get_maybe_string_field("ambience")
+                .ok()
+                .cloned()
+                .flatten()
+                .filter(|s| !s.is_empty()),
+            ambience_volume: float(
+                "ambience_volume",
+                default.ambience_volume,
+            ),
+            reverb,
+            lowpass: float("lowpass", default.lowpass),
+            highpass: float("highpass", default.highpass),
+            fade: float("fade", default.fade),
+        }
+    }
+}
+
+/// Reverb settings for the kinds of places in the game
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(EnumString)]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
+pub enum ReverbPreset {
+    #[default]
+    None,
+    /// Open air, only a short, faint reflection
+    Open,
+    Room,
+    Hall,
+    /// Long, dark tail
+    Cave,
+}
+
+impl ReverbPreset {
+    /// Values for [`AudioParam::ReverbMix`], [`AudioParam::ReverbSize`]
+    /// and [`AudioParam::ReverbDamping`]
+    pub fn params(self) -> [(AudioParam, f32); 3] {
+        let (mix, size, damping) = match self {
+            ReverbPreset::None => {
+                (
+                    AudioParam::ReverbMix.default_value(),
+                    AudioParam::ReverbSize.default_value(),
+                    AudioParam::ReverbDamping.default_value(),
+                )
+            },
+            ReverbPreset::Open => (0.05, 0.2, 0.8),
+            ReverbPreset::Room => (0.15, 0.35, 0.6),
+            ReverbPreset::Hall => (0.25, 0.7, 0.45),
+            ReverbPreset::Cave => (0.35, 0.85, 0.7),
+        };
+        [
+            (AudioParam::ReverbMix, mix),
+            (AudioParam::ReverbSize, size),
+            (AudioParam::ReverbDamping, damping),
+        ]
+    }
+}
+
+/// The zones the listener is in, and what was last applied
+#[derive(Resource, Default)]
+pub struct ListenerZones {
+    /// In the order they were entered
+    inside: Vec<Entity>,
+    applied: Option<Entity>,
+    /// The ambience currently playing, and its asset key
+    ambience: Option<(String, Arc<MixerVoice>)>,
+    /// Fade of the zone that was applied, for when leaving it
+    fade: f32,
+}
+
+impl ListenerZones {
+    /// The zone whose settings are in effect
+    pub fn current(&self) -> Option<Entity> {
+        self.applied
+    }
+}
+
+fn setup_audio_zones(
+    query: Query<(Entity, &AudioZone), Added<AudioZone>>,
+    collision_layers: Res<CollisionLayerRegistry>,
+    mut commands: Commands,
+) {
+    for (entity, zone) in query.iter() {
+        let layers = CollisionLayers::new("sensor");
+        commands.entity(entity).insert((
+            Name::new("AudioZone"),
+            Collider::cuboid(
+                zone.size.x,
+                zone.size.y,
+                collision_layers.groups(&layers),
+            ),
+            layers,
+            CollisionEvents,
+        ));
+    }
+}
+
+fn audio_zone_collisions(
+    q_zone: Query<(), With<AudioZone>>,
+    q_listener: Query<(), With<AudioListener>>,
+    mut evr_started: EventReader<CollisionStarted>,
+    mut evr_ended: EventReader<CollisionEnded>,
+    mut zones: ResMut<ListenerZones>,
+) {
+    for ev in evr_started.read() {
+        if q_zone.contains(ev.entity) && q_listener.contains(ev.other) {
+            zones.inside.retain(|e| *e != ev.entity);
+            zones.inside.push(ev.entity);
+        }
+    }
+    for ev in evr_ended.read() {
+        if q_listener.contains(ev.other) {
+            zones.inside.retain(|e| *e != ev.entity);
+        }
+    }
+}
+
+fn apply_audio_zone(
+    q_zone: Query<&AudioZone>,
+    q_mixer: Query<&PrecisionMixerControl>,
+    preloaded: Res<PreloadedAssets>,
+    ass_audio: Res<Assets<AudioSource>>,
+    mut zones: ResMut<ListenerZones>,
+) {
+    // zones go away with their level
+    zones.inside.retain(|e| q_zone.contains(*e));
+    let current = zones.inside.last().copied();
+    if current == zones.applied {
+        return;
+    }
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let zone = current.and_then(|e| q_zone.get(e).ok());
+    // entering takes the new zone's time, leaving takes the old one's
+    let fade = zone.map(|z| z.fade).unwrap_or(zones.fade).max(0.0);
+    let default = AudioZone::default();
+    let settings = zone.unwrap_or(&default);
+    zones.applied = current;
+    zones.fade = fade;
+
+    let now = ctl.controller.sample_count();
+    let len = (fade * ctl.controller.sample_rate() as f32) as i64;
+    let filters = [
+        (AudioParam::Lowpass, settings.lowpass),
+        (AudioParam::Highpass, settings.highpass),
+    ];
+    for (param, value) in settings.reverb.params().into_iter().chain(filters) {
+        ctl.controller
+            .ramp_bus_param(AudioBus::Sfx, param, value, now, len);
+    }
+
+    if let Some((key, voice)) = &zones.ambience {
+        if settings.ambience.as_ref() == Some(key) {
+            voice.ramp_volume(settings.ambience_volume, now, len);
+            return;
+        }
+        voice.ramp_volume(0.0, now, len);
+        voice.stop_at_sample(now + len);
+    }
+    zones.ambience = None;
+    let Some(key) = &settings.ambience else {
+        return;
+    };
+    let Some(sound) = preloaded
+        .get_multi_asset(key)
+        .and_then(|handles| handles.first())
+        .and_then(|h| ass_audio.get(h.id().typed::<AudioSource>()))
+    else {
+        warn!("Ambience {:?} is not loaded", key);
+        return;
+    };
+    let voice = ctl.controller.play_immediately(
+        sound.decoder().repeat_infinite(),
+        AudioBus::Ambience,
+        0.0,
+        0.0,
+    );
+    voice.ramp_volume(settings.ambience_volume, now, len);
+    zones.ambience = Some((key.clone(), voice));
+}
+
+/// Stop the ambience and dry up the sfx bus, when the level goes away
+fn leave_audio_zones(
+    q_mixer: Query<&PrecisionMixerControl>,
+    mut zones: ResMut<ListenerZones>,
+) {
+    if let Some((_, voice)) = &zones.ambience {
+        voice.stop();
+    }
+    *zones = ListenerZones::default();
+    let Ok(ctl) = q_mixer.get_single() else {
+        return;
+    };
+    let default = AudioZone::default();
+    let filters = [
+        (AudioParam::Lowpass, default.lowpass),
+        (AudioParam::Highpass, default.highpass),
+    ];
+    for (param, value) in default.reverb.params().into_iter().chain(filters) {
+        ctl.controller.set_bus_param(AudioBus::Sfx, param, value);
+    }
+}
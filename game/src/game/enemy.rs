@@ -2,25 +2,32 @@
 use bevy_inspector_egui::quick::FilterQueryInspectorPlugin;
 use rand::distributions::Standard;
 use rapier2d::geometry::SharedShape;
-use rapier2d::parry::query::TOIStatus;
-use rapier2d::prelude::{Group, InteractionGroups};
 use theseeker_engine::animation::SpriteAnimationBundle;
 use theseeker_engine::assets::animation::SpriteAnimation;
 use theseeker_engine::ballistics_math::ballistic_speed;
 use theseeker_engine::gent::{Gent, GentPhysicsBundle, TransformGfxFromGent};
+use theseeker_engine::physics::controller::{
+    KinematicController, KinematicSet,
+};
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
+};
 use theseeker_engine::physics::{
-    into_vec2, update_sprite_colliders, AnimationCollider, Collider,
-    LinearVelocity, PhysicsWorld, ShapeCaster, ENEMY, ENEMY_ATTACK, ENEMY_HURT,
-    ENEMY_INSIDE, GROUND, PLAYER, SENSOR,
+    is_walkable, project_on_slope, update_sprite_colliders, AnimationCollider,
+    Collider, LinearVelocity, PhysicsQuery, PhysicsWorld, ShapeCaster,
 };
 use theseeker_engine::script::ScriptPlayer;
 
 use super::physics::Knockback;
 use super::player::{Player, PlayerConfig, StatusModifier, Stealthing};
-use crate::game::attack::arc_attack::Projectile;
+use crate::game::attack::arc_attack::{
+    OnImpact, Projectile, ProjectileImpact,
+};
 use crate::game::attack::particles::ArcParticleEffectHandle;
 use crate::game::attack::*;
 use crate::game::gentstate::*;
+use crate::game::music::Boss;
+use crate::game::water::InWater;
 use crate::graphics::particles_util::BuildParticles;
 use crate::prelude::*;
 
@@ -28,6 +35,7 @@ pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
+        app.warn_on_tick_rate_change("Enemy AI (state durations in ticks)");
         app.add_systems(
             GameTickUpdate,
             (setup_enemy.run_if(in_state(GameState::Playing)))
@@ -69,6 +77,7 @@ pub enum EnemyStateSet {
 
 #[derive(Bundle, LdtkEntity, Default)]
 pub struct EnemyBlueprintBundle {
+    #[with(EnemyBlueprint::from_entity_instance)]
     marker: EnemyBlueprint,
 }
 
@@ -105,6 +114,20 @@ impl EnemySpawner {
 pub struct EnemyBlueprint {
     /// Hp added from spawner due to number of enemies killed.
     bonus_hp: u32,
+    /// Gets the [`Boss`] music for as long as it is alive
+    boss: bool,
+}
+
+impl EnemyBlueprint {
+    pub fn from_entity_instance(entity_instance: &EntityInstance) -> Self {
+        Self {
+            bonus_hp: 0,
+            boss: entity_instance
+                .get_bool_field("boss")
+                .copied()
+                .unwrap_or(false),
+        }
+    }
 }
 
 #[derive(Bundle)]
@@ -203,6 +226,7 @@ fn spawn_enemy(
                             EnemyBlueprintBundle {
                                 marker: EnemyBlueprint {
                                     bonus_hp: 20 * killed.0 as u32,
+                                    boss: false,
                                 },
                             },
                             TransformBundle::from_transform(*transform),
@@ -222,7 +246,9 @@ fn setup_enemy(
         Entity,
         Ref<EnemyBlueprint>,
     )>,
+    mut rng: ResMut<GameRng>,
     mut commands: Commands,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (mut xf_gent, e_gent, bp) in q.iter_mut() {
         if !bp.is_added() {
@@ -244,27 +270,28 @@ fn setup_enemy(
                 phys: GentPhysicsBundle {
                     // need to find a way to offset this one px toward back of enemys facing
                     // direction
+                    // no `CollisionLayers` here, the groups get swapped
+                    // to `ENEMY_INSIDE` and back while the player is inside
                     collider: Collider::cuboid(
                         16.0,
                         10.0,
-                        InteractionGroups {
-                            memberships: ENEMY,
-                            filter: Group::all(),
-                        },
+                        collision_layers.groups(&CollisionLayers::new("enemy")),
                     ),
                     shapecast: ShapeCaster {
                         shape: SharedShape::cuboid(22.0, 10.0),
                         direction: Direction2d::NEG_Y,
                         origin: Vec2::new(0.0, -2.0),
                         max_toi: 0.0,
-                        interaction: InteractionGroups {
-                            memberships: ENEMY,
-                            filter: GROUND,
-                        },
+                        interaction: collision_layers
+                            .query_groups("enemy", &["ground", "one_way"]),
                     },
                     linear_velocity: LinearVelocity(Vec2::ZERO),
                 },
             },
+            KinematicController::new(collision_layers.query_groups(
+                "enemy",
+                &["player", "ground", "one_way"],
+            )),
             Navigation::Grounded,
             Range::None,
             Target(None),
@@ -272,7 +299,7 @@ fn setup_enemy(
                 current: 100 + bp.bonus_hp,
                 max: 100 + bp.bonus_hp,
             },
-            Role::random(),
+            Role::random(&mut **rng),
             Facing::Right,
             Patrolling,
             Idle,
@@ -315,6 +342,9 @@ fn setup_enemy(
             },
             StateDespawnMarker,
         ));
+        if bp.boss {
+            commands.entity(e_gent).insert(Boss);
+        }
         commands.entity(e_gfx).remove::<EnemyBlueprint>();
     }
 }
@@ -355,7 +385,11 @@ impl Plugin for EnemyBehaviorPlugin {
                     .run_if(in_state(AppState::InGame))
                     .in_set(EnemyStateSet::Behavior)
                     .before(update_sprite_colliders),
-                (move_collide, remove_inside)
+                (
+                    move_collide.before(KinematicSet),
+                    enemy_blocked.after(KinematicSet),
+                    remove_inside,
+                )
                     .chain()
                     .in_set(EnemyStateSet::Collisions),
             ),
@@ -397,7 +431,7 @@ impl GenericState for Chasing {}
 
 #[derive(Component, Debug)]
 #[component(storage = "SparseSet")]
-struct Aggroed;
+pub struct Aggroed;
 
 impl GentState for Aggroed {}
 impl Transitionable<Patrolling> for Aggroed {
@@ -480,7 +514,7 @@ impl GenericState for Waiting {}
 pub struct JustGotHitMarker;
 
 #[derive(Component, Reflect)]
-enum Navigation {
+pub(crate) enum Navigation {
     Grounded,
     // Falling,
     Blocked,
@@ -493,8 +527,7 @@ enum Role {
 }
 
 impl Role {
-    fn random() -> Role {
-        let mut rng = rand::thread_rng();
+    fn random(rng: &mut impl Rng) -> Role {
         rng.gen()
     }
 }
@@ -674,6 +707,7 @@ fn patrolling(
         ),
         (With<Patrolling>, With<Enemy>),
     >,
+    mut rng: ResMut<GameRng>,
 ) {
     for (range, mut transitions, mut additions, maybe_waiting) in
         query.iter_mut()
@@ -689,7 +723,7 @@ fn patrolling(
                 if let Some(waiting) = maybe_waiting {
                     if waiting.ticks >= waiting.max_ticks {
                         transitions.push(Waiting::new_transition(Walking {
-                            max_ticks: rand::thread_rng().gen_range(24..300),
+                            max_ticks: rng.gen_range(24..300),
                             ticks: 0,
                         }));
                     }
@@ -785,11 +819,11 @@ fn aggro(
             With<Waiting>,
         ),
     >,
+    mut rng: ResMut<GameRng>,
 ) {
     for (role, range, target, mut velocity, mut transitions) in query.iter_mut()
     {
         if let Some(p_entity) = target.0 {
-            let mut rng = rand::thread_rng();
             // return to patrol if out of aggro range
             if matches!(range, Range::Far) {
                 transitions.push(Aggroed::new_transition(Patrolling));
@@ -836,11 +870,15 @@ fn ranged_attack(
         ),
         (With<Enemy>, Without<Knockback>),
     >,
-    player_query: Query<&Transform, With<Player>>,
+    player_query: Query<
+        (&Transform, Option<&LinearVelocity>),
+        (With<Player>, Without<Enemy>),
+    >,
     mut commands: Commands,
     config: Res<PlayerConfig>,
     time: Res<GameTime>,
     particle_effect: Res<ArcParticleEffectHandle>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (
         entity,
@@ -864,7 +902,8 @@ fn ranged_attack(
             add_q.add(Idle);
         }
         // if player isnt alive, do nothing, we will transition back once animation finishes
-        let Ok(transform) = player_query.get(attack.target) else {
+        let Ok((transform, player_vel)) = player_query.get(attack.target)
+        else {
             continue;
         };
         if attack.ticks == RangedAttack::STARTUP * 8 {
@@ -875,41 +914,21 @@ fn ranged_attack(
                 * 0.5;
             // how far is the ceiling above the mid point of the projectile trajectory?
             let mut ceiling = f32::MAX;
-            if let Some((hit_e, hit)) = spatial_query.ray_cast(
-                mid_pt,
-                Vec2::new(0.0, 1.0),
-                f32::MAX,
-                true,
-                InteractionGroups::new(ENEMY, GROUND),
-                None,
-            ) {
-                // only count it if the ray didn't start underground
-                if hit.toi != 0.0 {
-                    ceiling =
-                        mid_pt.y + hit.toi - enemy_transform.translation().y;
-                } else {
-                    // if it did start underground, fire another one to find how far underground, and then fire again from there + 0.1
-                    if let Some((hit_e, hit)) = spatial_query.ray_cast(
-                        mid_pt,
-                        Vec2::new(0.0, 1.0),
-                        f32::MAX,
-                        false,
-                        InteractionGroups::new(ENEMY, GROUND),
-                        None,
-                    ) {
-                        if let Some((hit_e, hit_2)) = spatial_query.ray_cast(
-                            mid_pt + Vec2::new(0.0, hit.toi + 0.001),
-                            Vec2::new(0.0, 1.0),
-                            f32::MAX,
-                            true,
-                            InteractionGroups::new(ENEMY, GROUND),
-                            None,
-                        ) {
-                            ceiling = mid_pt.y + hit.toi + hit_2.toi + 0.001
-                                - enemy_transform.translation().y;
-                        }
-                    }
-                }
+            // skip the ground the ray starts in (those hits have toi 0), if any
+            let ceiling_hit = spatial_query
+                .ray_cast_all(
+                    mid_pt,
+                    Vec2::new(0.0, 1.0),
+                    f32::MAX,
+                    true,
+                    &PhysicsQuery::new(
+                        collision_layers.query_groups("enemy", &["ground"]),
+                    ),
+                )
+                .into_iter()
+                .find(|(_, hit)| hit.toi != 0.0);
+            if let Some((_, hit)) = ceiling_hit {
+                ceiling = mid_pt.y + hit.toi - enemy_transform.translation().y;
             }
 
             // account for projectile width
@@ -924,6 +943,26 @@ fn ranged_attack(
             let mut speed =
                 ballistic_speed(Range::RANGED, gravity, relative_height)
                     * rng_factor as f32;
+            // lead the player, unless they can't be reached where they are
+            // going, then at least aim where they are
+            let player_vel = player_vel.map(|v| v.0).unwrap_or_default();
+            let aim = |speed| {
+                Projectile::with_vel_leading(
+                    transform.translation.xy(),
+                    player_vel,
+                    enemy_transform.translation().xy(),
+                    speed,
+                    gravity,
+                )
+                .or_else(|| {
+                    Projectile::with_vel(
+                        transform.translation.xy(),
+                        enemy_transform.translation().xy(),
+                        speed,
+                        gravity,
+                    )
+                })
+            };
             let max_attempts = 10;
             // Define default arc as 50ish degree shot with in the direction of the player
             let mut final_solution = Projectile {
@@ -933,24 +972,14 @@ fn ranged_attack(
                 )),
             };
             for i in 0..max_attempts {
-                if let Some(mut projectile) = Projectile::with_vel(
-                    transform.translation.xy(),
-                    enemy_transform.translation().xy(),
-                    speed,
-                    gravity,
-                ) {
+                if let Some(mut projectile) = aim(speed) {
                     let max_proj_h = projectile.vel.y.powi(2) / (2.0 * gravity);
                     if max_proj_h >= ceiling {
                         let max_vel_y = (ceiling * (2.0 * gravity)).sqrt();
                         let max_vel_x =
                             max_vel_y / projectile.vel.y * projectile.vel.x;
                         let max_vel = Vec2::new(max_vel_x, max_vel_y).length();
-                        if let Some(projectile_2) = Projectile::with_vel(
-                            transform.translation.xy(),
-                            enemy_transform.translation().xy(),
-                            max_vel,
-                            gravity,
-                        ) {
+                        if let Some(projectile_2) = aim(max_vel) {
                             projectile = projectile_2
                         } else {
                             // attempts to fire anyway, even though ceiling will always block the shot
@@ -972,11 +1001,14 @@ fn ranged_attack(
                     Attack::new(1000, entity)
                         .set_stat_mod(StatusModifier::basic_ice_spider()),
                     final_solution,
+                    OnImpact(ice_splash),
                     Collider::cuboid(
                         5.,
                         5.,
-                        InteractionGroups::new(ENEMY_ATTACK, PLAYER),
+                        collision_layers
+                            .groups(&CollisionLayers::new("enemy_attack")),
                     ),
+                    CollisionLayers::new("enemy_attack"),
                     TransformBundle::from(Transform::from_translation(
                         enemy_transform.translation(),
                     )),
@@ -991,6 +1023,30 @@ fn ranged_attack(
     }
 }
 
+/// Ticks the ice left by a spider's projectile stays on the ground
+const ICE_SPLASH_TICKS: u32 = 48;
+
+/// Leaves a patch of ice where a spider's projectile lands, which doesn't
+/// hurt, but slows the player down like a direct hit
+fn ice_splash(commands: &mut Commands, impact: &ProjectileImpact) {
+    let impact = *impact;
+    commands.add(move |world: &mut World| {
+        let layers = CollisionLayers::new("enemy_attack");
+        let groups = world.resource::<CollisionLayerRegistry>().groups(&layers);
+        world.spawn((
+            Name::new("IceSplash"),
+            Attack::new(ICE_SPLASH_TICKS, impact.attacker)
+                .with_damage(0)
+                .set_stat_mod(StatusModifier::basic_ice_spider()),
+            Collider::cuboid(16., 6., groups),
+            layers,
+            TransformBundle::from(Transform::from_translation(
+                impact.position.extend(0.0),
+            )),
+        ));
+    });
+}
+
 fn melee_attack(
     mut query: Query<
         (
@@ -1003,6 +1059,7 @@ fn melee_attack(
         With<Enemy>,
     >,
     mut commands: Commands,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (entity, mut attack, facing, mut trans_q, gent) in query.iter_mut() {
         attack.ticks += 1;
@@ -1010,10 +1067,10 @@ fn melee_attack(
             // spawn attack hitbox collider as child
             let collider = commands
                 .spawn((
-                    Collider::empty(InteractionGroups {
-                        memberships: SENSOR,
-                        filter: PLAYER,
-                    }),
+                    collision_layers.empty_collider(
+                        &CollisionLayers::new("enemy_attack"),
+                    ),
+                    CollisionLayers::new("enemy_attack"),
                     TransformBundle::from_transform(Transform::default()),
                     AnimationCollider(gent.e_gfx),
                     Attack::new(8, entity),
@@ -1222,87 +1279,120 @@ fn chasing(
     }
 }
 
-fn move_collide(
+/// How far below its center an enemy looks for the ground it is walking on
+const GROUND_PROBE_DIST: f32 = 16.0;
+
+/// Follows the slope of the ground, and stops at the edges of platforms
+///
+/// The [`KinematicController`] does the moving, and [`enemy_blocked`] checks
+/// what the enemy ran into. In water, the vertical velocity is left to
+/// [`water_physics`](crate::game::water), so the enemy floats up.
+pub(crate) fn move_collide(
     mut query: Query<
         (
             &mut LinearVelocity,
-            &mut Transform,
+            &Transform,
             &mut Navigation,
-            &Collider,
             Has<Knockback>,
+            Has<InWater>,
         ),
         With<Enemy>,
     >,
     time: Res<GameTime>,
     spatial_query: Res<PhysicsWorld>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
-    for (mut linear_velocity, mut transform, mut nav, collider, is_knocked) in
+    let ground =
+        collision_layers.query_groups("enemy", &["ground", "one_way"]);
+    for (mut linear_velocity, transform, mut nav, is_knocked, in_water) in
         query.iter_mut()
     {
-        let shape = collider.0.shared_shape().clone();
         let dir = linear_velocity.x.signum();
         let x_len = linear_velocity.x.abs();
         // TODO: should be based on collider half extent x
         let front = transform.translation.x + 10. * dir;
-        let z = transform.translation.z;
-        let mut projected_velocity = linear_velocity.xy();
-
-        // Simplified version of the player collisions
-        // If the enemy encounters a collision with the player, wall or edge of platform, it sets
-        // the Navigation component to Navigation::Blocked
-        while let Ok(shape_dir) = Direction2d::new(linear_velocity.0) {
-            if let Some((e, first_hit)) = spatial_query.shape_cast(
-                transform.translation.xy(),
-                shape_dir,
-                &*shape,
-                linear_velocity.length() / time.hz as f32 + 0.5,
-                InteractionGroups {
-                    memberships: ENEMY,
-                    // combination of the PLAYER + GROUND Groups
-                    filter: Group::from_bits_truncate(0b10001),
-                },
-                None,
-            ) {
-                if first_hit.status != TOIStatus::Penetrating {
-                    let sliding_plane = into_vec2(first_hit.normal1);
-                    projected_velocity = linear_velocity.xy()
-                        - sliding_plane
-                            * linear_velocity.xy().dot(sliding_plane);
-                    linear_velocity.0 = projected_velocity;
-                    if !is_knocked {
-                        *nav = Navigation::Blocked;
-                    }
-                } else {
-                    break;
-                }
-            } else {
-                break;
-            };
+
+        // Follow the slope of the ground below, so that walking down a ramp
+        // doesn't leave the enemy floating
+        if !is_knocked && !in_water {
+            linear_velocity.y = 0.0;
+        }
+        let mut on_slope = false;
+        if let Some((_, hit)) = spatial_query.ray_cast(
+            transform.translation.xy(),
+            Vec2::NEG_Y,
+            GROUND_PROBE_DIST,
+            true,
+            ground,
+            None,
+        ) {
+            let normal = Vec2::new(hit.normal.x, hit.normal.y);
+            if !is_knocked
+                && !in_water
+                && normal.x != 0.0
+                && is_walkable(normal)
+            {
+                linear_velocity.0 =
+                    project_on_slope(linear_velocity.0, normal);
+                on_slope = true;
+            }
         }
 
         // Raycast from underground directly below the enemy in direction of movement, detecting the edges of a platform from
-        // inside
-        if let Some((entity, first_hit)) = spatial_query.ray_cast(
+        // inside.
+        // On slopes, that would hit every seam between the slope tiles, so instead
+        // check that there is still ground below where the enemy is going.
+        if on_slope {
+            let next = transform.translation.xy()
+                + linear_velocity.0 * (1.0 / time.hz as f32);
+            let has_ground = spatial_query
+                .ray_cast(
+                    Vec2::new(next.x + 10. * dir, next.y),
+                    Vec2::NEG_Y,
+                    GROUND_PROBE_DIST,
+                    true,
+                    ground,
+                    None,
+                )
+                .is_some();
+            if !has_ground {
+                if !is_knocked {
+                    *nav = Navigation::Blocked;
+                }
+                linear_velocity.0 = Vec2::ZERO;
+            }
+        } else if let Some((_, first_hit)) = spatial_query.ray_cast(
             // TODO: should be based on collider half extent y + a little
             Vec2::new(front, transform.translation.y - 10.),
             Vec2::new(dir, 0.),
             x_len / time.hz as f32,
             false,
-            InteractionGroups {
-                memberships: ENEMY,
-                filter: GROUND,
-            },
+            ground,
             None,
         ) {
             if !is_knocked {
                 *nav = Navigation::Blocked;
             }
-            projected_velocity.x = first_hit.toi * dir;
+            linear_velocity.x = first_hit.toi * dir;
         }
+    }
+}
 
-        transform.translation = (transform.translation.xy()
-            + projected_velocity * (1.0 / time.hz as f32))
-            .extend(z);
+/// Walls and the player block enemies
+fn enemy_blocked(
+    mut query: Query<
+        (
+            &KinematicController,
+            &mut Navigation,
+            Has<Knockback>,
+        ),
+        With<Enemy>,
+    >,
+) {
+    for (controller, mut nav, is_knocked) in query.iter_mut() {
+        if controller.on_wall() && !is_knocked {
+            *nav = Navigation::Blocked;
+        }
     }
 }
 
@@ -1313,22 +1403,19 @@ fn remove_inside(
     >,
     mut commands: Commands,
     spatial_query: Res<PhysicsWorld>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (entity, transform, mut collider) in query.iter_mut() {
         let intersections = spatial_query.intersect(
             transform.translation().xy(),
             collider.0.shape(),
-            InteractionGroups {
-                memberships: ENEMY_INSIDE,
-                filter: PLAYER,
-            },
+            collision_layers.query_groups("enemy_inside", &["player"]),
             Some(entity),
         );
         if intersections.is_empty() {
-            collider.0.set_collision_groups(InteractionGroups {
-                memberships: ENEMY,
-                filter: Group::all(),
-            });
+            collider.0.set_collision_groups(
+                collision_layers.groups(&CollisionLayers::new("enemy")),
+            );
             commands.entity(entity).remove::<Inside>();
         }
     }
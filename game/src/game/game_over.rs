@@ -8,6 +8,7 @@ use sickle_ui::widgets::prelude::*;
 use theseeker_engine::gent::Gent;
 use theseeker_engine::prelude::{in_state, Color, GameTickUpdate, GameTime};
 
+use crate::audio::Muffle;
 use crate::camera::MainCamera;
 use crate::game::attack::KillCount;
 use crate::game::gentstate::Dead;
@@ -34,16 +35,32 @@ impl Plugin for GameOverPlugin {
                 .before(PlayerStateSet::Transition)
                 .run_if(in_state(AppState::InGame)),
         );
-        app.add_systems(Update, update_fade_in);
+        app.init_resource::<Muffle>();
+        app.add_systems(Update, (update_fade_in, game_over_muffle));
     }
 }
 
+/// The game over screen, for as long as it is shown
+#[derive(Component)]
+pub struct GameOverScreen;
+
 #[derive(Component)]
 #[component(storage = "SparseSet")]
 pub struct FadeIn {
     progress: f32,
 }
 
+/// Muffle the audio for as long as the game over screen is up
+fn game_over_muffle(
+    q_screen: Query<(), With<GameOverScreen>>,
+    mut muffle: ResMut<Muffle>,
+) {
+    let game_over = !q_screen.is_empty();
+    if muffle.game_over != game_over {
+        muffle.game_over = game_over;
+    }
+}
+
 pub fn update_fade_in(
     mut commands: Commands,
     time: Res<Time>,
@@ -102,6 +119,7 @@ pub fn on_game_over(
                 ..default()
             },
             FadeIn { progress: 0.0 },
+            GameOverScreen,
             TargetCamera(cam_e),
             StateDespawnMarker,
         ),
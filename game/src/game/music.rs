@@ -0,0 +1,345 @@
+//! Adaptive music
+//!
+//! The music has an intensity level, from exploring to fighting a boss,
+//! worked out from what is going on in the game. Each level has its own mix
+//! of the current track's stems (the layers), and changing level fades
+//! between them in time with the music. It is all set up in a config asset.
+
+use bevy_common_assets::toml::TomlAssetPlugin;
+use theseeker_engine::audio::music::{MusicPlayer, MusicSet, MusicSync};
+
+use crate::game::attack::Health;
+use crate::game::enemy::Aggroed;
+use crate::game::gentstate::Dead;
+use crate::game::player::Player;
+use crate::prelude::*;
+
+pub struct MusicIntensityPlugin;
+
+impl Plugin for MusicIntensityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(
+            TomlAssetPlugin::<MusicIntensityConfig>::new(&["intensity.toml"]),
+        );
+        app.init_resource::<MusicIntensity>();
+        app.add_systems(
+            GameTickUpdate,
+            update_music_intensity
+                .before(MusicSet)
+                .run_if(in_state(GameState::Playing))
+                .run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+/// How intense the music currently is
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(PartialOrd, Ord)]
+pub enum MusicIntensity {
+    #[default]
+    Exploration,
+    Tension,
+    Combat,
+    Boss,
+}
+
+/// Put on an enemy to switch to the boss layers for as long as it is alive
+///
+/// Enemies placed in LDtk get it when their `boss` field is set.
+#[derive(Component, Default, Debug)]
+pub struct Boss;
+
+/// Adaptive music config asset (`*.intensity.toml`)
+///
+/// ```toml
+/// tension_enemies = 1
+/// combat_enemies = 3
+///
+/// [layers.exploration]
+/// exploration = 1.0
+///
+/// [layers.combat]
+/// exploration = 0.5
+/// combat = 1.0
+/// ```
+#[derive(Asset, Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[derive(TypePath)]
+#[serde(default)]
+pub struct MusicIntensityConfig {
+    /// How many aggroed enemies it takes to get to [`MusicIntensity::Tension`]
+    pub tension_enemies: usize,
+    /// How many aggroed enemies it takes to get to [`MusicIntensity::Combat`]
+    pub combat_enemies: usize,
+    /// At or below this much health (fraction of max), the intensity goes up
+    /// by one level (but never to the boss level)
+    pub low_health: f32,
+    /// Seconds to stay at a level before it is allowed to go down again
+    pub hold: f32,
+    /// Fade used when the intensity goes up
+    pub rise: MusicFade,
+    /// Fade used when the intensity goes down
+    pub fall: MusicFade,
+    pub layers: MusicLayers,
+}
+
+impl Default for MusicIntensityConfig {
+    fn default() -> Self {
+        Self {
+            tension_enemies: 1,
+            combat_enemies: 3,
+            low_health: 0.25,
+            hold: 4.0,
+            rise: MusicFade {
+                sync: MusicSync::NextBar,
+                beats: 1.0,
+            },
+            fall: MusicFade {
+                sync: MusicSync::NextBar,
+                beats: 8.0,
+            },
+            layers: MusicLayers::default(),
+        }
+    }
+}
+
+/// When a change of layers starts, and how long it takes
+#[derive(Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize)]
+pub struct MusicFade {
+    #[serde(default)]
+    pub sync: MusicSync,
+    /// Length of the fade, in beats
+    pub beats: f32,
+}
+
+/// Gain of each stem (by name), at each intensity level
+///
+/// Stems that are not listed for a level are silent at that level.
+/// Stems that are not listed anywhere are left alone.
+#[derive(Debug, Default, Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct MusicLayers {
+    pub exploration: HashMap<String, f32>,
+    pub tension: HashMap<String, f32>,
+    pub combat: HashMap<String, f32>,
+    pub boss: HashMap<String, f32>,
+}
+
+impl MusicLayers {
+    pub fn get(&self, intensity: MusicIntensity) -> &HashMap<String, f32> {
+        match intensity {
+            MusicIntensity::Exploration => &self.exploration,
+            MusicIntensity::Tension => &self.tension,
+            MusicIntensity::Combat => &self.combat,
+            MusicIntensity::Boss => &self.boss,
+        }
+    }
+
+    /// Every stem that is mentioned at any level
+    pub fn stems(&self) -> HashSet<&str> {
+        [&self.exploration, &self.tension, &self.combat, &self.boss]
+            .into_iter()
+            .flat_map(|layer| layer.keys().map(|k| k.as_str()))
+            .collect()
+    }
+}
+
+impl MusicIntensityConfig {
+    /// The intensity the game state calls for
+    pub fn intensity(
+        &self,
+        aggroed: usize,
+        health: f32,
+        boss: bool,
+    ) -> MusicIntensity {
+        if boss {
+            return MusicIntensity::Boss;
+        }
+        let mut intensity = if aggroed >= self.combat_enemies {
+            MusicIntensity::Combat
+        } else if aggroed >= self.tension_enemies {
+            MusicIntensity::Tension
+        } else {
+            MusicIntensity::Exploration
+        };
+        if health <= self.low_health {
+            intensity = match intensity {
+                MusicIntensity::Exploration => MusicIntensity::Tension,
+                _ => MusicIntensity::Combat,
+            };
+        }
+        intensity
+    }
+}
+
+/// What the layers were last set up for
+#[derive(Default)]
+struct AppliedLayers {
+    key: Option<String>,
+    intensity: MusicIntensity,
+    /// Game tick of the last change of intensity
+    changed_at: u64,
+}
+
+fn update_music_intensity(
+    preloaded: Res<PreloadedAssets>,
+    cfgs: Res<Assets<MusicIntensityConfig>>,
+    gt: Res<GameTime>,
+    q_aggroed: Query<(), With<Aggroed>>,
+    q_boss: Query<(), (With<Boss>, Without<Dead>)>,
+    q_player: Query<&Health, With<Player>>,
+    mut intensity: ResMut<MusicIntensity>,
+    mut music: ResMut<MusicPlayer>,
+    mut applied: Local<AppliedLayers>,
+) {
+    let Some(cfg) = preloaded
+        .get_single_assetid::<MusicIntensityConfig>("cfg.music_intensity")
+        .and_then(|id| cfgs.get(id))
+    else {
+        return;
+    };
+
+    let health = q_player
+        .get_single()
+        .map(|h| h.current as f32 / h.max.max(1) as f32)
+        .unwrap_or(1.0);
+    let wanted = cfg.intensity(
+        q_aggroed.iter().count(),
+        health,
+        !q_boss.is_empty(),
+    );
+    // going up is immediate, but calming down waits a bit,
+    // so that the music doesn't flip back and forth
+    let held =
+        gt.tick().saturating_sub(applied.changed_at) as f32 / gt.hz as f32;
+    if wanted > *intensity || (wanted < *intensity && held >= cfg.hold) {
+        *intensity = wanted;
+        applied.changed_at = gt.tick();
+    }
+
+    let Some(key) = music.current_key() else {
+        applied.key = None;
+        return;
+    };
+    let (fade, sync) = if applied.key.as_deref() != Some(key) {
+        // a new track: its layers should be right from the start
+        (0.0, MusicSync::Now)
+    } else if *intensity > applied.intensity {
+        (cfg.rise.beats, cfg.rise.sync)
+    } else if *intensity < applied.intensity {
+        (cfg.fall.beats, cfg.fall.sync)
+    } else {
+        return;
+    };
+    applied.key = Some(key.to_owned());
+    applied.intensity = *intensity;
+    let layer = cfg.layers.get(*intensity);
+    for stem in cfg.layers.stems() {
+        let gain = layer.get(stem).copied().unwrap_or(0.0);
+        music.fade_stem(stem, gain, fade, sync);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intensity_from_enemies() {
+        // 1 for tension, 3 for combat
+        let cfg = MusicIntensityConfig::default();
+        assert_eq!(
+            cfg.intensity(0, 1.0, false),
+            MusicIntensity::Exploration
+        );
+        assert_eq!(
+            cfg.intensity(1, 1.0, false),
+            MusicIntensity::Tension
+        );
+        assert_eq!(
+            cfg.intensity(2, 1.0, false),
+            MusicIntensity::Tension
+        );
+        assert_eq!(
+            cfg.intensity(3, 1.0, false),
+            MusicIntensity::Combat
+        );
+        assert_eq!(
+            cfg.intensity(10, 1.0, false),
+            MusicIntensity::Combat
+        );
+    }
+
+    #[test]
+    fn low_health_raises_intensity() {
+        // at or below 25%
+        let cfg = MusicIntensityConfig::default();
+        assert_eq!(
+            cfg.intensity(0, 0.26, false),
+            MusicIntensity::Exploration
+        );
+        assert_eq!(
+            cfg.intensity(0, 0.25, false),
+            MusicIntensity::Tension
+        );
+        assert_eq!(
+            cfg.intensity(1, 0.1, false),
+            MusicIntensity::Combat
+        );
+        // but not past combat
+        assert_eq!(
+            cfg.intensity(5, 0.0, false),
+            MusicIntensity::Combat
+        );
+    }
+
+    #[test]
+    fn boss_wins() {
+        let cfg = MusicIntensityConfig::default();
+        assert_eq!(
+            cfg.intensity(0, 1.0, true),
+            MusicIntensity::Boss
+        );
+        assert_eq!(
+            cfg.intensity(5, 0.1, true),
+            MusicIntensity::Boss
+        );
+    }
+
+    #[test]
+    fn thresholds_from_config() {
+        let cfg = MusicIntensityConfig {
+            tension_enemies: 0,
+            combat_enemies: 2,
+            low_health: 0.0,
+            ..default()
+        };
+        assert_eq!(
+            cfg.intensity(0, 1.0, false),
+            MusicIntensity::Tension
+        );
+        assert_eq!(
+            cfg.intensity(2, 1.0, false),
+            MusicIntensity::Combat
+        );
+        assert_eq!(
+            cfg.intensity(0, 0.0, false),
+            MusicIntensity::Combat
+        );
+    }
+
+    #[test]
+    fn default_config_parses() {
+        let cfg: MusicIntensityConfig = toml::from_str(include_str!(
+            "../../../assets/music.intensity.toml"
+        ))
+        .unwrap();
+        assert_eq!(cfg.tension_enemies, 1);
+        assert_eq!(cfg.combat_enemies, 3);
+        assert_eq!(cfg.rise.sync, MusicSync::NextBar);
+        assert!(cfg.layers.stems().contains("combat"));
+        assert!(!cfg.layers.boss.is_empty());
+    }
+}
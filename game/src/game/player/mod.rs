@@ -9,17 +9,23 @@ use leafwing_input_manager::{Actionlike, InputManagerBundle};
 use player_anim::PlayerAnimationPlugin;
 use player_behaviour::PlayerBehaviorPlugin;
 use player_weapon::PlayerWeaponPlugin;
-use rapier2d::geometry::{Group, InteractionGroups};
+use rapier2d::geometry::InteractionGroups;
 use rapier2d::na::Vector3;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 use theseeker_engine::animation::SpriteAnimationBundle;
-use theseeker_engine::assets::config::{update_field, DynamicConfig};
+use theseeker_engine::assets::config::{
+    ConfigValidator, TypedConfigPlugin, ValidateConfig,
+};
+use theseeker_engine::audio::spatial::AudioListener;
 use theseeker_engine::gent::{Gent, GentPhysicsBundle, TransformGfxFromGent};
+use theseeker_engine::input::replay::InputReplayPlugin;
 use theseeker_engine::input::InputManagerPlugin;
-use theseeker_engine::physics::{
-    Collider, LinearVelocity, ShapeCaster, GROUND, PLAYER,
+use theseeker_engine::physics::controller::KinematicController;
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
 };
+use theseeker_engine::physics::{Collider, LinearVelocity, ShapeCaster};
 
 use super::physics::Knockback;
 use crate::game::attack::*;
@@ -30,16 +36,15 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(PlayerConfig::default());
+        app.add_plugins(TypedConfigPlugin::<PlayerConfig>::new("cfg.player"));
+        app.warn_on_tick_rate_change(
+            "Player movement (PlayerConfig values in ticks)",
+        );
         app.add_systems(
             GameTickUpdate,
-            (
-                load_player_config,
-                load_player_stats
-                    .before(PlayerStateSet::Behavior)
-                    .after(load_player_config)
-                    .run_if(resource_changed::<PlayerConfig>),
-            ),
+            load_player_stats
+                .before(PlayerStateSet::Behavior)
+                .run_if(resource_changed::<PlayerConfig>),
         );
         app.add_systems(Startup, load_dash_asset);
         app.add_systems(
@@ -59,6 +64,7 @@ impl Plugin for PlayerPlugin {
         )
         .add_plugins((
             InputManagerPlugin::<PlayerAction>::default(),
+            InputReplayPlugin::<PlayerAction>::default(),
             PlayerBehaviorPlugin,
             PlayerTransitionPlugin,
             PlayerAnimationPlugin,
@@ -118,6 +124,7 @@ pub struct PlayerGfx {
 }
 
 #[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect)]
+#[derive(Serialize, Deserialize, EnumIter)]
 pub enum PlayerAction {
     Move,
     Jump,
@@ -154,11 +161,10 @@ impl Passives {
     //     Passives::default()
     // }
 
-    fn gain(&mut self) {
+    fn gain(&mut self, rng: &mut impl Rng) {
         // TODO: add checks for no passives remaining
         // TODO add limit on gaining past max passive slots?
         // does nothing if there are no more passives to gain
-        let mut rng = rand::thread_rng();
         if !self.locked.is_empty() {
             let i = rng.gen_range(0..self.locked.len());
             let passive = self.locked.swap_remove(i);
@@ -278,6 +284,7 @@ fn setup_player(
     parent_query: Query<Entity, With<Children>>,
     mut commands: Commands,
     config: Res<PlayerConfig>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (mut xf_gent, e_gent, parent) in q.iter_mut() {
         // TODO: proper way of ensuring z is correct
@@ -297,11 +304,8 @@ fn setup_player(
                     collider: Collider::cuboid(
                         4.0,
                         10.0,
-                        InteractionGroups {
-                            memberships: PLAYER,
-                            // should be more specific
-                            filter: Group::all(),
-                        },
+                        collision_layers
+                            .groups(&CollisionLayers::new("player")),
                     ),
                     shapecast: ShapeCaster {
                         shape: Collider::cuboid(
@@ -315,10 +319,10 @@ fn setup_player(
                         origin: Vec2::new(0.0, 0.0),
                         max_toi: f32::MAX,
                         direction: Direction2d::NEG_Y,
-                        interaction: InteractionGroups {
-                            memberships: PLAYER,
-                            filter: GROUND,
-                        },
+                        interaction: collision_layers.query_groups(
+                            "player",
+                            &["ground", "one_way"],
+                        ),
                     },
                     linear_velocity: LinearVelocity(Vec2::ZERO),
                 },
@@ -375,6 +379,11 @@ fn setup_player(
             // bundling things up because we reached max tuple
             (
                 Falling,
+                KinematicController::new(collision_layers.query_groups(
+                    "player",
+                    &["ground", "one_way"],
+                )),
+                CollisionLayers::new("player"),
                 CanDash {
                     remaining_cooldown: 0.0,
                     total_cooldown: 0.0,
@@ -382,6 +391,8 @@ fn setup_player(
                 CanStealth {
                     remaining_cooldown: 0.0,
                 },
+                // positional sounds are heard from the player
+                AudioListener,
             ),
             PlayerStats::init_from_config(&config),
             WallSlideTime(f32::MAX),
@@ -480,6 +491,17 @@ impl Transitionable<Jumping> for Grounded {
 impl Transitionable<Falling> for Grounded {
     type Removals = (Grounded, Idle, Running, Whirling);
 }
+// cant be Idle or Running if not Grounded
+impl Transitionable<Swimming> for Grounded {
+    type Removals = (Grounded, Idle, Running, Whirling);
+}
+
+/// In a water volume, replaces Grounded/Falling/Jumping
+#[derive(Component, Default, Debug)]
+#[component(storage = "SparseSet")]
+pub struct Swimming;
+impl GentState for Swimming {}
+impl GenericState for Swimming {}
 
 #[derive(Component, Debug, Default)]
 #[component(storage = "SparseSet")]
@@ -698,6 +720,11 @@ pub struct CoyoteTime(f32);
 #[derive(Component, Default, Debug)]
 pub struct JumpCount(u8);
 
+/// Lets the player fall through one-way platforms for the given number of ticks
+#[derive(Component, Default, Debug)]
+#[component(storage = "SparseSet")]
+pub struct DropThrough(u32);
+
 /// Indicates that sliding is tracked for this entity
 #[derive(Component, Default, Debug)]
 pub struct WallSlideTime(f32);
@@ -726,7 +753,10 @@ pub struct WhirlAbility {
     pub energy: f32,
 }
 
+/// Loaded from `player.cfg.toml`
 #[derive(Resource, Debug, Default)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PlayerConfig {
     /// The maximum horizontal velocity the player can move at.
     ///
@@ -772,9 +802,15 @@ pub struct PlayerConfig {
     /// and trying to walk into the wall
     sliding_friction: f32,
 
+    /// How many ticks does the player fall through one-way platforms for after pressing down?
+    drop_through_ticks: u32,
+
     /// How many ticks is the players velocity locked to zero after landing an attack?
     hitfreeze_ticks: u32,
 
+    /// How many seconds is the game frozen for when the player lands an attack?
+    pub hitstop_duration: f32,
+
     /// How many seconds does our character dash for?
     dash_duration: f32,
 
@@ -849,92 +885,106 @@ pub struct PlayerConfig {
     /// Velocity of the projectiles fired by the Bow weapon
     arrow_velocity: f32,
 
+    /// How many extra enemies an arrow can go through
+    arrow_pierce: u32,
+
+    /// How many times an arrow bounces off the ground before it stops
+    arrow_bounces: u32,
+
+    /// How much of its speed into the ground an arrow keeps when bouncing
+    arrow_bounce_restitution: f32,
+
+    /// Multiplies the gravity applied to arrows (0 for straight shots)
+    arrow_gravity_scale: f32,
+
+    /// How fast arrows turn towards the nearest enemy in front of the player.
+    /// 0 disables homing.
+    ///
+    /// (in radians/second)
+    arrow_homing_turn_rate: f32,
+
     /// How many kills to trigger a passive gain
     passive_gain_rate: u32,
-}
 
-fn load_player_config(
-    mut ev_asset: EventReader<AssetEvent<DynamicConfig>>,
-    cfgs: Res<Assets<DynamicConfig>>,
-    preloaded: Res<PreloadedAssets>,
-    mut player_config: ResMut<PlayerConfig>,
-    mut initialized_config: Local<bool>,
-) {
-    // convert from asset key string to bevy handle
-    let Some(cfg_handle) =
-        preloaded.get_single_asset::<DynamicConfig>("cfg.player")
-    else {
-        return;
-    };
-    // The reason we do this here instead of in an AssetEvent::Added match arm, is because
-    // the Added match arm fires before preloaded updates with the asset key; as a result
-    // you can't tell what specific DynamicConfig loaded in like that.
-    if !*initialized_config {
-        if let Some(cfg) = cfgs.get(cfg_handle.clone()) {
-            update_player_config(&mut player_config, cfg);
-            println!("init:");
-            dbg!(&player_config);
-        }
-        *initialized_config = true;
-    }
-    for ev in ev_asset.read() {
-        if let AssetEvent::Modified { id } = ev {
-            if let Some(cfg) = cfgs.get(*id) {
-                if cfg_handle.id() == *id {
-                    println!("before:");
-                    dbg!(&player_config);
-                    update_player_config(&mut player_config, cfg);
-                    println!("after:");
-                    dbg!(&player_config);
-                }
-            }
-        }
-    }
-}
+    /// The maximum horizontal velocity the player can swim at.
+    ///
+    /// (in pixels/second)
+    swim_move_vel: f32,
+
+    /// The vertical velocity the player swims up or dives down at.
+    ///
+    /// (in pixels/second)
+    swim_vel: f32,
 
-#[rustfmt::skip]
-fn update_player_config(config: &mut PlayerConfig, cfg: &DynamicConfig) {
-    let mut errors = Vec::new();
-    update_field(&mut errors, &cfg.0, "max_move_vel", |val| config.max_move_vel = val);
-    update_field(&mut errors, &cfg.0, "max_fall_vel", |val| config.max_fall_vel = val);
-    update_field(&mut errors, &cfg.0, "move_accel_init", |val| config.move_accel_init = val);
-    update_field(&mut errors, &cfg.0, "move_accel", |val| config.move_accel = val);
-    update_field(&mut errors, &cfg.0, "jump_vel_init", |val| config.jump_vel_init = val);
-    update_field(&mut errors, &cfg.0, "jump_fall_accel", |val| config.jump_fall_accel = val);
-    update_field(&mut errors, &cfg.0, "fall_accel", |val| config.fall_accel = val);
-    update_field(&mut errors, &cfg.0, "max_coyote_time", |val| config.max_coyote_time = val);
-    update_field(&mut errors, &cfg.0, "sliding_friction", |val| config.sliding_friction = val);
-    update_field(&mut errors, &cfg.0, "hitfreeze_ticks", |val| config.hitfreeze_ticks = val as u32);
-    update_field(&mut errors, &cfg.0, "dash_duration", |val| config.dash_duration = val);
-    update_field(&mut errors, &cfg.0, "dash_down_duration", |val| config.dash_down_duration = val);
-    update_field(&mut errors, &cfg.0, "dash_velocity", |val| config.dash_velocity = val);
-    update_field(&mut errors, &cfg.0, "dash_down_horizontal_velocity", |val| config.dash_down_horizontal_velocity = val);
-    update_field(&mut errors, &cfg.0, "dash_down_vertical_velocity", |val| config.dash_down_vertical_velocity = val);
-    update_field(&mut errors, &cfg.0, "dash_cooldown_duration", |val| config.dash_cooldown_duration = val);
-    update_field(&mut errors, &cfg.0, "dash_down_cooldown_duration", |val| config.dash_down_cooldown_duration = val);
-    update_field(&mut errors, &cfg.0, "stealth_duration", |val| config.stealth_duration = val);
-    update_field(&mut errors, &cfg.0, "stealth_cooldown", |val| config.stealth_cooldown = val);
-    update_field(&mut errors, &cfg.0, "max_whirl_energy", |val| config.max_whirl_energy = val);
-    update_field(&mut errors, &cfg.0, "whirl_cost", |val| config.whirl_cost = val);
-    update_field(&mut errors, &cfg.0, "whirl_regen", |val| config.whirl_regen = val);
-    update_field(&mut errors, &cfg.0, "max_health", |val| config.max_health = val as u32);
-    update_field(&mut errors, &cfg.0, "wall_pushback", |val| config.wall_pushback = val);
-    update_field(&mut errors, &cfg.0, "wall_pushback_ticks", |val| config.wall_pushback_ticks = val as u32);
-    update_field(&mut errors, &cfg.0, "melee_self_pushback", |val| config.melee_self_pushback = val);
-    update_field(&mut errors, &cfg.0, "melee_self_pushback_ticks", |val| config.melee_self_pushback_ticks = val as u32);
-    update_field(&mut errors, &cfg.0, "melee_pushback", |val| config.melee_pushback = val);
-    update_field(&mut errors, &cfg.0, "melee_pushback_ticks", |val| config.melee_pushback_ticks = val as u32);
-    update_field(&mut errors, &cfg.0, "bow_attack_damage", |val| config.bow_attack_damage = val as u32);
-    update_field(&mut errors, &cfg.0, "bow_self_pushback", |val| config.bow_self_pushback = val);
-    update_field(&mut errors, &cfg.0, "bow_self_pushback_ticks", |val| config.bow_self_pushback_ticks = val as u32);
-    update_field(&mut errors, &cfg.0, "bow_pushback", |val| config.bow_pushback = val);
-    update_field(&mut errors, &cfg.0, "bow_pushback_ticks", |val| config.bow_pushback_ticks = val as u32);
-    update_field(&mut errors, &cfg.0, "passive_gain_rate", |val| config.passive_gain_rate = val as u32);
-    update_field(&mut errors, &cfg.0, "arrow_velocity", |val| config.arrow_velocity = val);
-
-    for error in errors{
-       warn!("failed to load player cfg value: {}", error);
-   }
+    /// How fast the player reaches their vertical swimming velocity.
+    ///
+    /// (in pixels/tick^2)
+    swim_accel: f32,
+}
+
+impl ValidateConfig for PlayerConfig {
+    fn validate(&self, v: &mut ConfigValidator) {
+        v.range("max_move_vel", self.max_move_vel, 0.0..);
+        v.range("max_fall_vel", self.max_fall_vel, 0.0..);
+        v.range("move_accel_init", self.move_accel_init, 0.0..);
+        v.range("move_accel", self.move_accel, 0.0..);
+        v.range("jump_vel_init", self.jump_vel_init, 0.0..);
+        v.range("jump_fall_accel", self.jump_fall_accel, 0.0..);
+        v.range("fall_accel", self.fall_accel, 0.0..);
+        v.range("max_coyote_time", self.max_coyote_time, 0.0..);
+        v.range("sliding_friction", self.sliding_friction, 0.0..=1.0);
+        v.range("hitstop_duration", self.hitstop_duration, 0.0..);
+        v.range("dash_duration", self.dash_duration, 0.0..);
+        v.range("dash_down_duration", self.dash_down_duration, 0.0..);
+        v.range("stealth_duration", self.stealth_duration, 0.0..);
+        v.range("stealth_cooldown", self.stealth_cooldown, 0.0..);
+        v.range("dash_velocity", self.dash_velocity, 0.0..);
+        v.range(
+            "dash_down_horizontal_velocity",
+            self.dash_down_horizontal_velocity,
+            0.0..,
+        );
+        v.range(
+            "dash_down_vertical_velocity",
+            self.dash_down_vertical_velocity,
+            0.0..,
+        );
+        v.range(
+            "dash_cooldown_duration",
+            self.dash_cooldown_duration,
+            0.0..,
+        );
+        v.range(
+            "dash_down_cooldown_duration",
+            self.dash_down_cooldown_duration,
+            0.0..,
+        );
+        v.range("max_whirl_energy", self.max_whirl_energy, 0.0..);
+        v.range("whirl_cost", self.whirl_cost, 0.0..);
+        v.range("whirl_regen", self.whirl_regen, 0.0..);
+        v.range("wall_pushback", self.wall_pushback, 0.0..);
+        v.range("melee_self_pushback", self.melee_self_pushback, 0.0..);
+        v.range("melee_pushback", self.melee_pushback, 0.0..);
+        v.range("bow_self_pushback", self.bow_self_pushback, 0.0..);
+        v.range("bow_pushback", self.bow_pushback, 0.0..);
+        v.range("max_health", self.max_health, 1..);
+        v.range("passive_gain_rate", self.passive_gain_rate, 1..);
+        v.range("arrow_velocity", self.arrow_velocity, 0.0..);
+        v.range(
+            "arrow_bounce_restitution",
+            self.arrow_bounce_restitution,
+            0.0..=1.0,
+        );
+        v.range("arrow_gravity_scale", self.arrow_gravity_scale, 0.0..);
+        v.range(
+            "arrow_homing_turn_rate",
+            self.arrow_homing_turn_rate,
+            0.0..,
+        );
+        v.range("swim_move_vel", self.swim_move_vel, 0.0..);
+        v.range("swim_vel", self.swim_vel, 0.0..);
+        v.range("swim_accel", self.swim_accel, 0.0..);
+    }
 }
 
 fn load_player_stats(
@@ -10,7 +10,8 @@ use crate::appstate::AppState;
 use crate::game::gentstate::Facing;
 use crate::game::player::{
     Attacking, CanAttack, Dashing, Falling, HitFreezeTime, Idle, Jumping,
-    PlayerConfig, PlayerGfx, PlayerStateSet, Running, WallSlideTime, Whirling,
+    PlayerConfig, PlayerGfx, PlayerStateSet, Running, Swimming, WallSlideTime,
+    Whirling,
 };
 use crate::prelude::{
     in_state, Added, App, Has, IntoSystemConfigs, Local, Or, Plugin, Query,
@@ -31,6 +32,7 @@ impl Plugin for PlayerAnimationPlugin {
                 player_falling_animation,
                 player_jumping_animation,
                 player_running_animation,
+                player_swimming_animation,
                 player_attacking_animation,
                 player_whirling_animation,
                 player_dashing_animation,
@@ -146,6 +148,30 @@ fn player_running_animation(
     }
 }
 
+fn player_swimming_animation(
+    query: Query<
+        (&Gent, &LinearVelocity),
+        Or<(
+            (With<Swimming>, Without<Attacking>),
+            (With<Swimming>, Added<CanAttack>),
+        )>,
+    >,
+    mut gfx_query: Query<&mut ScriptPlayer<SpriteAnimation>, With<PlayerGfx>>,
+) {
+    for (gent, velocity) in query.iter() {
+        if let Ok(mut player) = gfx_query.get_mut(gent.e_gfx) {
+            let key = if velocity.length() > 10.0 {
+                "anim.player.Swim"
+            } else {
+                "anim.player.SwimIdle"
+            };
+            if player.current_key().unwrap_or("") != key {
+                player.play_key(key);
+            }
+        }
+    }
+}
+
 fn player_attacking_animation(
     query: Query<
         (
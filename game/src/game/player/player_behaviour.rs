@@ -2,19 +2,23 @@ use bevy::sprite::{Sprite, SpriteSheetBundle};
 use bevy::transform::TransformSystem::TransformPropagate;
 use glam::{Vec2, Vec2Swizzles, Vec3Swizzles};
 use leafwing_input_manager::action_state::ActionState;
-use rapier2d::geometry::{Group, InteractionGroups};
 use rapier2d::na::ComplexField;
 use rapier2d::parry::query::TOIStatus;
 use theseeker_engine::assets::animation::SpriteAnimation;
 use theseeker_engine::gent::Gent;
+use theseeker_engine::physics::controller::{KinematicController, KinematicSet};
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
+};
 use theseeker_engine::physics::{
-    into_vec2, update_sprite_colliders, AnimationCollider, Collider,
-    LinearVelocity, PhysicsWorld, ShapeCaster, ENEMY, ENEMY_HURT, ENEMY_INSIDE,
-    GROUND, PLAYER, PLAYER_ATTACK,
+    into_vec2, project_on_slope, update_sprite_colliders, AnimationCollider,
+    Collider, LinearVelocity, PhysicsWorld,
 };
 use theseeker_engine::script::ScriptPlayer;
 
-use super::arc_attack::{Arrow, Projectile};
+use super::arc_attack::{
+    Arrow, Bounce, GravityScale, Homing, Pierce, Projectile,
+};
 use super::player_weapon::{is_player_using_bow, PlayerWeapon};
 use super::{
     dash_icon_fx, player_dash_fx, player_new_stats_mod, AttackBundle,
@@ -24,12 +28,16 @@ use super::{
 use crate::camera::CameraShake;
 use crate::game::attack::{Attack, SelfPushback, Stealthed};
 use crate::game::enemy::Enemy;
-use crate::game::gentstate::{Facing, TransitionQueue, Transitionable};
+use crate::game::gentstate::{
+    Dead, Facing, TransitionQueue, Transitionable,
+};
 use crate::game::player::{
-    Attacking, CanAttack, CanDash, CoyoteTime, Dashing, Falling, Grounded,
-    HitFreezeTime, Idle, Jumping, Player, PlayerAction, PlayerConfig,
-    PlayerGfx, PlayerStateSet, Running, WallSlideTime, WhirlAbility,
+    Attacking, CanAttack, CanDash, CoyoteTime, Dashing, DropThrough, Falling,
+    Grounded, HitFreezeTime, Idle, Jumping, Player, PlayerAction,
+    PlayerConfig, PlayerGfx, PlayerStateSet, Running, Swimming, WallSlideTime,
+    WhirlAbility,
 };
+use crate::game::water::{InWater, Water};
 use crate::prelude::{
     any_with_component, resource_changed, App, BuildChildren, Commands,
     DetectChanges, Direction2d, Entity, GameTickUpdate, GameTime, Has,
@@ -74,11 +82,16 @@ impl Plugin for PlayerBehaviorPlugin {
                         .run_if(any_with_component::<DashIcon>),
                     player_grounded.run_if(any_with_component::<Grounded>),
                     player_falling.run_if(any_with_component::<Falling>),
-                    crate::game::physics::knockback
-                        // player_pushback
-                        .run_if(any_with_component::<Knockback>)
-                        .before(player_jump)
-                        .after(player_sliding),
+                    player_drop_through
+                        .before(player_falling)
+                        .run_if(any_with_component::<DropThrough>),
+                    player_enter_water
+                        .after(player_grounded)
+                        .after(player_falling)
+                        .run_if(any_with_component::<InWater>),
+                    player_swimming
+                        .after(player_move)
+                        .run_if(any_with_component::<Swimming>),
                     player_sliding
                         .before(player_jump)
                         .run_if(any_with_component::<Falling>),
@@ -89,11 +102,12 @@ impl Plugin for PlayerBehaviorPlugin {
                     .in_set(PlayerStateSet::Behavior)
                     .before(update_sprite_colliders),
                 // consider a set for all movement/systems modify velocity, then collisions/move
-                // moves based on velocity
+                // the KinematicController moves based on velocity
                 (
                     // hitfreeze,
                     set_movement_slots,
-                    player_collisions,
+                    player_collisions.before(KinematicSet),
+                    player_contacts.after(KinematicSet),
                 )
                     .chain()
                     .before(TransformPropagate)
@@ -108,10 +122,11 @@ fn gain_passives(
     mut query: Query<&mut Passives, With<Player>>,
     kills: Res<KillCount>,
     player_config: Res<PlayerConfig>,
+    mut rng: ResMut<GameRng>,
 ) {
     for mut passives in query.iter_mut() {
         if **kills % player_config.passive_gain_rate == 0 {
-            passives.gain();
+            passives.gain(&mut **rng);
             println!("{:?}", passives);
         }
     }
@@ -493,6 +508,7 @@ pub fn player_dash_strike(
     >,
     mut commands: Commands,
     config: Res<PlayerConfig>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (
         entity,
@@ -508,6 +524,7 @@ pub fn player_dash_strike(
             add_dash_strike_collider(
                 &mut commands,
                 &config,
+                &collision_layers,
                 entity,
                 gent,
                 facing,
@@ -617,6 +634,7 @@ fn trigger_dash_strike(
 fn add_dash_strike_collider(
     mut commands: &mut Commands,
     config: &PlayerConfig,
+    collision_layers: &CollisionLayerRegistry,
     entity: Entity,
     gent: &Gent,
     facing: &Facing,
@@ -627,10 +645,9 @@ fn add_dash_strike_collider(
             TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 0.0)),
             AnimationCollider(gent.e_gfx),
             // TODO: ? ColliderMeta
-            Collider::empty(InteractionGroups::new(
-                PLAYER_ATTACK,
-                ENEMY_HURT,
-            )),
+            collision_layers
+                .empty_collider(&CollisionLayers::new("player_attack")),
+            CollisionLayers::new("player_attack"),
             Attack::new(16, entity),
             SelfPushback(Knockback::new(
                 Vec2::new(
@@ -655,230 +672,174 @@ fn add_dash_strike_collider(
     };
 }
 
+/// Walks along slopes and runs into enemies,
+/// before the [`KinematicController`] moves the player
+///
+/// Enemies are left out of what the controller collides with, because
+/// dashes and whirls go through them, and getting inside one makes the
+/// enemy let the player out instead.
 pub fn player_collisions(
     spatial_query: Res<PhysicsWorld>,
     mut q_gent: Query<
         (
             Entity,
-            &mut Transform,
+            &Transform,
             &mut LinearVelocity,
             &Collider,
-            Option<&mut WallSlideTime>,
-            Option<&mut Dashing>,
+            &KinematicController,
+            Has<Dashing>,
             Has<DashStrike>,
-            Option<&mut Whirling>,
+            Has<Whirling>,
+            Has<Grounded>,
+            Has<Knockback>,
         ),
         With<Player>,
     >,
-    mut q_enemy: Query<(Entity, &mut Collider), (With<Enemy>, Without<Player>)>,
+    mut q_enemy: Query<&mut Collider, (With<Enemy>, Without<Player>)>,
     mut commands: Commands,
     time: Res<GameTime>,
-    config: Res<PlayerConfig>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (
         entity,
-        mut pos,
+        transform,
         mut linear_velocity,
         collider,
-        slide,
-        mut dashing,
+        controller,
+        is_dashing,
         is_dash_strike,
-        whirling,
+        is_whirling,
+        is_grounded,
+        is_knocked,
     ) in q_gent.iter_mut()
     {
-        let mut shape = collider.0.shared_shape().clone();
-        let mut original_pos = pos.translation.xy();
-        let mut possible_pos = pos.translation.xy();
-        let z = pos.translation.z;
-        let mut projected_velocity = linear_velocity.xy();
-        let mut interaction = InteractionGroups {
-            memberships: PLAYER,
-            filter: Group::from_bits_truncate(0b10010),
-        };
-
-        let mut wall_slide = false;
-        let dir = linear_velocity.x.signum();
-        // We loop over the shape cast operation to check if the new trajectory might *also* collide.
-        // This can happen in a corner for example, where the first collision is on one wall, and
-        // so the velocity is only stopped in the x direction, but not the y, so without the extra
-        // check with the new velocity and position, the y might clip the player through the roof
-        // of the corner.
+        // walk along the slope we are standing on, instead of into it or off of it
+        if is_grounded && !is_dashing && !is_knocked {
+            linear_velocity.0 =
+                project_on_slope(linear_velocity.0, controller.ground_normal);
+        }
         // if we are not moving, we can not shapecast in direction of movement
-        while let Ok(shape_dir) = Direction2d::new(projected_velocity) {
-            if let Some((e, first_hit)) = spatial_query.shape_cast(
-                possible_pos,
-                shape_dir,
-                &*shape,
-                projected_velocity.length() / time.hz as f32 + 0.5,
-                interaction,
-                Some(entity),
-            ) {
-                // If we are colliding with an enemy
-                if let Ok((enemy, mut collider)) = q_enemy.get_mut(e) {
-                    // change collision groups to only include ground so on the next loop we can
-                    // ignore enemies/check our ground collision
-                    interaction = InteractionGroups {
-                        memberships: PLAYER,
-                        filter: GROUND,
-                    };
-                    match first_hit.status {
-                        // if we are not yet inside the enemy, collide, but not if we are falling
-                        // from above
-                        TOIStatus::Converged | TOIStatus::OutOfIterations => {
-                            // If we are dashing downwards, immediately cancel the dash, shake the camera, and start the attack animation, but don't do anything else.
-                            if let Some(mut dashing) = dashing.as_mut() {
-                                if dashing.is_down_dash() {
-                                    // currently, this feels out of place, as the strike stops just short of the ground.
-                                    // we currently do not have any flying enemies, so disabling dash strikes for enemies.
-
-                                    // trigger_dash_strike(
-                                    //     &mut commands,
-                                    //     dashing,
-                                    //     false,
-                                    // );
-                                }
-                            }
-
-                            // if we are also dashing, or whirling, ignore the collision
-                            if dashing.is_none()
-                                && whirling.is_none()
-                                && !is_dash_strike
-                            {
-                                let sliding_plane =
-                                    into_vec2(first_hit.normal1);
-                                // configurable threshold for collision normal/sliding plane in case of physics instability
-                                let threshold = 0.000001;
-                                if !(1. - threshold..=1. + threshold)
-                                    .contains(&sliding_plane.y)
-                                {
-                                    projected_velocity.x = linear_velocity.x
-                                        - sliding_plane.x
-                                            * linear_velocity
-                                                .xy()
-                                                .dot(sliding_plane);
-                                }
-                            }
-                        },
-                        // if we are already inside, modify the enemies collision group and add
-                        // Inside so next frame we dont collide with them
-                        TOIStatus::Penetrating => {
-                            collider.0.set_collision_groups(
-                                InteractionGroups {
-                                    memberships: ENEMY_INSIDE,
-                                    filter: Group::all(),
-                                },
-                            );
-                            commands
-                                .entity(enemy)
-                                .insert(crate::game::enemy::Inside);
-                        },
-                        // maybe failed never happens?
-                        TOIStatus::Failed => {
-                            println!("player/enemy collision failed")
-                        },
-                    }
-                // otherwise we are colliding with the ground
-                } else {
-                    match first_hit.status {
-                        TOIStatus::Converged | TOIStatus::OutOfIterations => {
-                            // Applies a very small amount of bounce, as well as sliding to the character
-                            // the bounce helps prevent the player from getting stuck.
-                            let sliding_plane = into_vec2(first_hit.normal1);
-
-                            // If we are dashing downwards, immediately cancel the dash, shake the camera, and start the attack animation
-                            if let Some(mut dashing) = dashing.as_mut() {
-                                if dashing.is_down_dash() {
-                                    trigger_dash_strike(
-                                        &mut commands,
-                                        dashing,
-                                        true,
-                                    );
-                                }
-                            }
-
-                            let bounce_coefficient =
-                                if dashing.is_some() || is_dash_strike {
-                                    0.0
-                                } else {
-                                    0.05
-                                };
-                            let bounce_force = -sliding_plane
-                                * linear_velocity.dot(sliding_plane)
-                                * bounce_coefficient;
-
-                            projected_velocity = linear_velocity.xy()
-                                - sliding_plane
-                                    * linear_velocity.xy().dot(sliding_plane);
-
-                            // Applies downward friction only when player tries to push
-                            // against the wall while falling. Ignores x component.
-                            let friction_coefficient = config.sliding_friction;
-                            let friction_force = if projected_velocity.y < -0.0
-                            {
-                                // make sure at least 1/2 of player is against the wall
-                                // (because it looks weird to have the character hanging by their head)
-                                if spatial_query
-                                    .ray_cast(
-                                        pos.translation.xy(),
-                                        Vec2::new(dir, 0.0),
-                                        shape
-                                            .as_cuboid()
-                                            .unwrap()
-                                            .half_extents
-                                            .x
-                                            + 0.1,
-                                        true,
-                                        InteractionGroups {
-                                            memberships: PLAYER,
-                                            filter: GROUND,
-                                        },
-                                        Some(entity),
-                                    )
-                                    .is_some()
-                                {
-                                    wall_slide = true;
-                                    -(projected_velocity.y
-                                        * friction_coefficient)
-                                } else {
-                                    0.0
-                                }
-                            } else {
-                                0.0
-                            };
-                            let friction_vec = Vec2::new(0.0, friction_force);
-
-                            projected_velocity += friction_vec + bounce_force;
-
-                            possible_pos = pos.translation.xy()
-                                + (shape_dir.xy() * (first_hit.toi - 0.01));
-                        },
-                        TOIStatus::Penetrating => {
-                            let depenetration = -linear_velocity.0;
-                            projected_velocity += depenetration;
-                            possible_pos = original_pos;
-                        },
-                        TOIStatus::Failed => {
-                            println!("player/ground collision failed")
-                        },
-                    }
+        let Ok(shape_dir) = Direction2d::new(linear_velocity.0) else {
+            continue;
+        };
+        let Some((enemy, first_hit)) = spatial_query.shape_cast(
+            transform.translation.xy(),
+            shape_dir,
+            collider.0.shape(),
+            linear_velocity.length() / time.hz as f32 + 0.5,
+            collision_layers.query_groups("player", &["enemy"]),
+            Some(entity),
+        ) else {
+            continue;
+        };
+        let Ok(mut enemy_collider) = q_enemy.get_mut(enemy) else {
+            continue;
+        };
+        match first_hit.status {
+            // if we are not yet inside the enemy, collide, but not if we are falling
+            // from above
+            TOIStatus::Converged | TOIStatus::OutOfIterations => {
+                // currently, a dash strike feels out of place on enemies, as the strike
+                // stops just short of the ground. we currently do not have any flying
+                // enemies, so dash strikes only trigger on the ground.
+
+                // if we are also dashing, or whirling, ignore the collision
+                if is_dashing || is_whirling || is_dash_strike {
+                    continue;
                 }
-                linear_velocity.0 = projected_velocity;
-                pos.translation = possible_pos.extend(z);
-            } else {
-                break;
-            }
+                let sliding_plane = into_vec2(first_hit.normal1);
+                // configurable threshold for collision normal/sliding plane in case of physics instability
+                let threshold = 0.000001;
+                if !(1. - threshold..=1. + threshold).contains(&sliding_plane.y)
+                {
+                    linear_velocity.x -= sliding_plane.x
+                        * linear_velocity.xy().dot(sliding_plane);
+                }
+            },
+            // if we are already inside, modify the enemies collision group and add
+            // Inside so next frame we dont collide with them
+            TOIStatus::Penetrating => {
+                enemy_collider.0.set_collision_groups(
+                    collision_layers
+                        .groups(&CollisionLayers::new("enemy_inside")),
+                );
+                commands.entity(enemy).insert(crate::game::enemy::Inside);
+            },
+            // maybe failed never happens?
+            TOIStatus::Failed => {
+                println!("player/enemy collision failed")
+            },
         }
+    }
+}
 
-        // if the final collision results in zero x velocity, cancel the active dash
-        if projected_velocity.x.abs() < 0.00001 {
-            if let Some(mut dashing) = dashing {
+/// Reacts to what the [`KinematicController`] ran into while moving the player
+fn player_contacts(
+    spatial_query: Res<PhysicsWorld>,
+    mut q_gent: Query<
+        (
+            Entity,
+            &Transform,
+            &mut LinearVelocity,
+            &Collider,
+            &KinematicController,
+            Option<&mut WallSlideTime>,
+            Option<&mut Dashing>,
+        ),
+        With<Player>,
+    >,
+    mut commands: Commands,
+    time: Res<GameTime>,
+    config: Res<PlayerConfig>,
+    collision_layers: Res<CollisionLayerRegistry>,
+) {
+    for (
+        entity,
+        transform,
+        mut linear_velocity,
+        collider,
+        controller,
+        slide,
+        dashing,
+    ) in q_gent.iter_mut()
+    {
+        if let Some(mut dashing) = dashing {
+            // If we dashed downwards into the ground, shake the camera, and start the
+            // attack animation
+            if dashing.is_down_dash() && controller.grounded {
+                trigger_dash_strike(&mut commands, &mut dashing, true);
+            }
+            // if the collisions stopped us, cancel the active dash
+            if linear_velocity.x.abs() < 0.00001 {
                 dashing.duration = f32::MAX;
             }
         }
 
-        pos.translation = (pos.translation.xy()
-            + linear_velocity.xy() * (1.0 / time.hz as f32))
-            .extend(z);
+        // Applies downward friction only when player pushes against the wall
+        // while falling.
+        let mut wall_slide = false;
+        if controller.on_wall() && linear_velocity.y < 0.0 {
+            let dir = if controller.wall_left { -1.0 } else { 1.0 };
+            let half_width =
+                collider.0.shape().as_cuboid().unwrap().half_extents.x;
+            // make sure at least 1/2 of player is against the wall
+            // (because it looks weird to have the character hanging by their head)
+            if spatial_query
+                .ray_cast(
+                    transform.translation.xy(),
+                    Vec2::new(dir, 0.0),
+                    half_width + controller.skin_width * 2.0,
+                    true,
+                    collision_layers.query_groups("player", &["ground"]),
+                    Some(entity),
+                )
+                .is_some()
+            {
+                wall_slide = true;
+                linear_velocity.y -=
+                    linear_velocity.y * config.sliding_friction;
+            }
+        }
 
         if let Some(mut slide) = slide {
             if wall_slide {
@@ -890,19 +851,13 @@ pub fn player_collisions(
     }
 }
 
-/// Tries to keep the characters shape caster this far above the ground
-///
-/// Needs to be non-zero to avoid getting stuck in the ground.
-const GROUNDED_THRESHOLD: f32 = 1.0;
-
 fn player_grounded(
     spatial_query: Res<PhysicsWorld>,
     mut query: Query<
         (
             Entity,
-            &ShapeCaster,
+            &KinematicController,
             &ActionState<PlayerAction>,
-            &mut Transform,
             &mut TransitionQueue,
             Option<&mut CoyoteTime>,
             &mut JumpCount,
@@ -915,32 +870,28 @@ fn player_grounded(
     >,
     time: Res<GameTime>,
     config: Res<PlayerConfig>,
+    mut commands: Commands,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     // in seconds
     let max_coyote_time = config.max_coyote_time;
+    let one_way = collision_layers.layer("one_way");
     for (
         entity,
-        ray_cast_info,
+        controller,
         action_state,
-        mut position,
         mut transitions,
         coyote_time,
         mut jump_count,
     ) in query.iter_mut()
     {
-        let mut time_of_impact = 0.0;
-        let is_falling = ray_cast_info
-            .cast(&spatial_query, &position, Some(entity))
-            .iter()
-            .any(|x| {
-                time_of_impact = x.1.toi;
-                x.1.toi > GROUNDED_THRESHOLD + 0.01
-            });
-        // Ensures player character lands at the expected x height every time.
-        if !is_falling && time_of_impact != 0.0 {
-            position.translation.y =
-                position.translation.y - time_of_impact + GROUNDED_THRESHOLD;
-        }
+        // the controller keeps us on the ground, and follows it down slopes
+        let is_falling = !controller.grounded;
+        let on_one_way = controller.ground.is_some_and(|e| {
+            spatial_query
+                .collision_groups(e)
+                .is_some_and(|groups| groups.memberships.contains(one_way))
+        });
         let mut in_c_time = false;
         if let Some(mut c_time) = coyote_time {
             if !is_falling {
@@ -960,6 +911,12 @@ fn player_grounded(
         if action_state.just_pressed(&PlayerAction::Jump) {
             jump_count.0 = 1;
             transitions.push(Grounded::new_transition(Jumping))
+        } else if action_state.pressed(&PlayerAction::Fall) && on_one_way {
+            jump_count.0 = 1;
+            transitions.push(Grounded::new_transition(Falling));
+            commands
+                .entity(entity)
+                .insert(DropThrough(config.drop_through_ticks));
         } else if is_falling && !in_c_time {
             jump_count.0 = 1;
             transitions.push(Grounded::new_transition(Falling))
@@ -967,15 +924,42 @@ fn player_grounded(
     }
 }
 
-fn player_falling(
-    spatial_query: Res<PhysicsWorld>,
+/// Stops the player from landing on one-way platforms,
+/// until the [`DropThrough`] runs out
+fn player_drop_through(
     mut query: Query<
         (
             Entity,
-            &mut Transform,
+            &mut DropThrough,
+            &mut KinematicController,
+        ),
+        With<Player>,
+    >,
+    mut commands: Commands,
+    collision_layers: Res<CollisionLayerRegistry>,
+) {
+    let one_way = collision_layers.layer("one_way");
+    for (entity, mut drop_through, mut controller) in query.iter_mut() {
+        if drop_through.0 == 0 {
+            controller.interaction.filter.insert(one_way);
+            commands.entity(entity).remove::<DropThrough>();
+        } else {
+            if controller.interaction.filter.contains(one_way) {
+                controller.interaction.filter.remove(one_way);
+                // the platform we were standing on doesn't hold us anymore
+                controller.grounded = false;
+            }
+            drop_through.0 -= 1;
+        }
+    }
+}
+
+fn player_falling(
+    mut query: Query<
+        (
             &mut LinearVelocity,
             &ActionState<PlayerAction>,
-            &ShapeCaster,
+            &KinematicController,
             &mut TransitionQueue,
             &mut JumpCount,
         ),
@@ -985,42 +969,28 @@ fn player_falling(
             Without<Dashing>,
         ),
     >,
-    time: Res<GameTime>,
     config: Res<PlayerConfig>,
 ) {
     for (
-        entity,
-        mut transform,
         mut velocity,
         action_state,
-        hits,
+        controller,
         mut transitions,
         mut jump_count,
     ) in query.iter_mut()
     {
         let fall_accel = config.fall_accel;
-        let mut falling = true;
-        if let Some((_, toi)) =
-            hits.cast(&spatial_query, &transform, Some(entity))
-        {
-            // if we are ~touching the ground
-            if (toi.toi + velocity.y * (1.0 / time.hz) as f32)
-                < GROUNDED_THRESHOLD
-            {
-                transitions.push(Falling::new_transition(Grounded));
-                // stop falling
-                velocity.y = 0.0;
-                transform.translation.y =
-                    transform.translation.y - toi.toi + GROUNDED_THRESHOLD;
-                if action_state.pressed(&PlayerAction::Move) {
-                    transitions.push(Falling::new_transition(Running));
-                } else {
-                    transitions.push(Falling::new_transition(Idle));
-                }
-                falling = false;
+        // the controller stopped us on the ground
+        if controller.grounded && velocity.y <= 0.0 {
+            transitions.push(Falling::new_transition(Grounded));
+            // stop falling
+            velocity.y = 0.0;
+            if action_state.pressed(&PlayerAction::Move) {
+                transitions.push(Falling::new_transition(Running));
+            } else {
+                transitions.push(Falling::new_transition(Idle));
             }
-        }
-        if falling {
+        } else {
             if action_state.just_pressed(&PlayerAction::Jump)
                 && jump_count.0 > 0
             {
@@ -1042,6 +1012,110 @@ fn player_falling(
     }
 }
 
+/// Starts swimming once the player sinks below the surface
+///
+/// Not while jumping, so the player can jump out of the water.
+fn player_enter_water(
+    mut query: Query<
+        (
+            &Transform,
+            &InWater,
+            &mut TransitionQueue,
+            Has<Grounded>,
+            Has<Falling>,
+        ),
+        (
+            With<Player>,
+            Without<Swimming>,
+            Without<Jumping>,
+            Without<Dashing>,
+        ),
+    >,
+) {
+    for (transform, in_water, mut transitions, is_grounded, is_falling) in
+        query.iter_mut()
+    {
+        // something else is already changing our state this tick
+        if !transitions.is_empty()
+            || transform.translation.y >= in_water.surface
+        {
+            continue;
+        }
+        if is_grounded {
+            transitions.push(Grounded::new_transition(Swimming));
+        } else if is_falling {
+            transitions.push(Falling::new_transition(Swimming));
+        }
+    }
+}
+
+fn player_swimming(
+    mut query: Query<
+        (
+            &Transform,
+            &mut LinearVelocity,
+            &ActionState<PlayerAction>,
+            Option<&InWater>,
+            &mut TransitionQueue,
+        ),
+        (With<Player>, With<Swimming>),
+    >,
+    q_water: Query<&Water>,
+    time: Res<GameTime>,
+    config: Res<PlayerConfig>,
+) {
+    // how far below the surface the player can still jump out
+    const SURFACE_JUMP_DEPTH: f32 = 6.0;
+
+    let dt = 1.0 / time.hz as f32;
+    for (transform, mut velocity, action_state, in_water, mut transitions) in
+        query.iter_mut()
+    {
+        let Some(in_water) = in_water else {
+            transitions.push(Swimming::new_transition(Falling));
+            continue;
+        };
+        let Ok(water) = q_water.get(in_water.volume) else {
+            continue;
+        };
+        let depth = in_water.surface - transform.translation.y;
+
+        if depth < SURFACE_JUMP_DEPTH
+            && action_state.just_pressed(&PlayerAction::Jump)
+        {
+            velocity.y = 0.0;
+            transitions.push(Swimming::new_transition(Jumping));
+            continue;
+        }
+
+        let stroke = if action_state.pressed(&PlayerAction::Jump) {
+            1.0
+        } else if action_state.pressed(&PlayerAction::Fall) {
+            -1.0
+        } else {
+            0.0
+        };
+        if stroke != 0.0 {
+            let target = stroke * config.swim_vel;
+            velocity.y += (target - velocity.y)
+                .clamp(-config.swim_accel, config.swim_accel);
+        } else {
+            // float back up to the surface
+            velocity.y += water.buoyancy * dt;
+        }
+        velocity.0 *= (1.0 - water.drag * dt).max(0.0);
+        velocity.x = velocity.x.clamp(
+            -config.swim_move_vel,
+            config.swim_move_vel,
+        );
+        velocity.y = velocity.y.max(-water.max_fall_speed);
+        // bob at the surface instead of popping out of the water
+        if depth <= 0.0 && velocity.y > 0.0 {
+            velocity.y = 0.0;
+        }
+    }
+}
+
 pub fn player_sliding(
     mut commands: Commands,
     mut query: Query<
@@ -1173,10 +1247,12 @@ fn player_attack(
         ),
         (With<Player>, Without<Whirling>),
     >,
+    q_enemy: Query<(Entity, &Transform), (With<Enemy>, Without<Dead>)>,
     mut commands: Commands,
     config: Res<PlayerConfig>,
     weapon: Res<PlayerWeapon>,
     time: Res<GameTime>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (
         entity,
@@ -1219,36 +1295,59 @@ fn player_attack(
                         ));
                     }
 
-                    commands
-                        .spawn((
-                            Arrow,
-                            SpriteSheetBundle {
-                                transform: *transform,
-                                ..Default::default()
-                            },
-                            Projectile { vel },
-                            Collider::cuboid(
-                                12.0,
-                                3.0,
-                                InteractionGroups::new(
-                                    PLAYER_ATTACK,
-                                    ENEMY_HURT | GROUND,
-                                ),
-                            ),
-                            Attack::new(192, entity)
-                                .with_damage(config.bow_attack_damage)
-                                .with_max_targets(1),
-                            Pushback(Knockback::new(
-                                Vec2::new(
-                                    facing.direction() * config.bow_pushback,
-                                    0.,
-                                ),
-                                config.bow_pushback_ticks,
+                    let mut arrow = commands.spawn((
+                        Arrow,
+                        SpriteSheetBundle {
+                            transform: *transform,
+                            ..Default::default()
+                        },
+                        Projectile { vel },
+                        Collider::cuboid(
+                            12.0,
+                            3.0,
+                            collision_layers.groups(&CollisionLayers::new(
+                                "player_attack",
                             )),
-                            animation,
-                            StateDespawnMarker,
-                        ))
-                        .id()
+                        ),
+                        CollisionLayers::new("player_attack"),
+                        Attack::new(192, entity)
+                            .with_damage(config.bow_attack_damage)
+                            .with_max_targets(1),
+                        Pushback(Knockback::new(
+                            Vec2::new(
+                                facing.direction() * config.bow_pushback,
+                                0.,
+                            ),
+                            config.bow_pushback_ticks,
+                        )),
+                        animation,
+                        StateDespawnMarker,
+                    ));
+                    if config.arrow_pierce > 0 {
+                        arrow.insert(Pierce(config.arrow_pierce));
+                    }
+                    if config.arrow_bounces > 0 {
+                        arrow.insert(Bounce {
+                            restitution: config.arrow_bounce_restitution,
+                            remaining: config.arrow_bounces,
+                        });
+                    }
+                    if config.arrow_gravity_scale > 0.0 {
+                        arrow.insert(GravityScale(config.arrow_gravity_scale));
+                    }
+                    let homing_target = nearest_enemy_ahead(
+                        transform.translation.xy(),
+                        arrow_direction,
+                        &q_enemy,
+                    )
+                    .filter(|_| config.arrow_homing_turn_rate > 0.0);
+                    if let Some(target) = homing_target {
+                        arrow.insert(Homing {
+                            target,
+                            turn_rate: config.arrow_homing_turn_rate,
+                        });
+                    }
+                    arrow.id()
                 },
                 PlayerWeapon::Sword => {
                     commands
@@ -1258,10 +1357,10 @@ fn player_attack(
                             ),
                             AnimationCollider(gent.e_gfx),
                             // TODO: ? ColliderMeta
-                            Collider::empty(InteractionGroups::new(
-                                PLAYER_ATTACK,
-                                ENEMY_HURT,
-                            )),
+                            collision_layers.empty_collider(
+                                &CollisionLayers::new("player_attack"),
+                            ),
+                            CollisionLayers::new("player_attack"),
                             Attack::new(16, entity),
                             SelfPushback(Knockback::new(
                                 Vec2::new(
@@ -1353,6 +1452,7 @@ pub fn player_whirl(
     mut commands: Commands,
     config: Res<PlayerConfig>,
     time: Res<GameTime>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (
         entity,
@@ -1381,11 +1481,11 @@ pub fn player_whirl(
                         AttackBundle {
                             // lifetime of two frames...
                             attack: Attack::new(24, entity),
-                            collider: Collider::empty(InteractionGroups::new(
-                                PLAYER_ATTACK,
-                                ENEMY_HURT,
-                            )),
+                            collider: collision_layers.empty_collider(
+                                &CollisionLayers::new("player_attack"),
+                            ),
                         },
+                        CollisionLayers::new("player_attack"),
                         TransformBundle::from_transform(Transform::from_xyz(
                             0.0, 0.0, 0.0,
                         )),
@@ -1414,10 +1514,29 @@ pub fn player_whirl(
     }
 }
 
+/// The closest enemy in a 90 degree cone from `pos`, towards `direction`
+fn nearest_enemy_ahead(
+    pos: Vec2,
+    direction: f32,
+    q_enemy: &Query<(Entity, &Transform), (With<Enemy>, Without<Dead>)>,
+) -> Option<Entity> {
+    q_enemy
+        .iter()
+        .map(|(entity, transform)| (entity, transform.translation.xy() - pos))
+        .filter(|(_, diff)| {
+            diff.x * direction > 0.0 && diff.y.abs() <= diff.x.abs()
+        })
+        .min_by(|(_, a), (_, b)| {
+            a.length_squared().total_cmp(&b.length_squared())
+        })
+        .map(|(entity, _)| entity)
+}
+
 pub fn bow_auto_aim(
     mut q_gent: Query<(&mut Facing, &Transform), (With<Player>, With<Gent>)>,
     q_enemy: Query<Entity, With<Enemy>>,
     spatial_query: Res<PhysicsWorld>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     for (mut facing, transform) in q_gent.iter_mut() {
         let is_facing_enemies = spatial_query
@@ -1426,10 +1545,7 @@ pub fn bow_auto_aim(
                 Vec2::X * facing.direction(),
                 f32::MAX,
                 true,
-                InteractionGroups {
-                    memberships: PLAYER,
-                    filter: ENEMY | GROUND,
-                },
+                collision_layers.query_groups("player", &["enemy", "ground"]),
                 None,
             )
             .is_some_and(|(entity, _)| q_enemy.contains(entity));
@@ -1440,10 +1556,7 @@ pub fn bow_auto_aim(
                 Vec2::NEG_X * facing.direction(),
                 f32::MAX,
                 true,
-                InteractionGroups {
-                    memberships: PLAYER,
-                    filter: ENEMY | GROUND,
-                },
+                collision_layers.query_groups("player", &["enemy", "ground"]),
                 None,
             )
             .is_some_and(|(entity, _)| q_enemy.contains(entity));
@@ -1453,3 +1566,202 @@ pub fn bow_auto_aim(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::prelude::{Bundle, World};
+
+    use super::*;
+    use crate::game::gentstate::transition;
+
+    /// 96 Hz, with the default water (see [`Water`])
+    fn world() -> (World, Entity) {
+        let mut world = World::new();
+        world.insert_resource(GameTime::new(96.0));
+        world.insert_resource(PlayerConfig {
+            swim_move_vel: 45.0,
+            swim_vel: 50.0,
+            swim_accel: 2.0,
+            ..Default::default()
+        });
+        let water = world.spawn(Water::default()).id();
+        (world, water)
+    }
+
+    /// A player at height `y`, in water with its surface at 0
+    fn spawn_player(
+        world: &mut World,
+        water: Entity,
+        y: f32,
+        state: impl Bundle,
+    ) -> Entity {
+        world
+            .spawn((
+                Player,
+                state,
+                Transform::from_xyz(0.0, y, 0.0),
+                InWater {
+                    volume: water,
+                    surface: 0.0,
+                },
+                LinearVelocity(Vec2::ZERO),
+                ActionState::<PlayerAction>::default(),
+                TransitionQueue::default(),
+            ))
+            .id()
+    }
+
+    fn step(world: &mut World) {
+        world.run_system_once(player_enter_water);
+        world.run_system_once(player_swimming);
+        world.run_system_once(transition);
+    }
+
+    fn velocity(world: &World, player: Entity) -> Vec2 {
+        world.get::<LinearVelocity>(player).unwrap().0
+    }
+
+    #[test]
+    fn start_swimming_below_surface() {
+        let (mut world, water) = world();
+        let grounded = spawn_player(&mut world, water, -4.0, Grounded);
+        let falling = spawn_player(&mut world, water, -4.0, Falling);
+        let wading = spawn_player(&mut world, water, 2.0, Grounded);
+        let jumping = spawn_player(&mut world, water, -4.0, Jumping);
+        step(&mut world);
+
+        for player in [grounded, falling] {
+            assert!(world.get::<Swimming>(player).is_some());
+            assert!(world.get::<Grounded>(player).is_none());
+            assert!(world.get::<Falling>(player).is_none());
+        }
+        assert!(world.get::<Swimming>(wading).is_none());
+        assert!(world.get::<Grounded>(wading).is_some());
+        assert!(world.get::<Swimming>(jumping).is_none());
+    }
+
+    #[test]
+    fn fall_when_out_of_water() {
+        let (mut world, water) = world();
+        let player = spawn_player(&mut world, water, -4.0, Swimming);
+        world.entity_mut(player).remove::<InWater>();
+        step(&mut world);
+        assert!(world.get::<Swimming>(player).is_none());
+        assert!(world.get::<Falling>(player).is_some());
+    }
+
+    #[test]
+    fn jump_out_near_surface() {
+        let (mut world, water) = world();
+        let near = spawn_player(&mut world, water, -4.0, Swimming);
+        let deep = spawn_player(&mut world, water, -20.0, Swimming);
+        for player in [near, deep] {
+            world
+                .get_mut::<ActionState<PlayerAction>>(player)
+                .unwrap()
+                .press(&PlayerAction::Jump);
+        }
+        step(&mut world);
+
+        assert!(world.get::<Swimming>(near).is_none());
+        assert!(world.get::<Jumping>(near).is_some());
+        assert_eq!(velocity(&world, near).y, 0.0);
+        // too deep to jump, swims up instead
+        assert!(world.get::<Swimming>(deep).is_some());
+        assert!(world.get::<Jumping>(deep).is_none());
+        assert!(velocity(&world, deep).y > 0.0);
+    }
+
+    #[test]
+    fn float_up_and_bob() {
+        let (mut world, water) = world();
+        let under = spawn_player(&mut world, water, -10.0, Swimming);
+        let surfaced = spawn_player(&mut world, water, 0.5, Swimming);
+        step(&mut world);
+
+        // buoyancy, then drag (both per second, at 96 Hz)
+        let expected = 200.0 / 96.0 * (1.0 - 3.0 / 96.0);
+        let y = velocity(&world, under).y;
+        assert!((y - expected).abs() < 0.001, "{y}");
+        assert_eq!(velocity(&world, surfaced).y, 0.0);
+    }
+
+    #[test]
+    fn dive() {
+        let (mut world, water) = world();
+        let player = spawn_player(&mut world, water, -10.0, Swimming);
+        world
+            .get_mut::<ActionState<PlayerAction>>(player)
+            .unwrap()
+            .press(&PlayerAction::Fall);
+        world.get_mut::<LinearVelocity>(player).unwrap().0 =
+            Vec2::new(200.0, 0.0);
+        step(&mut world);
+
+        // accelerates towards the swim velocity, and the speed is capped
+        let expected = -2.0 * (1.0 - 3.0 / 96.0);
+        let velocity = velocity(&world, player);
+        assert!((velocity.y - expected).abs() < 0.001, "{velocity}");
+        assert_eq!(velocity.x, 45.0);
+    }
+
+    /// A falling player, with what its controller found last tick
+    fn spawn_falling(world: &mut World, grounded: bool, vy: f32) -> Entity {
+        let mut controller = KinematicController::default();
+        controller.grounded = grounded;
+        world
+            .spawn((
+                Player,
+                Falling,
+                controller,
+                LinearVelocity(Vec2::new(0.0, vy)),
+                ActionState::<PlayerAction>::default(),
+                TransitionQueue::default(),
+                JumpCount(0),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn land_when_the_controller_is_grounded() {
+        let (mut world, _) = world();
+        let landed = spawn_falling(&mut world, true, 0.0);
+        let in_air = spawn_falling(&mut world, false, 0.0);
+        // still going up, through a one-way platform
+        let rising = spawn_falling(&mut world, true, 50.0);
+        world.run_system_once(player_falling);
+        world.run_system_once(transition);
+
+        assert!(world.get::<Grounded>(landed).is_some());
+        assert!(world.get::<Idle>(landed).is_some());
+        assert_eq!(velocity(&world, landed).y, 0.0);
+        for player in [in_air, rising] {
+            assert!(world.get::<Falling>(player).is_some());
+            assert!(world.get::<Grounded>(player).is_none());
+        }
+    }
+
+    #[test]
+    fn drop_through_one_way_platforms() {
+        let (mut world, _) = world();
+        let layers = CollisionLayerRegistry::default();
+        let one_way = layers.layer("one_way");
+        world.insert_resource(layers);
+        let mut controller = KinematicController::default();
+        controller.grounded = true;
+        let player = world.spawn((Player, controller, DropThrough(2))).id();
+        let controller = |world: &World| {
+            world.get::<KinematicController>(player).unwrap().clone()
+        };
+
+        world.run_system_once(player_drop_through);
+        assert!(!controller(&world).interaction.filter.contains(one_way));
+        assert!(!controller(&world).grounded);
+        world.run_system_once(player_drop_through);
+        assert!(world.get::<DropThrough>(player).is_some());
+        world.run_system_once(player_drop_through);
+        assert!(controller(&world).interaction.filter.contains(one_way));
+        assert!(world.get::<DropThrough>(player).is_none());
+    }
+}
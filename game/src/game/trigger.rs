@@ -0,0 +1,209 @@
+//! Trigger volumes placed in LDtk, that start scripts or set script slots
+//! when a gent walks in or out of them
+
+use theseeker_engine::assets::script::Script;
+use theseeker_engine::gent::Gent;
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
+};
+use theseeker_engine::physics::{
+    Collider, CollisionEnded, CollisionEvents, CollisionStarted, PhysicsSet,
+};
+use theseeker_engine::script::common::ScriptBundle;
+use theseeker_engine::script::label::EntityLabels;
+use theseeker_engine::script::ScriptPlayer;
+
+use crate::game::enemy::Enemy;
+use crate::game::player::Player;
+use crate::prelude::*;
+
+pub struct TriggerPlugin;
+
+impl Plugin for TriggerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            GameTickUpdate,
+            (
+                setup_trigger,
+                trigger_collisions.after(PhysicsSet),
+            )
+                .run_if(in_state(GameState::Playing))
+                .run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+#[derive(Bundle, LdtkEntity, Default)]
+pub struct TriggerBundle {
+    #[with(Trigger::from_entity_instance)]
+    trigger: Trigger,
+}
+
+/// A rectangle that reacts to gents entering and leaving it
+///
+/// It "enters" when the first matching gent walks in, and "exits"
+/// when the last one leaves.
+#[derive(Component, Default, Debug)]
+pub struct Trigger {
+    pub size: Vec2,
+    /// Asset key of a script to start when entered
+    pub on_enter_script: Option<String>,
+    /// Asset key of a script to start when exited
+    pub on_exit_script: Option<String>,
+    /// Slot to enable (while inside) on the scripts of the `target` entities
+    pub slot: Option<String>,
+    /// Label of the entities to set the `slot` on
+    pub target: Option<String>,
+    /// Only trigger the first time, then stop reacting
+    pub once: bool,
+    pub filter: TriggerFilter,
+    inside: HashSet<Entity>,
+    spent: bool,
+}
+
+/// Which gents can set off a [`Trigger`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerFilter {
+    #[default]
+    Player,
+    Enemy,
+    Any,
+}
+
+impl TriggerFilter {
+    fn matches(self, is_player: bool, is_enemy: bool) -> bool {
+        match self {
+            TriggerFilter::Player => is_player,
+            TriggerFilter::Enemy => is_enemy,
+            TriggerFilter::Any => is_player || is_enemy,
+        }
+    }
+}
+
+impl Trigger {
+    pub fn from_entity_instance(entity_instance: &EntityInstance) -> Self {
+        let string = |field: &str| {
+            entity_instance
+                .get_maybe_string_field(field)
+                .ok()
+                .cloned()
+                .flatten()
+                .filter(|s| !s.is_empty())
+        };
+        let filter = match entity_instance
+            .get_enum_field("filter")
+            .map(|s| s.to_lowercase())
+            .as_deref()
+        {
+            Ok("enemy") => TriggerFilter::Enemy,
+            Ok("any") => TriggerFilter::Any,
+            _ => TriggerFilter::Player,
+        };
+        Self {
+            size: Vec2::new(
+                entity_instance.width as f32,
+                entity_instance.height as f32,
+            ),
+            on_enter_script: string("on_enter_script"),
+            on_exit_script: string("on_exit_script"),
+            slot: string("slot"),
+            target: string("target"),
+            once: entity_instance
+                .get_bool_field("once")
+                .copied()
+                .unwrap_or(false),
+            filter,
+            ..Default::default()
+        }
+    }
+}
+
+fn setup_trigger(
+    query: Query<(Entity, &Trigger), Added<Trigger>>,
+    collision_layers: Res<CollisionLayerRegistry>,
+    mut commands: Commands,
+) {
+    for (entity, trigger) in query.iter() {
+        let layers = CollisionLayers::new("sensor");
+        commands.entity(entity).insert((
+            Name::new("Trigger"),
+            Collider::cuboid(
+                trigger.size.x,
+                trigger.size.y,
+                collision_layers.groups(&layers),
+            ),
+            layers,
+            CollisionEvents,
+        ));
+    }
+}
+
+fn trigger_collisions(
+    mut q_trigger: Query<&mut Trigger>,
+    q_gent: Query<(Has<Player>, Has<Enemy>), With<Gent>>,
+    mut q_script: Query<&mut ScriptPlayer<Script>>,
+    elabels: Res<EntityLabels>,
+    mut evr_started: EventReader<CollisionStarted>,
+    mut evr_ended: EventReader<CollisionEnded>,
+    mut commands: Commands,
+) {
+    for ev in evr_started.read() {
+        let Ok(mut trigger) = q_trigger.get_mut(ev.entity) else {
+            continue;
+        };
+        let Ok((is_player, is_enemy)) = q_gent.get(ev.other) else {
+            continue;
+        };
+        if trigger.spent || !trigger.filter.matches(is_player, is_enemy) {
+            continue;
+        }
+        trigger.inside.insert(ev.other);
+        if trigger.inside.len() == 1 {
+            if let Some(key) = &trigger.on_enter_script {
+                spawn_script(&mut commands, key);
+            }
+            set_target_slot(&trigger, &elabels, &mut q_script, true);
+        }
+    }
+    for ev in evr_ended.read() {
+        let Ok(mut trigger) = q_trigger.get_mut(ev.entity) else {
+            continue;
+        };
+        if !trigger.inside.remove(&ev.other) || !trigger.inside.is_empty() {
+            continue;
+        }
+        if let Some(key) = &trigger.on_exit_script {
+            spawn_script(&mut commands, key);
+        }
+        set_target_slot(&trigger, &elabels, &mut q_script, false);
+        if trigger.once {
+            trigger.spent = true;
+            commands.entity(ev.entity).remove::<CollisionEvents>();
+        }
+    }
+}
+
+fn spawn_script(commands: &mut Commands, key: &str) {
+    let mut player = ScriptPlayer::new();
+    player.play_key(key);
+    commands.spawn((
+        ScriptBundle { player },
+        StateDespawnMarker,
+    ));
+}
+
+fn set_target_slot(
+    trigger: &Trigger,
+    elabels: &EntityLabels,
+    q_script: &mut Query<&mut ScriptPlayer<Script>>,
+    state: bool,
+) {
+    let (Some(slot), Some(target)) = (&trigger.slot, &trigger.target) else {
+        return;
+    };
+    for e in elabels.iter_label_entities(target) {
+        if let Ok(mut player) = q_script.get_mut(*e) {
+            player.set_slot(slot, state);
+        }
+    }
+}
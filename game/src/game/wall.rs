@@ -1,6 +1,7 @@
-use rapier2d::geometry::InteractionGroups;
-use rapier2d::prelude::Group;
-use theseeker_engine::physics::{Collider, GROUND};
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
+};
+use theseeker_engine::physics::{Collider, StaticCollider};
 
 use crate::prelude::*;
 
@@ -8,7 +9,14 @@ pub struct WallPlugin;
 
 impl Plugin for WallPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (spawn_wall_collision,));
+        app.add_systems(
+            Update,
+            (
+                spawn_wall_collision::<Wall>,
+                spawn_wall_collision::<OneWay>,
+                spawn_slope_collision,
+            ),
+        );
     }
 }
 
@@ -22,6 +30,61 @@ pub struct WallBundle {
     wall: Wall,
 }
 
+/// Marker for one-way platform tiles, that can be jumped through from below
+#[derive(Component, Default)]
+pub struct OneWay;
+
+/// Bundle for one-way platform tiles
+#[derive(Bundle, Default, LdtkIntCell)]
+pub struct OneWayBundle {
+    one_way: OneWay,
+}
+
+/// Marker for slope tiles, with the direction the slope rises towards
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slope {
+    UpRight,
+    UpLeft,
+}
+
+impl Slope {
+    fn up_right(_: IntGridCell) -> Self {
+        Slope::UpRight
+    }
+
+    fn up_left(_: IntGridCell) -> Self {
+        Slope::UpLeft
+    }
+}
+
+/// Bundle for slope tiles rising to the right
+#[derive(Bundle, LdtkIntCell)]
+pub struct SlopeUpRightBundle {
+    #[with(Slope::up_right)]
+    slope: Slope,
+}
+
+/// Bundle for slope tiles rising to the left
+#[derive(Bundle, LdtkIntCell)]
+pub struct SlopeUpLeftBundle {
+    #[with(Slope::up_left)]
+    slope: Slope,
+}
+
+/// Tile markers that get colliders spawned by [`spawn_wall_collision`]
+pub trait CollisionTile: Component {
+    /// The collision layer of the spawned colliders
+    const LAYER: &'static str;
+}
+
+impl CollisionTile for Wall {
+    const LAYER: &'static str = "ground";
+}
+
+impl CollisionTile for OneWay {
+    const LAYER: &'static str = "one_way";
+}
+
 /// Spawns XPBD colliders for the walls of a level
 ///
 /// (adapted from the `bevy_ecs_ldtk` example)
@@ -40,13 +103,14 @@ pub struct WallBundle {
 /// 2. combine wall tiles into flat "plates" in each individual row
 /// 3. combine the plates into rectangles across multiple rows wherever possible
 /// 4. spawn colliders for each rectangle
-pub fn spawn_wall_collision(
+pub fn spawn_wall_collision<T: CollisionTile>(
     mut commands: Commands,
-    wall_query: Query<(&GridCoords, &LdtkParent), Added<Wall>>,
-    parent_query: Query<&Parent, Without<Wall>>,
+    wall_query: Query<(&GridCoords, &LdtkParent), Added<T>>,
+    parent_query: Query<&Parent, Without<T>>,
     level_query: Query<(Entity, &LevelIid)>,
     ldtk_projects: Query<&Handle<LdtkProject>>,
     ldtk_project_assets: Res<Assets<LdtkProject>>,
+    collision_layers: Res<CollisionLayerRegistry>,
 ) {
     /// Represents a wide wall that is 1 tile tall
     /// Used to spawn wall collisions
@@ -172,6 +236,7 @@ pub fn spawn_wall_collision(
                 // Making the collider a child of the level serves two purposes:
                 // 1. Adjusts the transforms to be relative to the level for free
                 // 2. the colliders will be despawned automatically when levels unload
+                let layers = CollisionLayers::new(T::LAYER);
                 for wall_rect in wall_rects {
                     level.spawn((
                         Collider::cuboid(
@@ -181,12 +246,9 @@ pub fn spawn_wall_collision(
                             (wall_rect.top as f32 - wall_rect.bottom as f32
                                 + 1.)
                                 * grid_size as f32,
-                            InteractionGroups {
-                                memberships: GROUND,
-                                // TODO: layers, player and enemy ... and ranged attacks?
-                                filter: Group::all(),
-                            },
+                            collision_layers.groups(&layers),
                         ),
+                        layers.clone(),
                         Transform::from_xyz(
                             (wall_rect.left + wall_rect.right + 1) as f32
                                 * grid_size as f32
@@ -197,6 +259,7 @@ pub fn spawn_wall_collision(
                             10.,
                         ),
                         GlobalTransform::default(),
+                        StaticCollider,
                         StateDespawnMarker,
                     ));
                 }
@@ -204,3 +267,47 @@ pub fn spawn_wall_collision(
         }
     });
 }
+
+/// Spawns a triangle collider on every slope tile
+///
+/// Unlike walls, these are not merged, as slopes are usually just a few tiles long.
+pub fn spawn_slope_collision(
+    mut commands: Commands,
+    slope_query: Query<(Entity, &Slope, &Parent), Added<Slope>>,
+    layer_query: Query<&LayerMetadata>,
+    collision_layers: Res<CollisionLayerRegistry>,
+) {
+    let layers = CollisionLayers::new("ground");
+    for (entity, slope, parent) in slope_query.iter() {
+        let Ok(layer) = layer_query.get(parent.get()) else {
+            continue;
+        };
+        // the tile's transform is at its center
+        let half = layer.grid_size as f32 / 2.;
+        let (low, high) = match slope {
+            Slope::UpRight => {
+                (
+                    Vec2::new(-half, -half),
+                    Vec2::new(half, half),
+                )
+            },
+            Slope::UpLeft => {
+                (
+                    Vec2::new(half, -half),
+                    Vec2::new(-half, half),
+                )
+            },
+        };
+        let Some(collider) = Collider::convex_polygon(
+            &[low, Vec2::new(high.x, -half), high],
+            collision_layers.groups(&layers),
+        ) else {
+            continue;
+        };
+        commands.entity(entity).insert((
+            collider,
+            layers.clone(),
+            StaticCollider,
+        ));
+    }
+}
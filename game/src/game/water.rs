@@ -0,0 +1,411 @@
+//! Water volumes placed in LDtk
+//!
+//! Gents inside water get an [`InWater`] component. Everything except the
+//! player just gets slowed down and pushed up by it; the player switches to
+//! the [`Swimming`](crate::game::player::Swimming) state instead.
+
+use theseeker_engine::gent::Gent;
+use theseeker_engine::physics::controller::KinematicSet;
+use theseeker_engine::physics::layers::{
+    CollisionLayerRegistry, CollisionLayers,
+};
+use theseeker_engine::physics::{
+    Collider, CollisionEnded, CollisionEvents, CollisionStarted,
+    LinearVelocity, PhysicsSet,
+};
+
+use crate::audio::Muffle;
+use crate::game::enemy::{move_collide, EnemyStateSet};
+use crate::game::player::Player;
+use crate::prelude::*;
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            GameTickUpdate,
+            (
+                setup_water,
+                water_collisions.after(PhysicsSet),
+                water_physics
+                    .after(EnemyStateSet::Behavior)
+                    .after(move_collide)
+                    .before(KinematicSet),
+            )
+                .run_if(in_state(GameState::Playing))
+                .run_if(in_state(AppState::InGame)),
+        );
+        app.init_resource::<Muffle>();
+        app.add_systems(Update, underwater_muffle);
+    }
+}
+
+#[derive(Bundle, LdtkEntity, Default)]
+pub struct WaterBundle {
+    #[with(Water::from_entity_instance)]
+    water: Water,
+}
+
+/// A rectangle of water
+#[derive(Component, Debug, Clone)]
+pub struct Water {
+    pub size: Vec2,
+    /// How much velocity is lost every second (fraction, per second)
+    pub drag: f32,
+    /// Upwards acceleration while inside (in pixels/second^2)
+    pub buoyancy: f32,
+    /// The maximum downward velocity inside (in pixels/second)
+    pub max_fall_speed: f32,
+}
+
+impl Default for Water {
+    fn default() -> Self {
+        Self {
+            size: Vec2::ZERO,
+            drag: 3.0,
+            buoyancy: 200.0,
+            max_fall_speed: 60.0,
+        }
+    }
+}
+
+impl Water {
+    pub fn from_entity_instance(entity_instance: &EntityInstance) -> Self {
+        let default = Self::default();
+        let float = |field: &str, default: f32| {
+            entity_instance
+                .get_float_field(field)
+                .copied()
+                .unwrap_or(default)
+        };
+        Self {
+            size: Vec2::new(
+                entity_instance.width as f32,
+                entity_instance.height as f32,
+            ),
+            drag: float("drag", default.drag),
+            buoyancy: float("buoyancy", default.buoyancy),
+            max_fall_speed: float("max_fall_speed", default.max_fall_speed),
+        }
+    }
+}
+
+/// Added to gents while they are inside a [`Water`] volume
+#[derive(Component, Debug, Clone, Copy)]
+pub struct InWater {
+    /// The [`Water`] entity
+    pub volume: Entity,
+    /// Height of the top of the water, in world space
+    pub surface: f32,
+}
+
+fn setup_water(
+    query: Query<(Entity, &Water), Added<Water>>,
+    collision_layers: Res<CollisionLayerRegistry>,
+    mut commands: Commands,
+) {
+    for (entity, water) in query.iter() {
+        let layers = CollisionLayers::new("sensor");
+        commands.entity(entity).insert((
+            Name::new("Water"),
+            Collider::cuboid(
+                water.size.x,
+                water.size.y,
+                collision_layers.groups(&layers),
+            ),
+            layers,
+            CollisionEvents,
+        ));
+    }
+}
+
+fn water_collisions(
+    q_water: Query<(&Water, &GlobalTransform)>,
+    q_gent: Query<Option<&InWater>, With<Gent>>,
+    mut evr_started: EventReader<CollisionStarted>,
+    mut evr_ended: EventReader<CollisionEnded>,
+    mut commands: Commands,
+) {
+    for ev in evr_started.read() {
+        let Ok((water, transform)) = q_water.get(ev.entity) else {
+            continue;
+        };
+        if q_gent.get(ev.other).is_err() {
+            continue;
+        }
+        commands.entity(ev.other).insert(InWater {
+            volume: ev.entity,
+            surface: transform.translation().y + water.size.y / 2.0,
+        });
+    }
+    for ev in evr_ended.read() {
+        // it might have moved into another volume already
+        let Ok(Some(in_water)) = q_gent.get(ev.other) else {
+            continue;
+        };
+        if in_water.volume == ev.entity {
+            commands.entity(ev.other).remove::<InWater>();
+        }
+    }
+}
+
+/// Drag, buoyancy and a lower fall speed for gents in water
+///
+/// Runs after enemies picked their velocity, right before they are moved.
+/// The player does its own thing while swimming.
+fn water_physics(
+    mut query: Query<(&InWater, &mut LinearVelocity), Without<Player>>,
+    q_water: Query<&Water>,
+    time: Res<GameTime>,
+) {
+    let dt = 1.0 / time.hz as f32;
+    for (in_water, mut velocity) in query.iter_mut() {
+        let Ok(water) = q_water.get(in_water.volume) else {
+            continue;
+        };
+        velocity.0 *= (1.0 - water.drag * dt).max(0.0);
+        velocity.y += water.buoyancy * dt;
+        velocity.y = velocity.y.max(-water.max_fall_speed);
+    }
+}
+
+/// Muffle the audio while the player is underwater
+fn underwater_muffle(
+    q_player: Query<(&Transform, Option<&InWater>), With<Player>>,
+    mut muffle: ResMut<Muffle>,
+) {
+    let underwater = q_player.iter().any(|(transform, in_water)| {
+        in_water.is_some_and(|w| transform.translation.y < w.surface)
+    });
+    if muffle.underwater != underwater {
+        muffle.underwater = underwater;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+    use rapier2d::prelude::InteractionGroups;
+    use theseeker_engine::physics::PhysicsWorld;
+
+    use super::*;
+    use crate::game::enemy::{Enemy, Navigation};
+
+    /// 96 Hz, so a velocity of 96 px/s moves 1 px per tick
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert_resource(GameTime::new(96.0));
+        world.init_resource::<Events<CollisionStarted>>();
+        world.init_resource::<Events<CollisionEnded>>();
+        world
+    }
+
+    /// 100x20 water, with its surface at `surface`
+    fn spawn_water(world: &mut World, surface: f32) -> Entity {
+        world
+            .spawn((
+                Water {
+                    size: Vec2::new(100.0, 20.0),
+                    ..default()
+                },
+                GlobalTransform::from_translation(Vec3::new(
+                    0.0,
+                    surface - 10.0,
+                    0.0,
+                )),
+            ))
+            .id()
+    }
+
+    fn spawn_gent(world: &mut World) -> Entity {
+        let gfx = world.spawn_empty().id();
+        world
+            .spawn(Gent {
+                e_gfx: gfx,
+                e_effects_gfx: gfx,
+            })
+            .id()
+    }
+
+    fn start(world: &mut World, water: Entity, gent: Entity) {
+        world.send_event(CollisionStarted {
+            entity: water,
+            other: gent,
+            groups: InteractionGroups::none(),
+            other_groups: InteractionGroups::none(),
+        });
+    }
+
+    fn end(world: &mut World, water: Entity, gent: Entity) {
+        world.send_event(CollisionEnded {
+            entity: water,
+            other: gent,
+            groups: InteractionGroups::none(),
+            other_groups: InteractionGroups::none(),
+        });
+    }
+
+    #[test]
+    fn enter_and_leave() {
+        let mut world = world();
+        let water = spawn_water(&mut world, 20.0);
+        let gent = spawn_gent(&mut world);
+
+        start(&mut world, water, gent);
+        world.run_system_once(water_collisions);
+        let in_water = world.get::<InWater>(gent).unwrap();
+        assert_eq!(in_water.volume, water);
+        assert_eq!(in_water.surface, 20.0);
+
+        end(&mut world, water, gent);
+        world.run_system_once(water_collisions);
+        assert!(world.get::<InWater>(gent).is_none());
+    }
+
+    #[test]
+    fn move_between_volumes() {
+        let mut world = world();
+        let first = spawn_water(&mut world, 20.0);
+        let second = spawn_water(&mut world, 50.0);
+        let gent = spawn_gent(&mut world);
+        start(&mut world, first, gent);
+        world.run_system_once(water_collisions);
+
+        // entering the next one before leaving the first
+        start(&mut world, second, gent);
+        end(&mut world, first, gent);
+        world.run_system_once(water_collisions);
+        let in_water = world.get::<InWater>(gent).unwrap();
+        assert_eq!(in_water.volume, second);
+        assert_eq!(in_water.surface, 50.0);
+    }
+
+    #[test]
+    fn ignore_non_gents() {
+        let mut world = world();
+        let water = spawn_water(&mut world, 20.0);
+        let other = world.spawn_empty().id();
+        start(&mut world, water, other);
+        world.run_system_once(water_collisions);
+        assert!(world.get::<InWater>(other).is_none());
+    }
+
+    #[test]
+    fn buoyancy_and_drag() {
+        let mut world = world();
+        let water = spawn_water(&mut world, 20.0);
+        let in_water = InWater {
+            volume: water,
+            surface: 20.0,
+        };
+        let floating = world.spawn((in_water, LinearVelocity(Vec2::ZERO))).id();
+        let sinking = world
+            .spawn((
+                in_water,
+                LinearVelocity(Vec2::new(96.0, -200.0)),
+            ))
+            .id();
+        let dry = world.spawn(LinearVelocity(Vec2::new(96.0, -200.0))).id();
+        world.run_system_once(water_physics);
+
+        // default water: 3/s drag, 200 px/s^2 buoyancy, 60 px/s max fall
+        let velocity = |e| world.get::<LinearVelocity>(e).unwrap().0;
+        let floating = velocity(floating);
+        assert_eq!(floating.x, 0.0);
+        assert!(
+            (floating.y - 200.0 / 96.0).abs() < 0.001,
+            "{floating}"
+        );
+        let sinking = velocity(sinking);
+        assert!(
+            (sinking.x - 93.0).abs() < 0.001,
+            "{sinking}"
+        );
+        assert_eq!(sinking.y, -60.0);
+        assert_eq!(velocity(dry), Vec2::new(96.0, -200.0));
+    }
+
+    #[test]
+    fn player_does_its_own_thing() {
+        let mut world = world();
+        let water = spawn_water(&mut world, 20.0);
+        let player = world
+            .spawn((
+                Player,
+                InWater {
+                    volume: water,
+                    surface: 20.0,
+                },
+                LinearVelocity(Vec2::new(96.0, -200.0)),
+            ))
+            .id();
+        world.run_system_once(water_physics);
+        let velocity = world.get::<LinearVelocity>(player).unwrap().0;
+        assert_eq!(velocity, Vec2::new(96.0, -200.0));
+    }
+
+    #[test]
+    fn enemy_floats_up() {
+        let mut world = world();
+        world.insert_resource(PhysicsWorld::default());
+        world.insert_resource(CollisionLayerRegistry::default());
+        let water = spawn_water(&mut world, 20.0);
+        let spawn_enemy = |world: &mut World| {
+            world
+                .spawn((
+                    Enemy,
+                    Navigation::Grounded,
+                    Transform::default(),
+                    LinearVelocity(Vec2::ZERO),
+                ))
+                .id()
+        };
+        let swimming = spawn_enemy(&mut world);
+        world.entity_mut(swimming).insert(InWater {
+            volume: water,
+            surface: 20.0,
+        });
+        let dry = spawn_enemy(&mut world);
+        for _ in 0..2 {
+            world.run_system_once(move_collide);
+            world.run_system_once(water_physics);
+        }
+
+        // walking doesn't cancel the buoyancy, so it adds up
+        let velocity = |e| world.get::<LinearVelocity>(e).unwrap().0;
+        let once = 200.0 / 96.0;
+        let expected = once * (1.0 - 3.0 / 96.0) + once;
+        let y = velocity(swimming).y;
+        assert!((y - expected).abs() < 0.001, "{y}");
+        assert_eq!(velocity(dry), Vec2::ZERO);
+    }
+
+    #[test]
+    fn muffle_below_the_surface() {
+        let mut world = world();
+        world.init_resource::<Muffle>();
+        let water = spawn_water(&mut world, 20.0);
+        let player = world
+            .spawn((
+                Player,
+                Transform::from_xyz(0.0, 25.0, 0.0),
+                InWater {
+                    volume: water,
+                    surface: 20.0,
+                },
+            ))
+            .id();
+        let underwater = |world: &mut World| {
+            world.run_system_once(underwater_muffle);
+            world.resource::<Muffle>().underwater
+        };
+
+        // in the water, but with the head above it
+        assert!(!underwater(&mut world));
+        world.get_mut::<Transform>(player).unwrap().translation.y = 15.0;
+        assert!(underwater(&mut world));
+        world.entity_mut(player).remove::<InWater>();
+        assert!(!underwater(&mut world));
+    }
+}
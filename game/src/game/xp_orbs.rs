@@ -30,6 +30,7 @@ fn spawn_orbs_on_death(
             Added<Dead>,
         ),
     >,
+    mut rng: ResMut<GameRng>,
     mut commands: Commands,
 ) {
 
@@ -39,7 +40,6 @@ fn spawn_orbs_on_death(
 
         let translation = tr.translation();
         
-        let mut rng = rand::thread_rng();
 
         let init_vel = Vec2::new(0.0, 2.0);
         const POS_RADIUS: f32 = 3.0;
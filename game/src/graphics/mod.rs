@@ -7,7 +7,8 @@ pub(crate) mod particles_util;
 pub mod player_hp;
 pub mod post_processing;
 
-use bevy_hanabi::HanabiPlugin;
+use bevy::render::RenderApp;
+use bevy_hanabi::{EffectAsset, HanabiPlugin};
 // use post_processing::DarknessPlugin;
 use post_processing::PostProcessingPlugin;
 
@@ -22,12 +23,18 @@ pub struct GraphicsFxPlugin;
 
 impl Plugin for GraphicsFxPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(PostProcessingPlugin);
         app.add_plugins(DmgNumbersPlugin);
         app.add_plugins(PlayerHpBarPlugin);
         app.add_plugins(EnemyHpBarPlugin);
         app.add_plugins(AbilityCooldownPlugin);
-        app.add_plugins(HanabiPlugin);
-        app.add_plugins(DepthOfFieldPlugin);
+        // render-only stuff, not needed when running headless
+        if app.get_sub_app(RenderApp).is_ok() {
+            app.add_plugins(PostProcessingPlugin);
+            app.add_plugins(HanabiPlugin);
+            app.add_plugins(DepthOfFieldPlugin);
+        } else {
+            // gameplay code still creates particle effects
+            app.init_asset::<EffectAsset>();
+        }
     }
 }
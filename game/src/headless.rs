@@ -0,0 +1,173 @@
+//! Headless simulation mode
+//!
+//! `theseeker_game --headless --ticks N [--level <asset key>]` runs the full
+//! gameplay, without a window or renderer, as fast as possible, for `N` game
+//! ticks. Then it prints a JSON summary to stdout and exits.
+//!
+//! Other things get printed to stdout too, so for scripts, use
+//! `--summary <file.json>` to write the summary to a file instead.
+//!
+//! Useful for balance simulations and smoke tests on machines without a GPU.
+//!
+//! With `--audio-out <file.wav>`, the audio is rendered on a simulated clock
+//! (instead of not at all) and written to the file on exit.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use bevy::time::TimeUpdateStrategy;
+
+use crate::game::attack::{Health, KillCount};
+use crate::game::gentstate::Dead;
+use crate::game::player::Player;
+use crate::prelude::*;
+
+/// The process exit code, once the simulation is done
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+/// Get the exit code for the process, after the app has finished running
+pub fn exit_code() -> i32 {
+    EXIT_CODE.load(Ordering::Relaxed)
+}
+
+/// Options given on the command line
+#[derive(Debug, Default)]
+pub struct LaunchArgs {
+    /// `--headless`
+    pub headless: bool,
+    /// `--ticks <N>`: how many ticks to simulate in headless mode
+    pub ticks: Option<u64>,
+    /// `--level <asset key>`: which level to start in
+    pub level: Option<String>,
+    /// `--audio-out <path>`: render the audio to a WAV file (headless only)
+    pub audio_out: Option<PathBuf>,
+    /// `--summary <path>`: write the JSON summary to a file, not stdout
+    pub summary: Option<PathBuf>,
+}
+
+impl LaunchArgs {
+    pub fn from_env() -> Self {
+        let mut args = LaunchArgs::default();
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--headless" => args.headless = true,
+                "--ticks" => {
+                    args.ticks = iter.next().and_then(|s| s.parse().ok());
+                    if args.ticks.is_none() {
+                        eprintln!("\"--ticks <N>\"");
+                    }
+                },
+                "--level" => args.level = iter.next(),
+                "--audio-out" => {
+                    args.audio_out = iter.next().map(PathBuf::from);
+                    if args.audio_out.is_none() {
+                        eprintln!("\"--audio-out <path>\"");
+                    }
+                },
+                "--summary" => {
+                    args.summary = iter.next().map(PathBuf::from);
+                    if args.summary.is_none() {
+                        eprintln!("\"--summary <path>\"");
+                    }
+                },
+                other => eprintln!("Unknown argument: {:?}", other),
+            }
+        }
+        args
+    }
+}
+
+pub struct HeadlessPlugin {
+    /// How many game ticks to simulate (after entering the game) before exiting
+    pub ticks: u64,
+    /// Where to write the summary, instead of stdout
+    pub summary: Option<PathBuf>,
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        // one tick per frame update, regardless of real time;
+        // round up, so that there are no frames with zero ticks
+        let hz = app.world.resource::<GameTime>().hz;
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(
+            Duration::from_nanos((1_000_000_000.0 / hz).ceil() as u64),
+        ));
+        app.insert_resource(HeadlessRun {
+            ticks: self.ticks,
+            start_tick: None,
+            summary: self.summary.clone(),
+        });
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            headless_start,
+        );
+        app.add_systems(
+            Update,
+            headless_finish
+                .run_if(in_state(AppState::InGame))
+                .in_set(GameTickSet::Post),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct HeadlessRun {
+    ticks: u64,
+    start_tick: Option<u64>,
+    summary: Option<PathBuf>,
+}
+
+fn headless_start(gt: Res<GameTime>, mut run: ResMut<HeadlessRun>) {
+    if run.start_tick.is_none() {
+        run.start_tick = Some(gt.tick());
+    }
+}
+
+fn headless_finish(
+    gt: Res<GameTime>,
+    run: Res<HeadlessRun>,
+    q_player: Query<(&Health, Has<Dead>), With<Player>>,
+    kills: Res<KillCount>,
+    mut evw_exit: EventWriter<bevy::app::AppExit>,
+) {
+    let Some(start_tick) = run.start_tick else {
+        return;
+    };
+    let ticks = gt.tick() - start_tick;
+    if ticks < run.ticks {
+        return;
+    }
+
+    let player = q_player.iter().next();
+    let (hp, max_hp, alive) = match player {
+        Some((health, is_dead)) => (health.current, health.max, !is_dead),
+        None => (0, 0, false),
+    };
+    let summary = format!(
+        "{{\"ticks\":{},\"player_found\":{},\"player_alive\":{},\"player_hp\":{},\"player_max_hp\":{},\"kills\":{}}}",
+        ticks,
+        player.is_some(),
+        alive,
+        hp,
+        max_hp,
+        **kills,
+    );
+    // no player means the level didn't load properly
+    let mut exit_code = if player.is_some() { 0 } else { 1 };
+    match &run.summary {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, summary + "\n") {
+                error!(
+                    "Cannot write summary to {:?}: {}",
+                    path, e
+                );
+                exit_code = 1;
+            }
+        },
+        None => println!("{}", summary),
+    }
+
+    EXIT_CODE.store(exit_code, Ordering::Relaxed);
+    evw_exit.send(bevy::app::AppExit);
+}
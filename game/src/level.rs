@@ -25,6 +25,7 @@ impl Plugin for LevelManagerPlugin {
         app.insert_resource(LevelSelection::Identifier(
             "Level_0".into(),
         ));
+        app.init_resource::<StartingLevel>();
         app.add_systems(
             OnEnter(AppState::InGame),
             game_level_init,
@@ -38,16 +39,32 @@ impl Plugin for LevelManagerPlugin {
     }
 }
 
+/// Asset key of the LDtk project to load when entering the gameplay state
+#[derive(Resource, Debug, Clone)]
+pub struct StartingLevel(pub String);
+
+impl Default for StartingLevel {
+    fn default() -> Self {
+        StartingLevel("level.01".into())
+    }
+}
+
 /// System to perform initial setup when entering the gameplay state, load the starting level.
-fn game_level_init(mut commands: Commands, preloaded: Res<PreloadedAssets>) {
+fn game_level_init(
+    mut commands: Commands,
+    preloaded: Res<PreloadedAssets>,
+    starting: Res<StartingLevel>,
+) {
     // TODO: per-level asset management instead of preloaded assets
     // TODO: when we have save files, use that to choose the level to init at
 
     commands.spawn((
         LdtkWorldBundle {
             ldtk_handle: preloaded
-                .get_single_asset("level.01")
-                .expect("Expected asset key 'level.01'"),
+                .get_single_asset(&starting.0)
+                .unwrap_or_else(|| {
+                    panic!("Expected asset key '{}'", starting.0)
+                }),
             ..Default::default()
         },
         StateDespawnMarker,
@@ -25,8 +25,11 @@ mod camera;
 mod cli;
 mod game;
 mod gamestate;
+mod headless;
 mod level;
 mod locale;
+mod perf;
+mod replay;
 mod stepping_egui;
 
 mod screens {
@@ -41,6 +44,8 @@ pub mod graphics;
 mod parallax;
 
 fn main() {
+    let args = crate::headless::LaunchArgs::from_env();
+
     let mut app = App::new();
     app.insert_resource(ClearColor(Color::BLACK));
 
@@ -62,10 +67,29 @@ fn main() {
     });
     let bevy_plugins = bevy_plugins.set(ImagePlugin::default_nearest());
 
-    let bevy_plugins = bevy_plugins.set(RenderPlugin {
-        render_creation: wgpu_settings.into(),
-        synchronous_pipeline_compilation: false,
-    });
+    let bevy_plugins = if args.headless {
+        // no window, no GPU; everything else stays, so assets can still load
+        bevy_plugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: bevy::window::ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .disable::<bevy::winit::WinitPlugin>()
+            .set(RenderPlugin {
+                render_creation: WgpuSettings {
+                    backends: None,
+                    ..Default::default()
+                }
+                .into(),
+                synchronous_pipeline_compilation: false,
+            })
+    } else {
+        bevy_plugins.set(RenderPlugin {
+            render_creation: wgpu_settings.into(),
+            synchronous_pipeline_compilation: false,
+        })
+    };
 
     let cpus = num_cpus::get_physical();
     let cpus_io = (cpus * 3 / 4).max(2);
@@ -113,6 +137,27 @@ fn main() {
 
     // and custom "engine"
     app.add_plugins(theseeker_engine::EnginePlugins);
+
+    if let Some(level) = args.level {
+        app.insert_resource(crate::level::StartingLevel(level));
+    }
+    if args.headless {
+        app.add_plugins((
+            bevy::app::ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+            crate::headless::HeadlessPlugin {
+                ticks: args.ticks.unwrap_or(96 * 60),
+                summary: args.summary.clone(),
+            },
+        ));
+        if let Some(path) = &args.audio_out {
+            app.add_plugins(
+                theseeker_engine::audio::render::OfflineAudioPlugin {
+                    output: Some(path.clone()),
+                    ..Default::default()
+                },
+            );
+        }
+    }
     // app.add_plugin(Sprite3dPlugin);
 
     // external plugins
@@ -122,7 +167,11 @@ fn main() {
         iyes_bevy_extras::d2::WorldCursorPlugin,
         ProgressPlugin::new(AppState::AssetsLoading)
             .track_assets()
-            .continue_to(AppState::MainMenu),
+            .continue_to(if args.headless {
+                AppState::InGame
+            } else {
+                AppState::MainMenu
+            }),
         PhysicsPlugin,
         PerfUiPlugin,
     ));
@@ -133,8 +182,9 @@ fn main() {
             state: AppState::AssetsLoading,
         },
         crate::assets::AssetsPlugin,
-        crate::audio::AudioPlugin,
         crate::locale::LocalePlugin,
+        crate::perf::PerfPlugin,
+        crate::replay::ReplayPlugin,
         crate::cli::CliPlugin,
         crate::ui::UiPlugin,
         crate::camera::CameraPlugin,
@@ -145,12 +195,24 @@ fn main() {
         crate::graphics::GraphicsFxPlugin,
     ));
 
+    // keeps audio in sync with the game; there is no audio device when headless,
+    // unless the audio is being rendered offline
+    if !args.headless || args.audio_out.is_some() {
+        app.add_plugins(crate::audio::AudioPlugin);
+    }
+
     #[cfg(feature = "dev")]
-    app.add_plugins(crate::dev::DevPlugin);
+    if !args.headless {
+        app.add_plugins(crate::dev::DevPlugin);
+    }
 
     app.edit_schedule(Update, |s| {
         s.set_executor_kind(ExecutorKind::SingleThreaded);
     });
 
     app.run();
+
+    if args.headless {
+        std::process::exit(crate::headless::exit_code());
+    }
 }
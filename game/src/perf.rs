@@ -0,0 +1,187 @@
+//! Extra entries for the performance overlay
+
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::ecs::system::SystemParam;
+use iyes_perf_ui::{PerfUiAppExt, PerfUiEntry};
+
+use crate::audio::AudioSync;
+use crate::prelude::*;
+
+pub struct PerfPlugin;
+
+impl Plugin for PerfPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_perf_ui_entry_type::<PerfUiEntryDroppedTicks>();
+        app.add_perf_ui_entry_type::<PerfUiEntryAudioLag>();
+        app.add_perf_ui_entry_type::<PerfUiEntryAudioTarget>();
+        app.add_perf_ui_entry_type::<PerfUiEntryAudioResets>();
+    }
+}
+
+/// Perf UI entry: total number of game ticks dropped by the catch-up limit
+///
+/// See `GameTime::max_ticks_per_frame`.
+#[derive(Component, Debug, Clone)]
+pub struct PerfUiEntryDroppedTicks {
+    pub label: String,
+    pub sort_key: i32,
+}
+
+impl Default for PerfUiEntryDroppedTicks {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            sort_key: iyes_perf_ui::utils::next_sort_key(),
+        }
+    }
+}
+
+impl PerfUiEntry for PerfUiEntryDroppedTicks {
+    type SystemParam = SRes<GameTime>;
+    type Value = u64;
+
+    fn label(&self) -> &str {
+        if self.label.is_empty() {
+            "Dropped Ticks"
+        } else {
+            &self.label
+        }
+    }
+
+    fn sort_key(&self) -> i32 {
+        self.sort_key
+    }
+
+    fn update_value(
+        &self,
+        gametime: &mut <Self::SystemParam as SystemParam>::Item<'_, '_>,
+    ) -> Option<Self::Value> {
+        Some(gametime.dropped_ticks())
+    }
+}
+
+/// Perf UI entry: how many game ticks the audio is behind the game
+///
+/// See [`AudioSync`].
+#[derive(Component, Debug, Clone)]
+pub struct PerfUiEntryAudioLag {
+    pub label: String,
+    pub sort_key: i32,
+}
+
+impl Default for PerfUiEntryAudioLag {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            sort_key: iyes_perf_ui::utils::next_sort_key(),
+        }
+    }
+}
+
+impl PerfUiEntry for PerfUiEntryAudioLag {
+    type SystemParam = Option<SRes<AudioSync>>;
+    type Value = i64;
+
+    fn label(&self) -> &str {
+        if self.label.is_empty() {
+            "Audio Lag (ticks)"
+        } else {
+            &self.label
+        }
+    }
+
+    fn sort_key(&self) -> i32 {
+        self.sort_key
+    }
+
+    fn update_value(
+        &self,
+        sync: &mut <Self::SystemParam as SystemParam>::Item<'_, '_>,
+    ) -> Option<Self::Value> {
+        sync.as_ref().map(|sync| sync.lag())
+    }
+}
+
+/// Perf UI entry: how many ticks behind the game the audio is meant to be
+///
+/// See [`AudioSync`].
+#[derive(Component, Debug, Clone)]
+pub struct PerfUiEntryAudioTarget {
+    pub label: String,
+    pub sort_key: i32,
+}
+
+impl Default for PerfUiEntryAudioTarget {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            sort_key: iyes_perf_ui::utils::next_sort_key(),
+        }
+    }
+}
+
+impl PerfUiEntry for PerfUiEntryAudioTarget {
+    type SystemParam = Option<SRes<AudioSync>>;
+    type Value = u64;
+
+    fn label(&self) -> &str {
+        if self.label.is_empty() {
+            "Audio Target Delay"
+        } else {
+            &self.label
+        }
+    }
+
+    fn sort_key(&self) -> i32 {
+        self.sort_key
+    }
+
+    fn update_value(
+        &self,
+        sync: &mut <Self::SystemParam as SystemParam>::Item<'_, '_>,
+    ) -> Option<Self::Value> {
+        sync.as_ref().map(|sync| sync.target)
+    }
+}
+
+/// Perf UI entry: number of times the audio clock had to be reset
+///
+/// See [`AudioSync`].
+#[derive(Component, Debug, Clone)]
+pub struct PerfUiEntryAudioResets {
+    pub label: String,
+    pub sort_key: i32,
+}
+
+impl Default for PerfUiEntryAudioResets {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            sort_key: iyes_perf_ui::utils::next_sort_key(),
+        }
+    }
+}
+
+impl PerfUiEntry for PerfUiEntryAudioResets {
+    type SystemParam = Option<SRes<AudioSync>>;
+    type Value = u32;
+
+    fn label(&self) -> &str {
+        if self.label.is_empty() {
+            "Audio Resets"
+        } else {
+            &self.label
+        }
+    }
+
+    fn sort_key(&self) -> i32 {
+        self.sort_key
+    }
+
+    fn update_value(
+        &self,
+        sync: &mut <Self::SystemParam as SystemParam>::Item<'_, '_>,
+    ) -> Option<Self::Value> {
+        sync.as_ref().map(|sync| sync.resets)
+    }
+}
@@ -0,0 +1,161 @@
+//! Recording play sessions and replaying them
+//!
+//! Both recording and replay (re)start the game from the beginning, so that
+//! the recording covers everything from the moment the level is loaded.
+//!
+//! CliCommands:
+//!  - `record <file>`: restart and record until `record_stop` (or leaving the game)
+//!  - `record_stop`: stop recording and save the file
+//!  - `replay <file>`: restart and play back a recording
+
+use std::path::PathBuf;
+
+use theseeker_engine::input::replay::{
+    InputRecorder, InputRecording, InputReplay,
+};
+
+use crate::game::player::PlayerAction;
+use crate::level::StartingLevel;
+use crate::prelude::*;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_clicommand_args("record", cli_record);
+        app.register_clicommand_noargs("record_stop", cli_record_stop);
+        app.register_clicommand_args("replay", cli_replay);
+        app.add_systems(
+            OnEnter(AppState::InGame),
+            start_input_session,
+        );
+        app.add_systems(
+            OnExit(AppState::InGame),
+            (save_recording, stop_replay),
+        );
+    }
+}
+
+/// What to do the next time we enter `AppState::InGame`
+#[derive(Resource)]
+enum PendingInputSession {
+    Record(PathBuf),
+    Replay(InputRecording<PlayerAction>),
+}
+
+/// Where to save the current recording
+#[derive(Resource)]
+struct RecordingPath(PathBuf);
+
+fn cli_record(
+    In(args): In<Vec<String>>,
+    mut commands: Commands,
+    mut next: ResMut<NextState<AppState>>,
+) {
+    if args.len() != 1 {
+        error!("\"record <file>\"");
+        return;
+    }
+    commands.insert_resource(PendingInputSession::Record(
+        args[0].as_str().into(),
+    ));
+    next.set(AppState::Restart);
+}
+
+fn cli_record_stop(world: &mut World) {
+    if !world.contains_resource::<InputRecorder<PlayerAction>>() {
+        error!("Not recording!");
+        return;
+    }
+    save_recording(world);
+}
+
+fn cli_replay(
+    In(args): In<Vec<String>>,
+    mut commands: Commands,
+    mut next: ResMut<NextState<AppState>>,
+    preloaded: Res<PreloadedAssets>,
+) {
+    if args.len() != 1 {
+        error!("\"replay <file>\"");
+        return;
+    }
+    let recording = match InputRecording::load(&args[0]) {
+        Ok(recording) => recording,
+        Err(e) => {
+            error!("{:#}", e);
+            return;
+        },
+    };
+    if !recording.level.is_empty() {
+        // don't restart into a level that can't be loaded
+        if preloaded
+            .get_single_asset::<LdtkProject>(&recording.level)
+            .is_none()
+        {
+            error!(
+                "Recording starts in unknown level {:?}",
+                recording.level
+            );
+            return;
+        }
+        commands.insert_resource(StartingLevel(recording.level.clone()));
+    }
+    commands.insert_resource(PendingInputSession::Replay(recording));
+    next.set(AppState::Restart);
+}
+
+fn start_input_session(world: &mut World) {
+    let Some(pending) = world.remove_resource::<PendingInputSession>() else {
+        return;
+    };
+    let tick = world.resource::<GameTime>().tick();
+    match pending {
+        PendingInputSession::Record(path) => {
+            let seed = thread_rng().gen();
+            world.resource_mut::<GameRng>().reseed(seed);
+            let level = world.resource::<StartingLevel>().0.clone();
+            let hz = world.resource::<GameTime>().hz;
+            let recording = InputRecording::new(seed, level, hz);
+            world.insert_resource(InputRecorder::new(recording, tick));
+            world.insert_resource(RecordingPath(path));
+            info!("Recording input.");
+        },
+        PendingInputSession::Replay(recording) => {
+            if recording.hz != world.resource::<GameTime>().hz {
+                warn!(
+                    "Recording was made at {} Hz, replay will not match!",
+                    recording.hz
+                );
+            }
+            world.resource_mut::<GameRng>().reseed(recording.seed);
+            world.insert_resource(InputReplay::new(recording, tick));
+            info!("Replaying input.");
+        },
+    }
+}
+
+fn save_recording(world: &mut World) {
+    let Some(recorder) = world.remove_resource::<InputRecorder<PlayerAction>>()
+    else {
+        return;
+    };
+    let Some(RecordingPath(path)) = world.remove_resource::<RecordingPath>()
+    else {
+        return;
+    };
+    let recording = recorder.finish();
+    match recording.save(&path) {
+        Ok(()) => {
+            info!(
+                "Saved input recording ({} ticks) to {:?}.",
+                recording.len_ticks, path
+            );
+        },
+        Err(e) => error!("{:#}", e),
+    }
+}
+
+fn stop_replay(mut commands: Commands) {
+    commands.remove_resource::<InputReplay<PlayerAction>>();
+}